@@ -0,0 +1,159 @@
+
+; This code runs Training Level 1 of the game, hakka.
+
+; 16-bit Ship X position
+X_0 = $00
+X_1 = $01
+
+; 16-bit Ship Y position
+Y_0 = $02
+Y_1 = $03
+
+; The keycode of the last key pressed
+KEY = $04
+
+; 16-bit movement speed
+MOV_0 = $05
+MOV_1 = $06
+
+; Whether the flames are showing
+FLAME = $07
+
+; The sprite table: 6 bytes per sprite, the 16-bit X and Y position, the
+; image and the flags. Flag bit 0 shows the sprite
+SPRITES = $0200
+
+GameLoop
+
+JSR UpArrow
+JSR DownArrow
+JSR Flame
+JSR Sprites
+
+JMP GameLoop
+
+DownArrow:
+
+LDA KEY
+CMP #40 ; Was the down arrow pressed?
+BNE DownArrowEnd
+
+; Add the movement speed to the Y position
+CLC
+SEI
+LDA Y_0
+ADC MOV_0
+STA Y_0
+LDA Y_1
+ADC MOV_1
+STA Y_1
+CLI
+
+DownArrowEnd:
+RTS
+
+UpArrow:
+
+LDA KEY
+CMP #38 ; Was the up arrow pressed?
+BNE UpArrowEnd
+
+SEC
+SEI
+LDA Y_0
+SBC MOV_0
+STA Y_0
+LDA Y_1
+SBC MOV_1
+STA Y_1
+
+; clamp the Y position so it can't be bigger than <figure it out>
+
+UpArrowEnd:
+CLI
+RTS
+
+; Toggle the flame based on key state
+Flame:
+LDA KEY
+CMP #$00
+BEQ FlameOff
+LDA #$01
+STA FLAME
+JMP FlameEnd
+FlameOff:
+LDA #$00
+STA FLAME
+
+FlameEnd:
+RTS
+
+; Place the flames under the ship and the ship over them
+Sprites:
+
+; The left flame, at 10 pixels left of the ship and 150 below it
+LDX #$00
+SEC
+LDA X_0
+SBC #10
+STA SPRITES,X
+INX
+LDA X_1
+SBC #0
+STA SPRITES,X
+JSR FlameSprite
+
+; The right flame, at 77 pixels right of the ship
+LDX #$06
+CLC
+LDA X_0
+ADC #77
+STA SPRITES,X
+INX
+LDA X_1
+ADC #0
+STA SPRITES,X
+JSR FlameSprite
+
+; The ship itself, image 0 and always visible
+LDX #$0C
+LDA X_0
+STA SPRITES,X
+INX
+LDA X_1
+STA SPRITES,X
+INX
+LDA Y_0
+STA SPRITES,X
+INX
+LDA Y_1
+STA SPRITES,X
+INX
+LDA #$00
+STA SPRITES,X
+INX
+LDA #$01
+STA SPRITES,X
+RTS
+
+; Write the Y position, image 1 and the visibility of the flame whose
+; X position was just written
+FlameSprite:
+INX
+CLC
+LDA Y_0
+ADC #150
+STA SPRITES,X
+INX
+LDA Y_1
+ADC #0
+STA SPRITES,X
+INX
+LDA #$01
+STA SPRITES,X
+INX
+LDA FLAME
+STA SPRITES,X
+RTS
+
+END:
\ No newline at end of file
@@ -0,0 +1,465 @@
+use std::io::Write;
+use std::path::Path;
+
+use sdl2::event::Event;
+use sdl2::image::LoadTexture;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{Renderer, Texture, TextureQuery};
+use sdl2::ttf::Sdl2TtfContext;
+
+use vm::{assemble, evaluate, Bank, BankContents, Banner, Game, GameCore, InputMapping, Interconnect, Level, Mapper,
+         MapperLayout, MemoryWrite, Position, Progress, Protection, Recording, SoundDevice, SourceWatcher, Statistics,
+         SymbolTable, Text, VirtualMachine, SAMPLE_RATE};
+
+use UPDATES_PER_SECOND;
+use speaker::Speaker;
+use sprite::{Sprite, SpriteLayer};
+use tile_map::TileMap;
+
+const BANNER_HEIGHT: u32 = 120;
+const BANNER_TEXT_OFFSET: i32 = 25;
+const BANNER_FONT_SIZE: u16 = 56;
+const OUTCOME_Y: i32 = 300;
+const OUTCOME_TEXT_OFFSET: i32 = 30;
+const OUTCOME_FONT_SIZE: u16 = 64;
+const SUMMARY_Y: i32 = OUTCOME_Y + BANNER_HEIGHT as i32 + 30;
+const SUMMARY_LINE_HEIGHT: i32 = 40;
+const SUMMARY_FONT_SIZE: u16 = 28;
+
+/// Where to record a session to or replay it from, and capture its sound
+/// to, if anywhere
+#[derive(Clone, Default)]
+pub struct SessionOptions {
+    pub record: Option<String>,
+    pub replay: Option<String>,
+    /// A WAV file. Levels without a sound device aren't captured
+    pub audio_capture: Option<String>,
+}
+
+enum Outcome {
+    Playing,
+    Passed,
+    Failed,
+}
+
+/// Plays a level described by a manifest
+pub struct LevelScreen<'a> {
+    ttf_context: &'a Sdl2TtfContext,
+    font_file: &'a str,
+    game_core: GameCore<'a>,
+    level: Level,
+    inputs: Vec<(Keycode, InputMapping)>,
+    sprites: Vec<Sprite>,
+    tile_map: Option<TileMap>,
+    sprite_layer: Option<SpriteLayer>,
+    banners: Vec<(Banner, Text)>,
+    passed_text: Text,
+    failed_text: Text,
+    outcome: Outcome,
+    /// How the level was solved, shown once it is passed
+    summary: Vec<String>,
+    summary_texts: Option<Vec<Text>>,
+    quit: bool,
+}
+
+impl<'a> LevelScreen<'a> {
+    pub fn new(level: Level,
+               ttf_context: &'a Sdl2TtfContext,
+               mut renderer: &mut Renderer,
+               assets: &Path,
+               font_file: &'a str,
+               options: &SessionOptions,
+               speaker: Option<&Speaker>)
+               -> Result<LevelScreen<'a>, String> {
+        let center = (renderer.window().unwrap().size().0 / 2) as i32;
+        let banner_text = |renderer: &mut Renderer, text: &str, y: i32, size: u16| {
+            Text::new(ttf_context,
+                      renderer,
+                      text,
+                      Position::HorizontalCenter(center, y),
+                      size,
+                      Color::RGBA(0, 0, 0, 255),
+                      font_file)
+        };
+
+        let banners = level.banners
+            .iter()
+            .map(|banner| {
+                let text = banner_text(&mut renderer, &banner.text, banner.y + BANNER_TEXT_OFFSET, BANNER_FONT_SIZE);
+                (banner.clone(), text)
+            })
+            .collect();
+        let passed_text = banner_text(&mut renderer, "PASSED", OUTCOME_Y + OUTCOME_TEXT_OFFSET, OUTCOME_FONT_SIZE);
+        let failed_text = banner_text(&mut renderer, "FAILED", OUTCOME_Y + OUTCOME_TEXT_OFFSET, OUTCOME_FONT_SIZE);
+
+        // The size of the screen and of every texture, so expressions can
+        // place things without knowing them
+        let (screen_width, screen_height) = renderer.window().unwrap().size();
+        let mut constants = vec![("SCREEN_WIDTH".to_owned(), screen_width), ("SCREEN_HEIGHT".to_owned(), screen_height)];
+
+        let mut sprites = Vec::new();
+        for binding in &level.sprites {
+            let texture = renderer.load_texture(&assets.join(&binding.texture))?;
+            constants.extend(texture_constants(&binding.texture, &texture));
+            sprites.push(Sprite::new(texture, binding.clone()));
+        }
+
+        let tile_map = match level.display {
+            Some(ref display) => {
+                let tiles = match display.tiles {
+                    Some(ref tiles) => Some(renderer.load_texture(&assets.join(tiles))?),
+                    None => None,
+                };
+                Some(TileMap::new(display.clone(), tiles))
+            }
+            None => None,
+        };
+
+        let sprite_layer = match level.sprite_table {
+            Some(ref table) => {
+                let mut images = Vec::new();
+                for image in &table.images {
+                    let texture = renderer.load_texture(&assets.join(image))?;
+                    constants.extend(texture_constants(image, &texture));
+                    images.push(texture);
+                }
+                Some(SpriteLayer::new(table.clone(), images))
+            }
+            None => None,
+        };
+
+        let mut inputs = Vec::new();
+        for input in &level.inputs {
+            let keycode = Keycode::from_name(&input.key).ok_or(format!("Unknown key '{}'", input.key))?;
+            inputs.push((keycode, input.clone()));
+        }
+
+        let mut game_core = GameCore::new(ttf_context, &mut renderer, font_file)?;
+        for (name, value) in constants {
+            game_core.vm.define_constant(name, value as u16);
+        }
+
+        let source = level.source_path();
+        load_cpu(&mut game_core.vm, &source, level.load_address, &level.memory)?;
+        for layout in &level.mappers {
+            game_core.vm.add_mapper(load_mapper(&level, layout)?);
+        }
+        game_core.vm.set_protection(Protection::new(level.protected.clone()));
+        level.validate(&game_core.vm)?;
+        game_core.vm.load_hints(level.hints.clone());
+        let mut watcher = SourceWatcher::new();
+        watcher.watch_cpu(0, source, level.load_address, level.memory.clone(), &mut game_core.vm);
+        for (mapper, layout) in level.mappers.iter().enumerate() {
+            for (bank, contents) in layout.banks.iter().enumerate() {
+                if let BankContents::Rom(ref source) = *contents {
+                    watcher.watch_bank(mapper, bank, level.bank_source_path(source));
+                }
+            }
+        }
+        for coprocessor in &level.cpus {
+            let index = game_core.vm.add_cpu();
+            let source = level.coprocessor_source_path(coprocessor);
+            game_core.vm
+                .with_cpu(index, |vm| load_cpu(vm, &source, coprocessor.load_address, &coprocessor.memory))?;
+            watcher.watch_cpu(index,
+                              source,
+                              coprocessor.load_address,
+                              coprocessor.memory.clone(),
+                              &mut game_core.vm);
+        }
+        game_core.vm.interconnect = Interconnect::new(level.shared.clone(), level.mailboxes.clone());
+        game_core.vm.watcher = Some(watcher);
+        if let Some(address) = level.sound {
+            let sample_rate = speaker.map(|speaker| speaker.sample_rate()).unwrap_or(SAMPLE_RATE);
+            let mut sound = SoundDevice::new(address, sample_rate, UPDATES_PER_SECOND);
+            if let Some(speaker) = speaker {
+                sound.play_through(speaker.samples());
+            }
+            if let Some(ref file) = options.audio_capture {
+                sound.start_capture(file).map_err(|err| format!("Unable to capture audio to {}: {}", file, err))?;
+            }
+            game_core.vm.sound = Some(sound);
+        }
+        game_core.run_startup_script();
+
+        if let Some(ref record) = options.record {
+            game_core.start_recording(record).map_err(|err| err.to_string())?;
+        }
+        if let Some(ref replay) = options.replay {
+            game_core.start_replay(Recording::load(replay).map_err(|err| err.to_string())?);
+        }
+
+        Ok(LevelScreen {
+            ttf_context: ttf_context,
+            font_file: font_file,
+            game_core: game_core,
+            level: level,
+            inputs: inputs,
+            sprites: sprites,
+            tile_map: tile_map,
+            sprite_layer: sprite_layer,
+            banners: banners,
+            passed_text: passed_text,
+            failed_text: failed_text,
+            outcome: Outcome::Playing,
+            summary: Vec::new(),
+            summary_texts: None,
+            quit: false,
+        })
+    }
+
+    /// Whether the player closed the window, rather than leaving the level
+    pub fn quit(&self) -> bool {
+        self.quit
+    }
+
+    /// Evaluates and writes an input to the memory of the first CPU
+    fn write_input(&mut self, address: &str, value: &str) {
+        let (address, value) = self.game_core.vm.with_cpu(0, |vm| (evaluate(address, vm), evaluate(value, vm)));
+        if let (Ok(address), Ok(value)) = (address, value) {
+            if address >= 0 && address <= 0xFFFF {
+                self.game_core.write_input(address as usize, value as u8);
+            }
+        }
+    }
+
+    fn pass(&mut self) {
+        self.outcome = Outcome::Passed;
+
+        let statistics = self.game_core.statistics();
+        let mut progress = Progress::load();
+        let par = progress.record_pass(&self.level.id(), statistics.clone());
+        if let Err(err) = progress.save() {
+            writeln!(self.game_core.vm.console, "Unable to save progress: {:?}", err).unwrap();
+        }
+
+        self.summary = summarize(&statistics, par.as_ref());
+        for line in &self.summary {
+            writeln!(self.game_core.vm.console, "{}", line).unwrap();
+        }
+    }
+
+    fn render_summary(&mut self, renderer: &mut Renderer) {
+        if self.summary_texts.is_none() {
+            let center = (renderer.window().unwrap().size().0 / 2) as i32;
+            let texts = self.summary
+                .iter()
+                .enumerate()
+                .map(|(index, line)| {
+                    Text::new(self.ttf_context,
+                              renderer,
+                              &line[..],
+                              Position::HorizontalCenter(center, SUMMARY_Y + index as i32 * SUMMARY_LINE_HEIGHT),
+                              SUMMARY_FONT_SIZE,
+                              Color::RGBA(255, 255, 255, 255),
+                              self.font_file)
+                })
+                .collect();
+            self.summary_texts = Some(texts);
+        }
+
+        if let Some(ref texts) = self.summary_texts {
+            for text in texts {
+                text.render(renderer);
+            }
+        }
+    }
+}
+
+impl<'a> Game for LevelScreen<'a> {
+    fn process_event(&mut self, event: &Event) -> bool {
+        self.game_core.process_event(event);
+
+        if !self.game_core.vm.console.visible {
+            match *event {
+                Event::Quit { .. } => {
+                    self.quit = true;
+                    return false;
+                }
+                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => return false,
+                Event::KeyDown { keycode: Some(keycode), .. } => {
+                    let inputs = self.inputs.clone();
+                    for &(_, ref input) in inputs.iter().filter(|&&(key, _)| key == keycode) {
+                        self.write_input(&input.address, &input.pressed);
+                    }
+                }
+                Event::KeyUp { keycode: Some(keycode), .. } => {
+                    let inputs = self.inputs.clone();
+                    for &(_, ref input) in inputs.iter().filter(|&&(key, _)| key == keycode) {
+                        self.write_input(&input.address, &input.released);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        true
+    }
+
+    fn update(&mut self) {
+        if let Outcome::Playing = self.outcome {
+            // Rules are level logic rather than player input, so they are
+            // neither recorded nor replayed: a replay runs the rules of the
+            // level as it is now
+            let level = &self.level;
+            self.game_core.vm.with_cpu(0, |vm| {
+                let writes = level.rules
+                    .iter()
+                    .filter(|rule| holds(&rule.when, vm))
+                    .flat_map(|rule| rule.writes.iter())
+                    .filter_map(|write| write.evaluate(vm).ok())
+                    .collect::<Vec<_>>();
+                for (address, bytes) in writes {
+                    vm.cpu.memory_mut()[address..address + bytes.len()].copy_from_slice(&bytes);
+                }
+            });
+
+            // Only judge the level while interrupts are enabled, when the
+            // level code isn't halfway through an update
+            let (won, lost) = self.game_core.vm.with_cpu(0, |vm| {
+                if vm.cpu.interrupts_disabled() {
+                    (false, false)
+                } else {
+                    (holds_option(&level.win, vm), holds_option(&level.lose, vm))
+                }
+            });
+            if won {
+                self.pass();
+            } else if lost {
+                self.outcome = Outcome::Failed;
+            }
+        }
+
+        self.game_core.update();
+    }
+
+    fn render(&mut self, mut renderer: &mut Renderer) {
+        // Rendering only the background when interrupts are disabled results in a horrible
+        // flickering; therefore only render when we're either in single stepping mode or
+        // interrupts are enabled
+        let interrupt_disabled = self.game_core.vm.with_cpu(0, |vm| vm.cpu.interrupts_disabled());
+        if !self.game_core.vm.is_debugging() && interrupt_disabled {
+            return;
+        }
+
+        renderer.set_draw_color(Color::RGBA(0, 0, 0, 255));
+        renderer.clear();
+
+        // Render complete game screen only if interrupts are enabled
+        if !interrupt_disabled {
+            if let Some(ref mut tile_map) = self.tile_map {
+                self.game_core.vm.with_cpu(0, |vm| tile_map.render(renderer, vm));
+            }
+            match self.outcome {
+                Outcome::Passed => {
+                    draw_banner(&mut renderer, Color::RGB(0, 255, 0), OUTCOME_Y);
+                    self.passed_text.render(&mut renderer);
+                    self.render_summary(&mut renderer);
+                }
+                Outcome::Failed => {
+                    draw_banner(&mut renderer, Color::RGB(255, 64, 64), OUTCOME_Y);
+                    self.failed_text.render(&mut renderer);
+                }
+                Outcome::Playing => (),
+            }
+            for &(ref banner, ref text) in &self.banners {
+                let (r, g, b) = banner.color;
+                draw_banner(&mut renderer, Color::RGB(r, g, b), banner.y);
+                text.render(&mut renderer);
+            }
+            let sprites = &self.sprites;
+            let sprite_layer = &self.sprite_layer;
+            self.game_core.vm.with_cpu(0, |vm| {
+                for sprite in sprites {
+                    sprite.render(renderer, vm);
+                }
+                if let Some(ref sprite_layer) = *sprite_layer {
+                    sprite_layer.render(renderer, vm);
+                }
+            });
+        }
+        self.game_core.vm.render(&mut renderer);
+        renderer.present();
+    }
+}
+
+/// The lines shown under the PASSED banner, comparing the run to the par
+/// set by the best run before it
+fn summarize(statistics: &Statistics, par: Option<&Statistics>) -> Vec<String> {
+    let score = statistics.score();
+    let verdict = match par {
+        Some(par) if score < par.score() => format!("Score {}, new best! (par {})", score, par.score()),
+        Some(par) => format!("Score {} (par {})", score, par.score()),
+        None => format!("Score {}", score),
+    };
+    vec![statistics.to_string(), verdict]
+}
+
+/// Assembles the banks of a mapper
+fn load_mapper(level: &Level, layout: &MapperLayout) -> Result<Mapper, String> {
+    let mut banks = Vec::new();
+    for contents in &layout.banks {
+        let bank = match *contents {
+            BankContents::Rom(ref source) => {
+                let source = level.bank_source_path(source);
+                let segments = assemble(&source, layout.window).map_err(|err| err.to_string())?;
+                let symbols = SymbolTable::from_file(&source, layout.window).map_err(|err| err.to_string())?;
+                Bank::rom(segments, symbols, layout.window, layout.size)?
+            }
+            BankContents::Ram => Bank::ram(layout.size),
+        };
+        banks.push(bank);
+    }
+    Ok(Mapper::new(layout.window, layout.size, layout.register, banks))
+}
+
+/// Loads assembly source into the selected CPU, writes its initial memory
+/// and resets it
+fn load_cpu(vm: &mut VirtualMachine,
+            source: &Path,
+            load_address: u16,
+            memory: &[MemoryWrite])
+            -> Result<(), String> {
+    vm.cpu.set_flag('I', false);
+    vm.load_code_segments(assemble(source, load_address).map_err(|err| err.to_string())?);
+    vm.load_symbols(SymbolTable::from_file(source, load_address).map_err(|err| err.to_string())?);
+    for write in memory {
+        let (address, bytes) = write.evaluate(vm)
+            .map_err(|err| format!("Invalid memory write to '{}': {}", write.address, err))?;
+        for (index, byte) in bytes.into_iter().enumerate() {
+            vm.cpu.memory_mut()[address + index] = byte;
+        }
+    }
+    vm.cpu.reset();
+    Ok(())
+}
+
+/// Constants for the size of a texture, named after its file. The size of
+/// `ship-flame.png` is `SHIP_FLAME_WIDTH` by `SHIP_FLAME_HEIGHT`
+fn texture_constants(file: &str, texture: &Texture) -> Vec<(String, u32)> {
+    let stem = Path::new(file).file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let name = stem.chars()
+        .map(|c| if c.is_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect::<String>();
+    let TextureQuery { width, height, .. } = texture.query();
+    vec![(format!("{}_WIDTH", name), width), (format!("{}_HEIGHT", name), height)]
+}
+
+fn holds(condition: &str, vm: &VirtualMachine) -> bool {
+    evaluate(condition, vm).map(|value| value != 0).unwrap_or(false)
+}
+
+fn holds_option(condition: &Option<String>, vm: &VirtualMachine) -> bool {
+    match *condition {
+        Some(ref condition) => holds(condition, vm),
+        None => false,
+    }
+}
+
+fn draw_banner(renderer: &mut Renderer, color: Color, y: i32) {
+    let width = renderer.window().unwrap().size().0;
+    renderer.set_draw_color(color);
+    renderer.fill_rect(Rect::new(0, y, width, BANNER_HEIGHT)).unwrap();
+}
@@ -0,0 +1,165 @@
+use std::fs;
+use std::path::Path;
+
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Renderer;
+use sdl2::ttf::Sdl2TtfContext;
+
+use vm::{Game, Level, LevelProgress, Position, Progress, Text};
+
+const MANIFEST_FILE: &'static str = "level.json";
+
+const TITLE_Y: i32 = 80;
+const TITLE_FONT_SIZE: u16 = 56;
+const FIRST_ENTRY_Y: i32 = 220;
+const ENTRY_HEIGHT: i32 = 60;
+const ENTRY_FONT_SIZE: u16 = 36;
+const HIGHLIGHT_PADDING: i32 = 8;
+
+/// Finds every level in a folder of levels, one level per sub folder.
+/// Folders whose manifest doesn't load are reported and skipped
+pub fn discover<P>(folder: P) -> Vec<Level>
+    where P: AsRef<Path>
+{
+    let mut manifests = match fs::read_dir(folder) {
+        Ok(entries) => {
+            entries.filter_map(|entry| entry.ok())
+                .map(|entry| entry.path().join(MANIFEST_FILE))
+                .filter(|manifest| manifest.is_file())
+                .collect::<Vec<_>>()
+        }
+        Err(_) => Vec::new(),
+    };
+    manifests.sort();
+
+    manifests.into_iter()
+        .filter_map(|manifest| {
+            Level::load(&manifest)
+                .map_err(|err| eprintln!("Skipping level {}: {}", manifest.display(), err))
+                .ok()
+        })
+        .collect()
+}
+
+/// Lists the levels to pick from, marking the ones already passed
+pub struct LevelSelect {
+    levels: Vec<Level>,
+    title: Text,
+    entries: Vec<Text>,
+    selected: usize,
+    chosen: Option<usize>,
+}
+
+impl LevelSelect {
+    pub fn new(levels: Vec<Level>,
+               ttf_context: &Sdl2TtfContext,
+               renderer: &mut Renderer,
+               font_file: &str)
+               -> LevelSelect {
+        let progress = Progress::load();
+        let center = (renderer.window().unwrap().size().0 / 2) as i32;
+
+        let title = Text::new(ttf_context,
+                              renderer,
+                              "SELECT A LEVEL",
+                              Position::HorizontalCenter(center, TITLE_Y),
+                              TITLE_FONT_SIZE,
+                              Color::RGBA(0, 144, 192, 255),
+                              font_file);
+        let entries = levels.iter()
+            .enumerate()
+            .map(|(index, level)| {
+                let text = match progress.level(&level.id()) {
+                    Some(&LevelProgress { passed: true, best: Some(ref best) }) => {
+                        format!("{}  PASSED  par {}", level.name, best.score())
+                    }
+                    Some(&LevelProgress { passed: true, .. }) => format!("{}  PASSED", level.name),
+                    _ => level.name.clone(),
+                };
+                Text::new(ttf_context,
+                          renderer,
+                          text,
+                          Position::HorizontalCenter(center, entry_y(index)),
+                          ENTRY_FONT_SIZE,
+                          Color::RGBA(255, 255, 255, 255),
+                          font_file)
+            })
+            .collect();
+
+        LevelSelect {
+            levels: levels,
+            title: title,
+            entries: entries,
+            selected: 0,
+            chosen: None,
+        }
+    }
+
+    /// The level picked by the player, or `None` if they quit instead
+    pub fn into_chosen(self) -> Option<Level> {
+        match self.chosen {
+            Some(index) => self.levels.into_iter().nth(index),
+            None => None,
+        }
+    }
+}
+
+impl Game for LevelSelect {
+    fn process_event(&mut self, event: &Event) -> bool {
+        match *event {
+            Event::Quit { .. } |
+            Event::KeyDown { keycode: Some(Keycode::Escape), .. } => return false,
+            Event::KeyDown { keycode: Some(Keycode::Up), .. } => {
+                if self.selected > 0 {
+                    self.selected -= 1;
+                }
+            }
+            Event::KeyDown { keycode: Some(Keycode::Down), .. } => {
+                if self.selected + 1 < self.levels.len() {
+                    self.selected += 1;
+                }
+            }
+            Event::KeyDown { keycode: Some(Keycode::Return), .. } => {
+                if !self.levels.is_empty() {
+                    self.chosen = Some(self.selected);
+                    return false;
+                }
+            }
+            _ => (),
+        }
+
+        true
+    }
+
+    fn update(&mut self) {}
+
+    fn render(&mut self, renderer: &mut Renderer) {
+        renderer.set_draw_color(Color::RGBA(0, 0, 0, 255));
+        renderer.clear();
+
+        self.title.render(renderer);
+
+        if !self.entries.is_empty() {
+            let width = renderer.window().unwrap().size().0;
+            let (_, height) = self.entries[self.selected].size();
+            renderer.set_draw_color(Color::RGB(0, 144, 192));
+            renderer.fill_rect(Rect::new(0,
+                                         entry_y(self.selected) - HIGHLIGHT_PADDING,
+                                         width,
+                                         height + HIGHLIGHT_PADDING as u32 * 2))
+                .unwrap();
+        }
+        for entry in &self.entries {
+            entry.render(renderer);
+        }
+
+        renderer.present();
+    }
+}
+
+fn entry_y(index: usize) -> i32 {
+    FIRST_ENTRY_Y + index as i32 * ENTRY_HEIGHT
+}
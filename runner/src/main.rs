@@ -0,0 +1,125 @@
+extern crate find_folder;
+extern crate sdl2;
+extern crate vm;
+
+mod level_screen;
+mod level_select;
+mod speaker;
+mod sprite;
+mod tile_map;
+
+use std::env;
+use std::path::PathBuf;
+use std::process;
+
+use find_folder::Search;
+
+use vm::{GameLoop, Level};
+
+use level_screen::{LevelScreen, SessionOptions};
+use level_select::LevelSelect;
+use speaker::Speaker;
+
+const UPDATES_PER_SECOND: u32 = 60;
+
+fn main() {
+    let mut manifest = None;
+    let mut options = SessionOptions::default();
+
+    // `hakka [level.json] [--record <file>] [--replay <file>] [--audiocap <file>]`
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--record" => options.record = args.next(),
+            "--replay" => options.replay = args.next(),
+            "--audiocap" => options.audio_capture = args.next(),
+            _ => manifest = Some(PathBuf::from(arg)),
+        }
+    }
+
+    let window_width = 1280;
+    let window_height = 720;
+
+    let sdl_context = sdl2::init().unwrap();
+    let ttf_context = sdl2::ttf::init().unwrap();
+    let video_subsystem = sdl_context.video().unwrap();
+
+    // Levels are still playable without a sound card, and their sound can
+    // still be captured with `audiocap`
+    let speaker = match Speaker::open(&sdl_context) {
+        Ok(speaker) => Some(speaker),
+        Err(err) => {
+            eprintln!("Unable to open audio, playing without sound: {}", err);
+            None
+        }
+    };
+
+    let window = video_subsystem.window("hakka", window_width, window_height)
+        .build()
+        .unwrap();
+
+    let mut renderer = window.renderer()
+        .accelerated()
+        .build()
+        .unwrap();
+
+    let assets = Search::KidsThenParents(3, 3).for_folder("assets").unwrap();
+    let font = assets.join("FantasqueSansMono-Bold.ttf");
+    let font_file = font.to_str().unwrap();
+
+    let mut events = sdl_context.event_pump().unwrap();
+    let mut timer = sdl_context.timer().unwrap();
+    let game_loop = GameLoop::new(UPDATES_PER_SECOND);
+
+    // A level given on the command line is played straight away. Otherwise
+    // the player picks levels from the level select screen until they quit
+    let direct = manifest.is_some();
+    loop {
+        let level = match manifest.take() {
+            Some(manifest) => {
+                Level::load(&manifest).unwrap_or_else(|err| {
+                    eprintln!("Unable to load level {}: {}", manifest.display(), err);
+                    process::exit(1);
+                })
+            }
+            None => {
+                let folder = Search::Parents(3).for_folder("levels").unwrap();
+                let levels = level_select::discover(&folder);
+                if levels.is_empty() {
+                    eprintln!("No levels found in {}", folder.display());
+                    process::exit(1);
+                }
+
+                let mut select = LevelSelect::new(levels, &ttf_context, &mut renderer, font_file);
+                game_loop.run(&mut timer, &mut events, &mut renderer, &mut select);
+                match select.into_chosen() {
+                    Some(level) => level,
+                    None => break,
+                }
+            }
+        };
+
+        let name = level.name.clone();
+        let mut screen = match LevelScreen::new(level,
+                                          &ttf_context,
+                                          &mut renderer,
+                                          &assets,
+                                          font_file,
+                                          &options,
+                                          speaker.as_ref()) {
+            Ok(screen) => screen,
+            Err(err) => {
+                eprintln!("Unable to start level {}:\n{}", name, err);
+                if direct {
+                    process::exit(1);
+                }
+                continue;
+            }
+        };
+        game_loop.run(&mut timer, &mut events, &mut renderer, &mut screen);
+
+        if direct || screen.quit() {
+            break;
+        }
+    }
+}
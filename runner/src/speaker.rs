@@ -0,0 +1,67 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use sdl2::Sdl;
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+
+use vm::{SampleQueue, SAMPLE_RATE};
+
+/// Plays the samples of a level's sound device through SDL audio
+pub struct Speaker {
+    /// Keeps playing for as long as it is open
+    _device: AudioDevice<Playback>,
+    samples: SampleQueue,
+    sample_rate: u32,
+}
+
+struct Playback {
+    samples: SampleQueue,
+}
+
+impl AudioCallback for Playback {
+    type Channel = i16;
+
+    fn callback(&mut self, out: &mut [i16]) {
+        // Play silence while the sound device falls behind
+        let mut samples = self.samples.lock().unwrap();
+        for sample in out.iter_mut() {
+            *sample = samples.pop_front().unwrap_or(0);
+        }
+    }
+}
+
+impl Speaker {
+    pub fn open(sdl_context: &Sdl) -> Result<Speaker, String> {
+        let audio = sdl_context.audio()?;
+        let desired = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE as i32),
+            channels: Some(1),
+            samples: None,
+        };
+
+        let samples: SampleQueue = Arc::new(Mutex::new(VecDeque::new()));
+        let mut sample_rate = SAMPLE_RATE;
+        let device = audio.open_playback(None, &desired, |spec| {
+                sample_rate = spec.freq as u32;
+                Playback { samples: samples.clone() }
+            })?;
+        device.resume();
+
+        Ok(Speaker {
+            _device: device,
+            samples: samples,
+            sample_rate: sample_rate,
+        })
+    }
+
+    /// The queue the sound device puts the samples to play in
+    pub fn samples(&self) -> SampleQueue {
+        self.samples.clone()
+    }
+
+    /// The sample rate the audio device plays at, which may not be the one
+    /// asked for
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+}
@@ -0,0 +1,82 @@
+use sdl2::rect::Rect;
+use sdl2::render::{Renderer, Texture, TextureQuery};
+
+use vm::{evaluate, SpriteBinding, SpriteTable, VirtualMachine};
+
+/// A texture drawn wherever the level's memory says it is
+pub struct Sprite {
+    binding: SpriteBinding,
+    width: u32,
+    height: u32,
+    texture: Texture,
+}
+
+impl Sprite {
+    pub fn new(texture: Texture, binding: SpriteBinding) -> Sprite {
+        let TextureQuery { width, height, .. } = texture.query();
+
+        Sprite {
+            binding: binding,
+            width: width,
+            height: height,
+            texture: texture,
+        }
+    }
+
+    pub fn render(&self, renderer: &mut Renderer, vm: &VirtualMachine) {
+        if let Some(ref visible) = self.binding.visible {
+            if evaluate(visible, vm).unwrap_or(0) == 0 {
+                return;
+            }
+        }
+
+        let x = evaluate(&self.binding.x, vm).unwrap_or(0) as i32;
+        let y = evaluate(&self.binding.y, vm).unwrap_or(0) as i32;
+        renderer.copy(&self.texture, None, Some(Rect::new(x, y, self.width, self.height)))
+            .unwrap();
+    }
+}
+
+/// Draws the sprites of a level's sprite table, wherever its memory says
+/// they are
+pub struct SpriteLayer {
+    table: SpriteTable,
+    /// The textures the entries pick from, with their width and height
+    images: Vec<(Texture, u32, u32)>,
+}
+
+impl SpriteLayer {
+    pub fn new(table: SpriteTable, images: Vec<Texture>) -> SpriteLayer {
+        let images = images.into_iter()
+            .map(|texture| {
+                let TextureQuery { width, height, .. } = texture.query();
+                (texture, width, height)
+            })
+            .collect();
+
+        SpriteLayer {
+            table: table,
+            images: images,
+        }
+    }
+
+    pub fn render(&self, renderer: &mut Renderer, vm: &VirtualMachine) {
+        for entry in self.table.entries(vm.cpu.memory()) {
+            if !entry.visible {
+                continue;
+            }
+            // Entries picking an image the level doesn't have are skipped,
+            // like sprites hidden by the flags
+            if let Some(&(ref texture, width, height)) = self.images.get(entry.image as usize) {
+                renderer.copy_ex(texture,
+                                 None,
+                                 Some(Rect::new(entry.x as i32, entry.y as i32, width, height)),
+                                 0.0,
+                                 None,
+                                 entry.flip_horizontal,
+                                 entry.flip_vertical)
+                    .unwrap();
+            }
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{Renderer, Texture, TextureQuery};
+
+use vm::{TileDisplay, VirtualMachine};
+
+const COLORS: usize = 16;
+
+/// Draws the tile map and palette a level keeps in memory
+pub struct TileMap {
+    display: TileDisplay,
+    /// The tile sheet, and the size of a tile in it
+    tiles: Option<(Texture, u32)>,
+}
+
+impl TileMap {
+    pub fn new(display: TileDisplay, tiles: Option<Texture>) -> TileMap {
+        let tiles = tiles.map(|texture| {
+            let TextureQuery { height, .. } = texture.query();
+            (texture, height)
+        });
+
+        TileMap {
+            display: display,
+            tiles: tiles,
+        }
+    }
+
+    pub fn render(&mut self, renderer: &mut Renderer, vm: &VirtualMachine) {
+        let memory = vm.cpu.memory();
+        let palette = self.display.palette as usize;
+        let colors = memory[palette..palette + COLORS * 3]
+            .chunks(3)
+            .map(|rgb| (rgb[0], rgb[1], rgb[2]))
+            .collect::<Vec<_>>();
+
+        let size = self.display.tile_size;
+        for row in 0..self.display.rows {
+            for column in 0..self.display.columns {
+                let cell = memory[self.display.map as usize + (row * self.display.columns + column) as usize];
+                let color = (cell & 0x0F) as usize;
+                if color == 0 {
+                    continue;
+                }
+                let (r, g, b) = colors[color];
+
+                let target = Rect::new(self.display.x + (column * size) as i32,
+                                       self.display.y + (row * size) as i32,
+                                       size,
+                                       size);
+                match self.tiles {
+                    Some((ref mut texture, tile_size)) => {
+                        let tile = (cell >> 4) as u32;
+                        texture.set_color_mod(r, g, b);
+                        renderer.copy(texture,
+                                      Some(Rect::new((tile * tile_size) as i32, 0, tile_size, tile_size)),
+                                      Some(target))
+                            .unwrap();
+                    }
+                    None => {
+                        renderer.set_draw_color(Color::RGB(r, g, b));
+                        renderer.fill_rect(target).unwrap();
+                    }
+                }
+            }
+        }
+    }
+}
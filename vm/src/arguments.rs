@@ -0,0 +1,387 @@
+use std::fmt;
+
+use expression::{evaluate_in, Machine};
+
+/// The type a command argument is converted to before the command runs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArgumentType {
+    /// An expression resulting in an address between 0x0000 and 0xFFFF
+    Address,
+    /// An address, optionally prefixed with the bank it is in, as in
+    /// `2:$8000`
+    BankedAddress,
+    /// An expression resulting in a single byte. Negative values down to
+    /// -0x80 are stored as their two's complement
+    Byte,
+    /// An expression resulting in a memory page between 0x00 and 0xFF
+    Page,
+    /// An expression resulting in any number
+    Number,
+    /// The argument text, unmodified
+    Text,
+}
+
+impl ArgumentType {
+    fn convert<M>(&self, input: &str, machine: &M) -> Result<Argument, String>
+        where M: Machine
+    {
+        if let ArgumentType::Text = *self {
+            return Ok(Argument::Text(input.into()));
+        }
+
+        if let ArgumentType::BankedAddress = *self {
+            let mut parts = input.splitn(2, ':');
+            let (bank, address) = match (parts.next(), parts.next()) {
+                (Some(bank), Some(address)) => (Some(bank), address),
+                _ => (None, input),
+            };
+            let bank = match bank {
+                Some(bank) => {
+                    match evaluate_in(bank, machine).map_err(|err| err.to_string())? {
+                        bank if bank >= 0 => Some(bank as usize),
+                        bank => return Err(format!("Bank {} does not exist", bank)),
+                    }
+                }
+                None => None,
+            };
+            let address = evaluate_in(address, machine).map_err(|err| err.to_string())?;
+            if address < 0 || address > 0xFFFF {
+                return Err(format!("Address {:X} outside addressable range", address));
+            }
+            return Ok(Argument::BankedAddress(bank, address as usize));
+        }
+
+        let value = evaluate_in(input, machine).map_err(|err| err.to_string())?;
+        match *self {
+            ArgumentType::Address if value >= 0 && value <= 0xFFFF => Ok(Argument::Address(value as usize)),
+            ArgumentType::Address => Err(format!("Address {:X} outside addressable range", value)),
+            ArgumentType::Byte if value >= -0x80 && value <= 0xFF => Ok(Argument::Byte(value as u8)),
+            ArgumentType::Byte => Err(format!("Value {:X} does not fit in a byte", value)),
+            ArgumentType::Page if value >= 0 && value <= 0xFF => Ok(Argument::Page(value as usize)),
+            ArgumentType::Page => Err(format!("Page {:X} outside addressable range", value)),
+            _ => Ok(Argument::Number(value)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Arity {
+    One,
+    /// Any number of arguments, at least one, each converted separately
+    Repeated,
+    /// Everything that remains on the command line, joined into one argument
+    Remainder,
+}
+
+#[derive(Clone, Debug)]
+struct Parameter {
+    name: &'static str,
+    kind: ArgumentType,
+    arity: Arity,
+}
+
+/// Describes one accepted form of a command's arguments
+#[derive(Clone, Debug, Default)]
+pub struct Signature {
+    parameters: Vec<Parameter>,
+}
+
+impl Signature {
+    /// A signature accepting no arguments
+    pub fn new() -> Signature {
+        Signature { parameters: Vec::new() }
+    }
+
+    /// Adds a single argument
+    pub fn arg(mut self, name: &'static str, kind: ArgumentType) -> Signature {
+        self.parameters.push(Parameter {
+            name: name,
+            kind: kind,
+            arity: Arity::One,
+        });
+        self
+    }
+
+    /// Adds one or more arguments of the same type. Must be the last
+    /// parameter of the signature
+    pub fn repeated(mut self, name: &'static str, kind: ArgumentType) -> Signature {
+        self.parameters.push(Parameter {
+            name: name,
+            kind: kind,
+            arity: Arity::Repeated,
+        });
+        self
+    }
+
+    /// Joins the rest of the command line into a single argument, so that it
+    /// may contain whitespace. Must be the last parameter of the signature
+    pub fn remainder(mut self, name: &'static str, kind: ArgumentType) -> Signature {
+        self.parameters.push(Parameter {
+            name: name,
+            kind: kind,
+            arity: Arity::Remainder,
+        });
+        self
+    }
+
+    fn accepts(&self, count: usize) -> bool {
+        match self.parameters.last() {
+            Some(last) if last.arity != Arity::One => count >= self.parameters.len(),
+            _ => count == self.parameters.len(),
+        }
+    }
+
+    fn convert<M>(&self, args: &[String], machine: &M) -> Result<Vec<Argument>, String>
+        where M: Machine
+    {
+        let mut result = Vec::new();
+
+        for (index, parameter) in self.parameters.iter().enumerate() {
+            let inputs = match parameter.arity {
+                Arity::One => vec![args[index].clone()],
+                Arity::Repeated => args[index..].to_vec(),
+                Arity::Remainder => vec![args[index..].join(" ")],
+            };
+            for input in inputs {
+                let argument = parameter.kind
+                    .convert(&input, machine)
+                    .map_err(|err| format!("Invalid <{}> '{}': {}", parameter.name, input, err))?;
+                result.push(argument);
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let parameters = self.parameters
+            .iter()
+            .map(|parameter| match parameter.arity {
+                Arity::One => format!("<{}>", parameter.name),
+                _ => format!("<{}...>", parameter.name),
+            })
+            .collect::<Vec<_>>();
+        write!(f, "{}", parameters.join(" "))
+    }
+}
+
+/// A converted command argument
+#[derive(Clone, Debug, PartialEq)]
+pub enum Argument {
+    Address(usize),
+    BankedAddress(Option<usize>, usize),
+    Byte(u8),
+    Page(usize),
+    Number(i64),
+    Text(String),
+}
+
+/// The converted arguments a command is executed with.
+///
+/// The typed accessors, such as `address` and `number`, expect the type
+/// that the matched signature declares at that index, which `parse` has
+/// already converted the argument to. They panic when asked for any other
+/// type, as that is a mistake in the command rather than in its input.
+#[derive(Debug)]
+pub struct Arguments {
+    signature: usize,
+    values: Vec<Argument>,
+}
+
+impl Arguments {
+    /// Matches the raw arguments against the given signatures, converting
+    /// them according to the first signature that accepts as many
+    /// arguments as were given
+    pub fn parse<M>(signatures: &[Signature], args: &[String], machine: &M) -> Result<Arguments, String>
+        where M: Machine
+    {
+        for (index, signature) in signatures.iter().enumerate() {
+            if signature.accepts(args.len()) {
+                return Ok(Arguments {
+                    signature: index,
+                    values: signature.convert(args, machine)?,
+                });
+            }
+        }
+
+        Err(format!("Unexpected number of arguments ({})", args.len()))
+    }
+
+    /// The index of the signature the arguments matched
+    pub fn signature(&self) -> usize {
+        self.signature
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> &Argument {
+        &self.values[index]
+    }
+
+    pub fn address(&self, index: usize) -> usize {
+        match self.values[index] {
+            Argument::Address(address) => address,
+            ref other => panic!("Expected an address argument, found {:?}", other),
+        }
+    }
+
+    /// The bank, if one was given, and the address
+    pub fn banked_address(&self, index: usize) -> (Option<usize>, usize) {
+        match self.values[index] {
+            Argument::BankedAddress(bank, address) => (bank, address),
+            ref other => panic!("Expected a banked address argument, found {:?}", other),
+        }
+    }
+
+    pub fn byte(&self, index: usize) -> u8 {
+        match self.values[index] {
+            Argument::Byte(byte) => byte,
+            ref other => panic!("Expected a byte argument, found {:?}", other),
+        }
+    }
+
+    pub fn bytes(&self, from: usize) -> Vec<u8> {
+        (from..self.values.len()).map(|index| self.byte(index)).collect()
+    }
+
+    pub fn page(&self, index: usize) -> usize {
+        match self.values[index] {
+            Argument::Page(page) => page,
+            ref other => panic!("Expected a page argument, found {:?}", other),
+        }
+    }
+
+    pub fn number(&self, index: usize) -> i64 {
+        match self.values[index] {
+            Argument::Number(number) => number,
+            ref other => panic!("Expected a number argument, found {:?}", other),
+        }
+    }
+
+    pub fn text(&self, index: usize) -> &str {
+        match self.values[index] {
+            Argument::Text(ref text) => text,
+            ref other => panic!("Expected a text argument, found {:?}", other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use expression::Machine;
+
+    use super::*;
+
+    struct TestMachine;
+
+    impl Machine for TestMachine {
+        fn memory(&self) -> &[u8] {
+            &[]
+        }
+
+        fn register(&self, _name: &str) -> Option<u16> {
+            None
+        }
+
+        fn symbol(&self, name: &str) -> Option<u16> {
+            match name {
+                "START" => Some(0xC000),
+                _ => None,
+            }
+        }
+    }
+
+    fn parse(signatures: &[Signature], args: &[&str]) -> Result<Arguments, String> {
+        let args = args.iter().map(|&arg| arg.to_owned()).collect::<Vec<_>>();
+        Arguments::parse(signatures, &args, &TestMachine)
+    }
+
+    #[test]
+    fn rejects_too_few_and_too_many_arguments() {
+        let signatures = [Signature::new().arg("address", ArgumentType::Address)];
+        assert_eq!(parse(&signatures, &[]).unwrap_err(), "Unexpected number of arguments (0)");
+        assert_eq!(parse(&signatures, &["10", "20"]).unwrap_err(),
+                   "Unexpected number of arguments (2)");
+
+        let signatures = [Signature::new()
+                              .arg("address", ArgumentType::Address)
+                              .repeated("bytes", ArgumentType::Byte)];
+        assert_eq!(parse(&signatures, &["10"]).unwrap_err(), "Unexpected number of arguments (1)");
+    }
+
+    #[test]
+    fn picks_the_first_signature_accepting_the_arguments() {
+        let signatures = [Signature::new(),
+                          Signature::new().arg("address", ArgumentType::Address),
+                          Signature::new()
+                              .arg("start", ArgumentType::Address)
+                              .arg("length", ArgumentType::Number)];
+        assert_eq!(parse(&signatures, &[]).unwrap().signature(), 0);
+
+        let args = parse(&signatures, &["START", "10"]).unwrap();
+        assert_eq!(args.signature(), 2);
+        assert_eq!(args.address(0), 0xC000);
+        assert_eq!(args.number(1), 0x10);
+    }
+
+    #[test]
+    fn converts_repeated_arguments_separately() {
+        let signatures = [Signature::new()
+                              .arg("address", ArgumentType::Address)
+                              .repeated("bytes", ArgumentType::Byte)];
+        let args = parse(&signatures, &["200", "1", "$FF", "-1"]).unwrap();
+        assert_eq!(args.len(), 4);
+        assert_eq!(args.address(0), 0x200);
+        assert_eq!(args.bytes(1), vec![0x01, 0xFF, 0xFF]);
+        assert_eq!(parse(&signatures, &["200", "1", "ZZ"]).unwrap_err(),
+                   "Invalid <bytes> 'ZZ': Unknown symbol 'ZZ'");
+    }
+
+    #[test]
+    fn joins_the_remainder_into_one_argument() {
+        let signatures = [Signature::new()
+                              .arg("name", ArgumentType::Text)
+                              .remainder("command", ArgumentType::Text)];
+        let args = parse(&signatures, &["up", "break", "UpArrow"]).unwrap();
+        assert_eq!(args.len(), 2);
+        assert_eq!(args.text(0), "up");
+        assert_eq!(args.text(1), "break UpArrow");
+        assert_eq!(signatures[0].to_string(), "<name> <command...>");
+    }
+
+    #[test]
+    fn bytes_fit_in_eight_bits() {
+        let signatures = [Signature::new().arg("value", ArgumentType::Byte)];
+        assert_eq!(parse(&signatures, &["FF"]).unwrap().byte(0), 0xFF);
+        assert_eq!(parse(&signatures, &["-80"]).unwrap().byte(0), 0x80);
+        assert_eq!(parse(&signatures, &["100"]).unwrap_err(),
+                   "Invalid <value> '100': Value 100 does not fit in a byte");
+        assert!(parse(&signatures, &["-81"]).is_err());
+    }
+
+    #[test]
+    fn banked_addresses_take_an_optional_bank() {
+        let signatures = [Signature::new().arg("address", ArgumentType::BankedAddress)];
+        assert_eq!(parse(&signatures, &["2:8000"]).unwrap().banked_address(0), (Some(2), 0x8000));
+        assert_eq!(parse(&signatures, &["START"]).unwrap().banked_address(0), (None, 0xC000));
+        assert_eq!(parse(&signatures, &["-1:8000"]).unwrap_err(),
+                   "Invalid <address> '-1:8000': Bank -1 does not exist");
+        assert_eq!(parse(&signatures, &["2:10000"]).unwrap_err(),
+                   "Invalid <address> '2:10000': Address 10000 outside addressable range");
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected a number argument")]
+    fn accessors_expect_the_type_of_the_signature() {
+        let signatures = [Signature::new().arg("address", ArgumentType::Address)];
+        parse(&signatures, &["10"]).unwrap().number(0);
+    }
+}
@@ -1,17 +1,41 @@
 
 use std;
-use std::io::Write;
+use std::cell::Cell;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
 use vm::VirtualMachine;
+use reload::SourceWatcher;
+use arguments::{Arguments, ArgumentType, Signature};
+use monitor::{MAX_RANGE_LENGTH, MAX_RANGES};
+use gdb::GdbServer;
+use dap::DapServer;
+use script::ScriptEngine;
+use config::{Configuration, Macro};
+use interconnect::{Endpoint, Interrupt};
+use sound;
 
 pub type UnblockEvent = Box<Fn(&mut VirtualMachine)>;
 
+/// How deeply scripts may `exec` other scripts, which stops a script that
+/// runs itself from recursing forever
+const MAX_SCRIPT_DEPTH: usize = 8;
+
+/// How deeply aliases and macros may expand into other aliases and macros
+const MAX_EXPANSION_DEPTH: usize = 16;
+
 pub struct CommandSystem {
     commands: Vec<Box<Command>>, 
+    script_depth: Cell<usize>,
+    expansion_depth: Cell<usize>,
 }
 impl CommandSystem {
     pub fn new() -> CommandSystem {
         let mut system = CommandSystem {
-            commands: Vec::new()
+            commands: Vec::new(),
+            script_depth: Cell::new(0),
+            expansion_depth: Cell::new(0),
         };
 
         system.add_command(HelpCommand);
@@ -25,7 +49,22 @@ impl CommandSystem {
         system.add_command(FlagsCommand);
         system.add_command(MemdmpCommand);
         system.add_command(MemsetCommand);
-        system.add_command(MonitorCommand);
+        system.add_command(MonitorCommand::new());
+        system.add_command(UnmonitorCommand);
+        system.add_command(PrintCommand);
+        system.add_command(GdbServerCommand);
+        system.add_command(DapServerCommand);
+        system.add_command(ExecCommand);
+        system.add_command(ScriptCommand);
+        system.add_command(UnscriptCommand);
+        system.add_command(AliasCommand);
+        system.add_command(MacroCommand);
+        system.add_command(UnaliasCommand);
+        system.add_command(HintCommand);
+        system.add_command(ReloadCommand);
+        system.add_command(CpuCommand);
+        system.add_command(DevicesCommand);
+        system.add_command(AudioCaptureCommand);
         system.add_command(ExitCommand);
 
         system
@@ -41,11 +80,37 @@ impl CommandSystem {
         where S: Into<String>
     {
         let command = command.into();
-        let parts = command.split_whitespace().map(String::from).collect::<Vec<_>>();
+        let parts = split_arguments(&command);
+        if parts.is_empty() {
+            return (CommandResult::NotFound, None);
+        }
+
+        // Built in commands come first, so an alias or macro of the same
+        // name in a hand edited configuration can't hide them
+        if !self.is_command(&parts[0]) {
+            match expand(&parts, vm.console.config()) {
+                Some(Ok(commands)) => return self.execute_expansion(&parts[0], commands, vm),
+                Some(Err(err)) => {
+                    writeln!(vm.console, "{}", err).unwrap();
+                    return (CommandResult::InvalidArgs, None);
+                }
+                None => (),
+            }
+        }
 
         for command in self.commands.iter() {
             if command.matches_name(parts[0].clone()) {
-                let result = command.execute(parts[1..].to_owned(), &self, &mut vm);
+                let signatures = command.get_signatures();
+                let args = match Arguments::parse(&signatures, &parts[1..], vm) {
+                    Ok(args) => args,
+                    Err(err) => {
+                        writeln!(vm.console, "{}", err).unwrap();
+                        write_usage(&**command, &parts[0], vm);
+                        return (CommandResult::InvalidArgs, None);
+                    }
+                };
+
+                let result = command.execute(args, &self, &mut vm);
                 if let CommandResult::SucessBlock = result {
                     return (result, Some(command.on_unblock_event()));
                 } else {
@@ -56,6 +121,237 @@ impl CommandSystem {
 
         (CommandResult::NotFound, None)
     }
+
+    /// Whether `name` is one of the names of a built in command
+    pub fn is_command(&self, name: &str) -> bool {
+        self.commands.iter().any(|command| command.matches_name(name.into()))
+    }
+
+    fn execute_expansion(&self,
+                         name: &str,
+                         commands: Vec<String>,
+                         vm: &mut VirtualMachine)
+                         -> (CommandResult, Option<UnblockEvent>) {
+        let depth = self.expansion_depth.get();
+        if depth >= MAX_EXPANSION_DEPTH {
+            writeln!(vm.console, "'{}' expands too deeply, does it refer to itself?", name).unwrap();
+            return (CommandResult::InvalidArgs, None);
+        }
+
+        self.expansion_depth.set(depth + 1);
+        let mut last: (CommandResult, Option<UnblockEvent>) = (CommandResult::Sucess, None);
+        for command in commands {
+            // Only the final command can be left blocking
+            if let (_, Some(unblock_event)) = last {
+                unblock_event(vm);
+            }
+
+            last = self.execute(&command[..], vm);
+            match last.0 {
+                CommandResult::NotFound => {
+                    writeln!(vm.console, "'{}' in '{}' is not a command", command, name).unwrap();
+                    last = (CommandResult::InvalidArgs, None);
+                    break;
+                }
+                CommandResult::InvalidArgs => break,
+                _ => (),
+            }
+        }
+        self.expansion_depth.set(depth);
+
+        last
+    }
+
+    /// Executes every line of a file as a console command. Blank lines and
+    /// lines starting with '#' are skipped. Stops at the first command that
+    /// fails, reporting the file and line it was on
+    pub fn execute_file<P>(&self, path: P, vm: &mut VirtualMachine) -> io::Result<()>
+        where P: AsRef<Path>
+    {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+
+        let depth = self.script_depth.get();
+        if depth >= MAX_SCRIPT_DEPTH {
+            return Err(io::Error::new(io::ErrorKind::Other, "scripts are nested too deeply"));
+        }
+        self.script_depth.set(depth + 1);
+        let result = self.execute_lines(path, BufReader::new(file), vm);
+        self.script_depth.set(depth);
+        result
+    }
+
+    fn execute_lines<R>(&self, path: &Path, reader: R, vm: &mut VirtualMachine) -> io::Result<()>
+        where R: BufRead
+    {
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (result, unblock_event) = self.execute(line, vm);
+            // Nothing can unblock a command while a script runs, so do it
+            // straight away
+            if let Some(unblock_event) = unblock_event {
+                unblock_event(vm);
+            }
+
+            let message = match result {
+                CommandResult::NotFound => "command not recognized",
+                CommandResult::InvalidArgs => "command failed",
+                _ => continue,
+            };
+            return Err(io::Error::new(io::ErrorKind::Other,
+                                      format!("{}:{}: {} '{}'", path.display(), number + 1, message, line)));
+        }
+
+        Ok(())
+    }
+}
+
+/// Resolves an alias or macro invocation into the commands it stands for.
+/// Returns `None` if the name is neither
+fn expand(parts: &[String], config: &Configuration) -> Option<Result<Vec<String>, String>> {
+    let name = &parts[0][..];
+    let args = &parts[1..];
+
+    // Arguments given to an alias are appended to its expansion
+    if let Some(expansion) = config.get_alias(name) {
+        let mut line = expansion.to_owned();
+        for arg in args {
+            line.push(' ');
+            line.push_str(arg);
+        }
+        return Some(Ok(split_commands(&line)));
+    }
+
+    config.get_macro(name).map(|definition| {
+        if args.len() != definition.parameters {
+            return Err(format!("Macro '{}' expects {} arguments, found {}",
+                               name,
+                               definition.parameters,
+                               args.len()));
+        }
+        Ok(split_commands(&substitute(&definition.body, args)))
+    })
+}
+
+/// Replaces the `$1`, `$2`, etc. placeholders in a macro body with the
+/// arguments. Anything else starting with '$', like the hex number `$10` in a
+/// macro with fewer than 10 parameters, is left alone
+fn substitute(body: &str, args: &[String]) -> String {
+    let mut result = String::new();
+    let mut chars = body.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            result.push(c);
+            continue;
+        }
+
+        let mut word = String::new();
+        while let Some(&next) = chars.peek() {
+            if !next.is_alphanumeric() {
+                break;
+            }
+            word.push(next);
+            chars.next();
+        }
+
+        match word.parse::<usize>() {
+            Ok(index) if index >= 1 && index <= args.len() => result.push_str(&args[index - 1]),
+            _ => {
+                result.push('$');
+                result.push_str(&word);
+            }
+        }
+    }
+
+    result
+}
+
+/// Splits a line into the commands separated by ';'
+fn split_commands(line: &str) -> Vec<String> {
+    line.split(';')
+        .map(|command| command.trim())
+        .filter(|command| !command.is_empty())
+        .map(|command| command.to_owned())
+        .collect()
+}
+
+/// Writes every alias and macro to the console
+fn write_definitions(vm: &mut VirtualMachine) {
+    let mut lines = Vec::new();
+    for (name, expansion) in vm.console.config().aliases() {
+        lines.push(format!("alias {} \"{}\"", name, expansion));
+    }
+    for (name, definition) in vm.console.config().macros() {
+        let parameters = (1..definition.parameters + 1).map(|index| format!(" ${}", index)).collect::<String>();
+        lines.push(format!("macro {}{} = {}", name, parameters, definition.body));
+    }
+
+    if lines.is_empty() {
+        writeln!(vm.console, "No aliases or macros defined").unwrap();
+    }
+    for line in lines {
+        writeln!(vm.console, "{}", line).unwrap();
+    }
+}
+
+/// Checks that `name` can be used for a new alias or macro
+fn check_definition_name(name: &str, system: &CommandSystem) -> Result<(), String> {
+    if name.is_empty() || name.contains(|c: char| c == ';' || c == '"' || c == '$') {
+        return Err(format!("'{}' is not a valid name", name));
+    }
+    if system.is_command(name) {
+        return Err(format!("'{}' is already a command", name));
+    }
+    Ok(())
+}
+
+fn save_definitions(vm: &mut VirtualMachine) {
+    if let Err(err) = vm.console.save_config() {
+        writeln!(vm.console, "Unable to save the configuration file: {:?}", err).unwrap();
+    }
+}
+
+/// Splits a command line on whitespace, keeping bracketed expressions such
+/// as `[Y_0 + 1]` together as a single argument
+fn split_arguments(command: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+
+    for c in command.chars() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' if depth > 0 => depth -= 1,
+            c if c.is_whitespace() && depth == 0 => {
+                if !current.is_empty() {
+                    parts.push(current.clone());
+                    current.clear();
+                }
+                continue;
+            }
+            _ => (),
+        }
+        current.push(c);
+    }
+    if !current.is_empty() {
+        parts.push(current);
+    }
+
+    parts
+}
+
+/// Writes every accepted form of a command's arguments to the console
+fn write_usage(command: &Command, name: &str, vm: &mut VirtualMachine) {
+    for (index, signature) in command.get_signatures().iter().enumerate() {
+        let leader = if index == 0 { "Usage:" } else { "      " };
+        writeln!(vm.console, "{} {} {}", leader, name, signature).unwrap();
+    }
 }
 
 pub enum CommandResult {
@@ -66,12 +362,14 @@ pub enum CommandResult {
 }
 
 pub trait Command {
-    fn execute(&self, args: Vec<String>, system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult;
+    fn execute(&self, args: Arguments, system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult;
 
     fn get_names(&self) -> Vec<&str>;
 
-    fn get_arg_info(&self) -> Option<&str> {
-        None
+    /// The accepted forms of this command's arguments. The arguments are
+    /// validated and converted against these before `execute` is called
+    fn get_signatures(&self) -> Vec<Signature> {
+        vec![Signature::new()]
     }
 
     fn get_help(&self) -> &str {
@@ -95,15 +393,20 @@ pub trait Command {
 
 struct HelpCommand;
 impl Command for HelpCommand {
-    fn execute(&self, _args: Vec<String>, system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+    fn execute(&self, _args: Arguments, system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
         writeln!(vm.console, "Commands:").unwrap();
 
         // Creates strings containing all names, e.g. "help, h, ?"
         for command in system.commands.iter() {
             let name = command.get_names().join(", ");
             write!(vm.console, "   {}", name).unwrap();
-            if let Some(arg_info) = command.get_arg_info() {
-                write!(vm.console, ": {}", arg_info).unwrap();
+            let usage = command.get_signatures()
+                .iter()
+                .map(|signature| signature.to_string())
+                .map(|usage| if usage.is_empty() { "(none)".into() } else { usage })
+                .collect::<Vec<_>>();
+            if usage.iter().any(|usage| usage != "(none)") {
+                write!(vm.console, ": {}", usage.join(" OR ")).unwrap();
             }
             writeln!(vm.console, "").unwrap();
 
@@ -111,7 +414,7 @@ impl Command for HelpCommand {
             let help_lines = help.trim().lines().map(|line| line.trim()).collect::<Vec<_>>();
             if !help_lines.is_empty() {
                 for help_line in &help_lines {
-                    writeln!(vm.console, "      {}", help_line).unwrap();;
+                    writeln!(vm.console, "      {}", help_line).unwrap();
                 }
             }
         }
@@ -130,7 +433,7 @@ impl Command for HelpCommand {
 
 struct ClearCommand;
 impl Command for ClearCommand {
-    fn execute(&self, _args: Vec<String>, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+    fn execute(&self, _args: Arguments, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
         vm.console.clear();
         CommandResult::Sucess
     }
@@ -146,8 +449,16 @@ impl Command for ClearCommand {
 
 struct SourceCommand;
 impl Command for SourceCommand {
-    fn execute(&self, _args: Vec<String>, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
-        vm.dump_disassembly();
+    fn execute(&self, args: Arguments, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        if args.signature() == 1 {
+            let bank = args.number(0);
+            if bank < 0 || !vm.dump_bank_disassembly(bank as usize) {
+                writeln!(vm.console, "There is no bank {}", bank).unwrap();
+                return CommandResult::InvalidArgs;
+            }
+        } else {
+            vm.dump_disassembly();
+        }
         CommandResult::Sucess
     }
 
@@ -155,16 +466,22 @@ impl Command for SourceCommand {
         vec!["source"]
     }
 
+    fn get_signatures(&self) -> Vec<Signature> {
+        vec![Signature::new(), Signature::new().arg("bank", ArgumentType::Number)]
+    }
+
     fn get_help(&self) -> &str {
         "Lists the code currently running in the virtual
-         machine. A '>' symbol indicates the current
-         program counter."
+         machine, followed by the code in each bank. A '>'
+         symbol indicates the current program counter.
+         Lists only the code in the given <bank> if one
+         is specified."
     }
 }
 
 struct ListCommand;
 impl Command for ListCommand {
-    fn execute(&self, _args: Vec<String>, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+    fn execute(&self, _args: Arguments, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
         vm.dump_local_disassembly();
         CommandResult::Sucess
     }
@@ -181,7 +498,7 @@ impl Command for ListCommand {
 
 struct RegistersCommand;
 impl Command for RegistersCommand {
-    fn execute(&self, _args: Vec<String>, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+    fn execute(&self, _args: Arguments, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
         vm.dump_registers();
         CommandResult::Sucess
     }
@@ -196,88 +513,119 @@ impl Command for RegistersCommand {
     }
 }
 
-struct MonitorCommand;
+struct MonitorCommand {
+    help: String,
+}
+impl MonitorCommand {
+    fn new() -> MonitorCommand {
+        MonitorCommand {
+            help: format!("Shows the memory between <start> and <end>
+                           (inclusive) in a panel next to the console.
+                           Bytes that changed recently are highlighted.
+                           Up to {} ranges can be monitored at once.
+                           Without arguments, lists the monitored ranges.",
+                          MAX_RANGES),
+        }
+    }
+}
 impl Command for MonitorCommand {
-    fn execute(&self, args: Vec<String>, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
-        if args.len() != 2 {
-            writeln!(vm.console, "Expected 2 arguments, found {}", args.len()).unwrap();
-            return CommandResult::InvalidArgs;
+    fn execute(&self, args: Arguments, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        // List the watched ranges
+        if args.signature() == 0 {
+            let ranges = vm.monitor.ranges();
+            if ranges.is_empty() {
+                writeln!(vm.console, "No memory is being monitored").unwrap();
+            }
+            for (index, (start, end)) in ranges.into_iter().enumerate() {
+                writeln!(vm.console, "#{}: {:04X}-{:04X}", index, start, end).unwrap();
+            }
+            return CommandResult::Sucess;
         }
 
-        let start = usize::from_str_radix(&args[0].replace("0x", "")[..], 16);
-        if start.is_err() {
-            writeln!(vm.console, "Expected hexadecimal memory address, found {}", args[0]).unwrap();
+        let start = args.address(0);
+        let end = args.address(1);
+        if end < start {
+            writeln!(vm.console, "End address must not be before the start address").unwrap();
             return CommandResult::InvalidArgs;
         }
-        let start = start.unwrap();
-        
-        let end = usize::from_str_radix(&args[1].replace("0x", "")[..], 16);
-        if end.is_err() {
-            writeln!(vm.console, "Expected hexadecimal memory address, found {}", args[1]).unwrap();
+        if end - start >= MAX_RANGE_LENGTH {
+            writeln!(vm.console, "Can't monitor more than {} bytes at once", MAX_RANGE_LENGTH).unwrap();
             return CommandResult::InvalidArgs;
         }
-        let end = end.unwrap();
 
-        vm.enable_memory_monitor(start..end);
+        if !vm.monitor.add_range(start, end, vm.cpu.memory()) {
+            writeln!(vm.console, "Can't monitor more than {} ranges at once, see 'unmonitor'", MAX_RANGES).unwrap();
+            return CommandResult::InvalidArgs;
+        }
 
-        CommandResult::SucessBlock
+        CommandResult::Sucess
     }
 
-    fn on_unblock_event(&self) -> UnblockEvent {
-        Box::new(|vm| {
-            vm.disable_memory_monitor();
+    fn get_names(&self) -> Vec<&str> {
+        vec!["monitor", "mon"]
+    }
+
+    fn get_signatures(&self) -> Vec<Signature> {
+        vec![Signature::new(),
+             Signature::new()
+                 .arg("start", ArgumentType::Address)
+                 .arg("end", ArgumentType::Address)]
+    }
+
+    fn get_help(&self) -> &str {
+        &self.help
+    }
+}
+
+struct UnmonitorCommand;
+impl Command for UnmonitorCommand {
+    fn execute(&self, args: Arguments, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        if args.signature() == 0 {
+            vm.monitor.clear();
             writeln!(vm.console, "Disabled memory monitor").unwrap();
-        })
+            return CommandResult::Sucess;
+        }
+
+        let index = args.number(0);
+        if index < 0 || !vm.monitor.remove_range(index as usize) {
+            writeln!(vm.console, "No monitored range #{}", index).unwrap();
+            return CommandResult::InvalidArgs;
+        }
+
+        CommandResult::Sucess
     }
 
     fn get_names(&self) -> Vec<&str> {
-        vec!["monitor", "mon"]
+        vec!["unmonitor", "unmon"]
     }
-    
-    fn get_arg_info(&self) -> Option<&str> {
-        Some("start end")
+
+    fn get_signatures(&self) -> Vec<Signature> {
+        vec![Signature::new(), Signature::new().arg("index", ArgumentType::Number)]
     }
 
     fn get_help(&self) -> &str {
-        "Dumps the memory between <start> and <end>
-         (inclusive) every seconds. Press ENTER to
-         stop monitoring."
+        "Stops monitoring the range with the given
+         <index>, or every range if no index is given."
     }
 }
 
 struct MemsetCommand;
 impl Command for MemsetCommand {
-    fn execute(&self, args: Vec<String>, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
-        if args.len() < 2 {
-            writeln!(vm.console,
-                "Expected 2 arguments. E.g.: memset 0x00 0x01 stores 0x01 at address 0x00"
-            ).unwrap();
+    fn execute(&self, args: Arguments, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        let start = args.address(0);
+        let bytes = args.bytes(1);
+
+        if start + bytes.len() > vm.cpu.memory().len() {
+            writeln!(vm.console, "Values would be written outside addressable range.").unwrap();
             return CommandResult::InvalidArgs;
         }
-
-        let start = usize::from_str_radix(&args[0].replace("0x", "")[..], 16);
-        if start.is_err() {
-            writeln!(vm.console, "Expected hexadecimal memory address, found {}", args[0]).unwrap();
+        let protected = vm.write_protected(start, bytes.len()).cloned();
+        if let Some(region) = protected {
+            writeln!(vm.console, "Memory at {:04X}-{:04X} is {}", region.start, region.end, region.access).unwrap();
             return CommandResult::InvalidArgs;
         }
-        let start = start.unwrap();
 
-        let bytes = {
-            let mut bytes = Vec::new();
-            for index in 1..args.len() {
-                let byte = u8::from_str_radix(&args[index].replace("0x", "")[..], 16);
-                if byte.is_err() {
-                    writeln!(vm.console, "Expected hexadecimal byte, found {}", args[index]).unwrap();
-                    return CommandResult::InvalidArgs;
-                }
-                bytes.push(byte.unwrap());
-            }
-            bytes
-        };
-
-        for (index, byte) in bytes.iter().enumerate() {
-            vm.cpu.memory[start + index] = *byte;
-        }
+        vm.write_memory(start, &bytes);
 
         CommandResult::Sucess
     }
@@ -285,54 +633,46 @@ impl Command for MemsetCommand {
     fn get_names(&self) -> Vec<&str> {
         vec!["memset", "set"]
     }
-    
-    fn get_arg_info(&self) -> Option<&str> {
-        Some("addres value [values, ...]")
+
+    fn get_signatures(&self) -> Vec<Signature> {
+        vec![Signature::new()
+                 .arg("address", ArgumentType::Address)
+                 .repeated("values", ArgumentType::Byte)]
     }
 
     fn get_help(&self) -> &str {
         "Writes the given value to the given address.
          Multiple values will be written to consequent
-         addresses."
+         addresses. Read-only and privileged memory can't
+         be written."
     }
 }
 
 struct MemdmpCommand;
 impl Command for MemdmpCommand {
-    fn execute(&self, args: Vec<String>, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
-        if args.is_empty() || args.len() > 2 {
-            writeln!(vm.console, "Expected either 1 or 2 arguments, found {}", args.len()).unwrap();
-            return CommandResult::InvalidArgs;
-        }
-
+    fn execute(&self, args: Arguments, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
         // Dump a page
-        if args.len() == 1 {
-            let page = usize::from_str_radix(&args[0].replace("0x", "")[..], 16);
-            if page.is_err() {
-                writeln!(vm.console, "Expected page index, found {}", args[0]).unwrap();
-                return CommandResult::InvalidArgs;
-            }
-            let page = page.unwrap();
-
-            vm.dump_memory_page(page);
+        if args.signature() == 0 {
+            vm.dump_memory_page(args.page(0));
 
         // Dump a range
-        } else if args.len() == 2 { 
-            let start = usize::from_str_radix(&args[0].replace("0x", "")[..], 16);
-            if start.is_err() {
-                writeln!(vm.console, "Expected hexadecimal memory address, found {}", args[0]).unwrap();
+        } else {
+            let (bank, start) = args.banked_address(0);
+            let end = args.address(1);
+            if end < start {
+                writeln!(vm.console, "End address must not be before the start address").unwrap();
                 return CommandResult::InvalidArgs;
             }
-            let start = start.unwrap();
 
-            let end = usize::from_str_radix(&args[1].replace("0x", "")[..], 16);
-            if end.is_err() {
-                writeln!(vm.console, "Expected hexadecimal memory address, found {}", args[1]).unwrap();
-                return CommandResult::InvalidArgs;
+            match bank {
+                Some(bank) => {
+                    if !vm.dump_bank_range(bank, start, end) {
+                        writeln!(vm.console, "Bank {} isn't mapped at {:04X}-{:04X}", bank, start, end).unwrap();
+                        return CommandResult::InvalidArgs;
+                    }
+                }
+                None => vm.dump_memory_range(start, end),
             }
-            let end = end.unwrap();
-
-            vm.dump_memory_range(start, end);
         }
 
         CommandResult::Sucess
@@ -341,20 +681,61 @@ impl Command for MemdmpCommand {
     fn get_names(&self) -> Vec<&str> {
         vec!["memdmp", "dmp"]
     }
-    
-    fn get_arg_info(&self) -> Option<&str> {
-        Some("page OR start end")
+
+    fn get_signatures(&self) -> Vec<Signature> {
+        vec![Signature::new().arg("page", ArgumentType::Page),
+             Signature::new()
+                 .arg("start", ArgumentType::BankedAddress)
+                 .arg("end", ArgumentType::Address)]
     }
 
     fn get_help(&self) -> &str {
         "Dumps a single memory page, or a specified memory
-         range from <start> to <end>."
+         range from <start> to <end>. Prefix <start> with a
+         bank, as in 2:$8000, to dump a bank that isn't
+         mapped right now."
+    }
+}
+
+struct PrintCommand;
+impl Command for PrintCommand {
+    fn execute(&self, args: Arguments, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        let value = args.number(0);
+        if value >= 0 && value <= 0xFF {
+            writeln!(vm.console, "${:02X} ({}) %{:08b}", value, value, value).unwrap();
+        } else if value >= 0 && value <= 0xFFFF {
+            writeln!(vm.console, "${:04X} ({}) %{:016b}", value, value, value).unwrap();
+        } else {
+            writeln!(vm.console, "{}", value).unwrap();
+        }
+
+        CommandResult::Sucess
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["print", "p"]
+    }
+
+    fn get_signatures(&self) -> Vec<Signature> {
+        vec![Signature::new().remainder("expression", ArgumentType::Number)]
+    }
+
+    fn get_help(&self) -> &str {
+        "Evaluates and prints an expression. Numbers are
+         hexadecimal unless prefixed with % (binary) or
+         # (decimal). A, X, Y, S and PC are registers,
+         [addr] reads a byte and w[addr] a word from
+         memory. Labels from the level can be used by
+         name. Registers and labels win over numbers,
+         so write $A for the number A. Any address or
+         value argument of the other commands accepts
+         expressions as well."
     }
 }
 
 struct FlagsCommand;
 impl Command for FlagsCommand {
-    fn execute(&self, _args: Vec<String>, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+    fn execute(&self, _args: Arguments, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
         vm.dump_flags();
         CommandResult::Sucess
     }
@@ -364,40 +745,41 @@ impl Command for FlagsCommand {
     }
 
     fn get_help(&self) -> &str {
-        "Not yet implemented"
+        "Lists the CPU status flags and their current
+         values. While execution is stopped, the flags
+         are also shown in the top right corner."
     }
 }
 
 struct BreakCommand;
 impl Command for BreakCommand {
-    fn execute(&self, args: Vec<String>, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
-        if args.len() > 1 {
-            writeln!(vm.console, "Expected 0 or 1 arguments, found {}", args.len()).unwrap();
-            return CommandResult::InvalidArgs;
-        }
-
+    fn execute(&self, args: Arguments, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
         // Break at the given address
-        if args.len() == 1 {
-            let address = usize::from_str_radix(&args[0].replace("0x", "")[..], 16);
-            if address.is_err() {
-                writeln!(vm.console, "Expected hexadecimal memory address, found {}", args[0]).unwrap();
-            }
-            let address = address.unwrap();
-
-            if address <= u16::max_value() as usize {
-                if vm.toggle_breakpoint(address) {
-                    writeln!(vm.console, "Added breakpoint at {:04X}", address).unwrap();
-                } else {
-                    writeln!(vm.console, "Removed breakpoint at {:04X}", address).unwrap();
+        if args.signature() == 1 {
+            match args.banked_address(0) {
+                (Some(bank), address) => {
+                    match vm.toggle_bank_breakpoint(bank, address) {
+                        Some(true) => writeln!(vm.console, "Added breakpoint at {}:{:04X}", bank, address).unwrap(),
+                        Some(false) => writeln!(vm.console, "Removed breakpoint at {}:{:04X}", bank, address).unwrap(),
+                        None => {
+                            writeln!(vm.console, "Bank {} isn't mapped at {:04X}", bank, address).unwrap();
+                            return CommandResult::InvalidArgs;
+                        }
+                    }
+                }
+                (None, address) => {
+                    if vm.toggle_breakpoint(address) {
+                        writeln!(vm.console, "Added breakpoint at {:04X}", address).unwrap();
+                    } else {
+                        writeln!(vm.console, "Removed breakpoint at {:04X}", address).unwrap();
+                    }
                 }
-            } else {
-                writeln!(vm.console, "Address outside addressable range.").unwrap();
             }
 
         // Break at current program counter
         } else {
             vm.break_execution();
-            writeln!(vm.console, "Breaking execution at {:04X}", vm.cpu.registers.PC).unwrap();
+            writeln!(vm.console, "Breaking execution at {:04X}", vm.cpu.pc()).unwrap();
         }
 
         CommandResult::Sucess
@@ -407,8 +789,8 @@ impl Command for BreakCommand {
         vec!["break", "b"]
     }
 
-    fn get_arg_info(&self) -> Option<&str> {
-        Some("[address]")
+    fn get_signatures(&self) -> Vec<Signature> {
+        vec![Signature::new(), Signature::new().arg("address", ArgumentType::BankedAddress)]
     }
 
     fn get_help(&self) -> &str {
@@ -416,13 +798,14 @@ impl Command for BreakCommand {
          If the program counter hits this address, execution
          stops. If no address is specified, execution will
          be stopped at the current point, without inserting
-         a breakpoint."
+         a breakpoint. Prefix the address with a bank, as in
+         2:$8000, to only stop while that bank is mapped."
     }
 }
 
 struct ContinueCommand;
 impl Command for ContinueCommand {
-    fn execute(&self, _args: Vec<String>, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+    fn execute(&self, _args: Arguments, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
         vm.continue_execution();
         CommandResult::Sucess
     }
@@ -439,7 +822,7 @@ impl Command for ContinueCommand {
 
 struct StepCommand;
 impl Command for StepCommand {
-    fn execute(&self, _args: Vec<String>, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+    fn execute(&self, _args: Arguments, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
         vm.step_execution();
         CommandResult::Sucess
     }
@@ -454,9 +837,726 @@ impl Command for StepCommand {
     }
 }
 
+struct GdbServerCommand;
+impl Command for GdbServerCommand {
+    fn execute(&self, args: Arguments, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        // Report the server status
+        if args.signature() == 0 {
+            match vm.gdb_server {
+                Some(ref server) => {
+                    let address = server.local_addr().unwrap();
+                    let status = if server.is_connected() { "client connected" } else { "waiting for client" };
+                    writeln!(vm.console, "GDB server listening on {} ({})", address, status).unwrap();
+                }
+                None => {
+                    writeln!(vm.console, "GDB server is not running").unwrap();
+                }
+            }
+            return CommandResult::Sucess;
+        }
+
+        let target = args.text(0).to_owned();
+        if target == "stop" || target == "off" {
+            if vm.gdb_server.take().is_some() {
+                vm.continue_execution();
+                writeln!(vm.console, "GDB server stopped").unwrap();
+            }
+            return CommandResult::Sucess;
+        }
+
+        let port = match target.trim_start_matches(':').parse::<u16>() {
+            Ok(port) => port,
+            Err(_) => {
+                writeln!(vm.console, "Expected a port such as :2345, found {}", target).unwrap();
+                return CommandResult::InvalidArgs;
+            }
+        };
+
+        // Replace any running server, so its port is released first
+        vm.gdb_server = None;
+        match GdbServer::bind(port) {
+            Ok(server) => {
+                writeln!(vm.console, "GDB server listening on {}", server.local_addr().unwrap()).unwrap();
+                vm.gdb_server = Some(server);
+                CommandResult::Sucess
+            }
+            Err(err) => {
+                writeln!(vm.console, "Unable to start GDB server on port {}: {}", port, err).unwrap();
+                CommandResult::InvalidArgs
+            }
+        }
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["gdbserver"]
+    }
+
+    fn get_signatures(&self) -> Vec<Signature> {
+        vec![Signature::new(), Signature::new().arg("port", ArgumentType::Text)]
+    }
+
+    fn get_help(&self) -> &str {
+        "Serves the virtual machine over the GDB remote
+         protocol on the given localhost <port>, e.g.
+         'gdbserver :2345'. Registers are exchanged as
+         A, X, Y, S, P and a little endian PC. Use
+         'gdbserver stop' to shut the server down."
+    }
+}
+
+struct DapServerCommand;
+impl Command for DapServerCommand {
+    fn execute(&self, args: Arguments, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        // Report the server status
+        if args.signature() == 0 {
+            match vm.dap_server {
+                Some(ref server) => {
+                    let address = server.local_addr().unwrap();
+                    let status = if server.is_connected() { "client connected" } else { "waiting for client" };
+                    writeln!(vm.console, "Debug adapter listening on {} ({})", address, status).unwrap();
+                }
+                None => {
+                    writeln!(vm.console, "Debug adapter is not running").unwrap();
+                }
+            }
+            return CommandResult::Sucess;
+        }
+
+        let target = args.text(0).to_owned();
+        if target == "stop" || target == "off" {
+            if let Some(mut server) = vm.dap_server.take() {
+                server.close(vm);
+                writeln!(vm.console, "Debug adapter stopped").unwrap();
+            }
+            return CommandResult::Sucess;
+        }
+
+        let port = match target.trim_start_matches(':').parse::<u16>() {
+            Ok(port) => port,
+            Err(_) => {
+                writeln!(vm.console, "Expected a port such as :4711, found {}", target).unwrap();
+                return CommandResult::InvalidArgs;
+            }
+        };
+
+        // Replace any running server, so its port is released first
+        if let Some(mut server) = vm.dap_server.take() {
+            server.close(vm);
+        }
+        match DapServer::bind(port) {
+            Ok(server) => {
+                writeln!(vm.console, "Debug adapter listening on {}", server.local_addr().unwrap()).unwrap();
+                vm.dap_server = Some(server);
+                CommandResult::Sucess
+            }
+            Err(err) => {
+                writeln!(vm.console, "Unable to start debug adapter on port {}: {}", port, err).unwrap();
+                CommandResult::InvalidArgs
+            }
+        }
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["dap"]
+    }
+
+    fn get_signatures(&self) -> Vec<Signature> {
+        vec![Signature::new(), Signature::new().arg("port", ArgumentType::Text)]
+    }
+
+    fn get_help(&self) -> &str {
+        "Serves the virtual machine over the Debug
+         Adapter Protocol on the given localhost <port>,
+         e.g. 'dap :4711', so editors can set breakpoints
+         in level.asm and step through it. Use 'dap stop'
+         to shut the server down."
+    }
+}
+
+struct ExecCommand;
+impl Command for ExecCommand {
+    fn execute(&self, args: Arguments, system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        let file = args.text(0).to_owned();
+        match system.execute_file(&file, vm) {
+            Ok(()) => CommandResult::Sucess,
+            Err(err) => {
+                writeln!(vm.console, "Unable to execute {}: {}", file, err).unwrap();
+                CommandResult::InvalidArgs
+            }
+        }
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["exec"]
+    }
+
+    fn get_signatures(&self) -> Vec<Signature> {
+        vec![Signature::new().remainder("file", ArgumentType::Text)]
+    }
+
+    fn get_help(&self) -> &str {
+        "Runs each line of <file> as a console command,
+         skipping blank lines and lines starting with
+         '#'. Commands in the 'hakkarc' file next to
+         config.json are run when the game starts."
+    }
+}
+
+struct ScriptCommand;
+impl Command for ScriptCommand {
+    fn execute(&self, args: Arguments, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        if args.signature() == 0 {
+            let scripts = vm.scripts
+                .as_ref()
+                .map(|scripts| scripts.scripts().iter().map(|path| path.display().to_string()).collect())
+                .unwrap_or_else(Vec::new);
+            if scripts.is_empty() {
+                writeln!(vm.console, "No scripts are running").unwrap();
+            }
+            for (index, script) in scripts.iter().enumerate() {
+                writeln!(vm.console, "#{}: {}", index, script).unwrap();
+            }
+            return CommandResult::Sucess;
+        }
+
+        let file = args.text(0).to_owned();
+        let mut scripts = vm.scripts.take().unwrap_or_else(ScriptEngine::new);
+        let result = scripts.load(&file, vm);
+        vm.scripts = Some(scripts);
+
+        match result {
+            Ok(true) => {
+                writeln!(vm.console, "Script {} is running", file).unwrap();
+                CommandResult::Sucess
+            }
+            Ok(false) => CommandResult::Sucess,
+            Err(err) => {
+                writeln!(vm.console, "Unable to run {}: {}", file, err).unwrap();
+                CommandResult::InvalidArgs
+            }
+        }
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["script"]
+    }
+
+    fn get_signatures(&self) -> Vec<Signature> {
+        vec![Signature::new(), Signature::new().remainder("file", ArgumentType::Text)]
+    }
+
+    fn get_help(&self) -> &str {
+        "Runs a Rhai script <file>. Scripts that define
+         on_frame() or on_breakpoint(address) keep
+         running and are called every frame or when a
+         breakpoint is hit. Without arguments, lists
+         the running scripts."
+    }
+}
+
+struct UnscriptCommand;
+impl Command for UnscriptCommand {
+    fn execute(&self, args: Arguments, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        if args.signature() == 0 {
+            vm.scripts = None;
+            writeln!(vm.console, "Stopped all scripts").unwrap();
+            return CommandResult::Sucess;
+        }
+
+        let index = args.number(0);
+        let removed = match vm.scripts {
+            Some(ref mut scripts) => index >= 0 && scripts.unload(index as usize),
+            None => false,
+        };
+        if !removed {
+            writeln!(vm.console, "No script #{}", index).unwrap();
+            return CommandResult::InvalidArgs;
+        }
+
+        CommandResult::Sucess
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["unscript"]
+    }
+
+    fn get_signatures(&self) -> Vec<Signature> {
+        vec![Signature::new(), Signature::new().arg("index", ArgumentType::Number)]
+    }
+
+    fn get_help(&self) -> &str {
+        "Stops the script with the given <index>, or
+         every script if no index is given."
+    }
+}
+
+struct AliasCommand;
+impl Command for AliasCommand {
+    fn execute(&self, args: Arguments, system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        if args.signature() == 0 {
+            write_definitions(vm);
+            return CommandResult::Sucess;
+        }
+
+        let name = args.text(0).to_owned();
+        let expansion = args.text(1).trim_matches('"').trim().to_owned();
+        if let Err(err) = check_definition_name(&name, system) {
+            writeln!(vm.console, "{}", err).unwrap();
+            return CommandResult::InvalidArgs;
+        }
+        if expansion.is_empty() {
+            writeln!(vm.console, "Expected the command '{}' stands for", name).unwrap();
+            return CommandResult::InvalidArgs;
+        }
+
+        vm.console.config_mut().set_alias(name, expansion);
+        save_definitions(vm);
+        CommandResult::Sucess
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["alias"]
+    }
+
+    fn get_signatures(&self) -> Vec<Signature> {
+        vec![Signature::new(),
+             Signature::new().arg("name", ArgumentType::Text).remainder("command", ArgumentType::Text)]
+    }
+
+    fn get_help(&self) -> &str {
+        "Defines <name> as another name for <command>,
+         e.g. 'alias fly \"memset 04 26\"'. Arguments given
+         to the alias are appended to the command, and
+         several commands can be separated by ';'.
+         Without arguments, lists all aliases and macros."
+    }
+}
+
+struct MacroCommand;
+impl Command for MacroCommand {
+    fn execute(&self, args: Arguments, system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        if args.signature() == 0 {
+            write_definitions(vm);
+            return CommandResult::Sucess;
+        }
+
+        let definition = args.text(0).to_owned();
+        let (head, body) = match definition.find('=') {
+            Some(index) => (&definition[..index], definition[index + 1..].trim()),
+            None => {
+                writeln!(vm.console, "Expected '=' between the macro name and its commands").unwrap();
+                return CommandResult::InvalidArgs;
+            }
+        };
+
+        let mut words = head.split_whitespace();
+        let name = words.next().unwrap_or("").to_owned();
+        if let Err(err) = check_definition_name(&name, system) {
+            writeln!(vm.console, "{}", err).unwrap();
+            return CommandResult::InvalidArgs;
+        }
+
+        // Parameters have to be listed as $1, $2, ... in order
+        let mut parameters = 0;
+        for word in words {
+            if word != format!("${}", parameters + 1) {
+                writeln!(vm.console, "Expected parameter ${}, found '{}'", parameters + 1, word).unwrap();
+                return CommandResult::InvalidArgs;
+            }
+            parameters += 1;
+        }
+        if body.is_empty() {
+            writeln!(vm.console, "Expected the commands '{}' runs", name).unwrap();
+            return CommandResult::InvalidArgs;
+        }
+
+        vm.console.config_mut().set_macro(name,
+                                          Macro {
+                                              parameters: parameters,
+                                              body: body.to_owned(),
+                                          });
+        save_definitions(vm);
+        CommandResult::Sucess
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["macro"]
+    }
+
+    fn get_signatures(&self) -> Vec<Signature> {
+        vec![Signature::new(), Signature::new().remainder("definition", ArgumentType::Text)]
+    }
+
+    fn get_help(&self) -> &str {
+        "Defines a command that runs several others,
+         separated by ';'. $1, $2, ... are replaced by
+         its arguments, e.g.
+         'macro pin $1 $2 = memset $1 $2; monitor $1 $1'.
+         Without arguments, lists all aliases and macros."
+    }
+}
+
+struct UnaliasCommand;
+impl Command for UnaliasCommand {
+    fn execute(&self, args: Arguments, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        let name = args.text(0).to_owned();
+        if !vm.console.config_mut().remove_definition(&name) {
+            writeln!(vm.console, "There is no alias or macro named '{}'", name).unwrap();
+            return CommandResult::InvalidArgs;
+        }
+
+        save_definitions(vm);
+        CommandResult::Sucess
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["unalias"]
+    }
+
+    fn get_signatures(&self) -> Vec<Signature> {
+        vec![Signature::new().arg("name", ArgumentType::Text)]
+    }
+
+    fn get_help(&self) -> &str {
+        "Removes the alias or macro called <name>"
+    }
+}
+
+struct HintCommand;
+impl Command for HintCommand {
+    fn execute(&self, _args: Arguments, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        let count = vm.hint_count();
+        if count == 0 {
+            writeln!(vm.console, "This level has no hints").unwrap();
+            return CommandResult::Sucess;
+        }
+
+        let revealed = vm.revealed_hints().len();
+        let hint = vm.reveal_hint().map(|hint| hint.to_owned());
+        match hint {
+            Some(hint) => writeln!(vm.console, "Hint {}/{}: {}", revealed + 1, count, hint).unwrap(),
+            None => {
+                let hints = vm.revealed_hints().to_vec();
+                for (index, hint) in hints.iter().enumerate() {
+                    writeln!(vm.console, "Hint {}/{}: {}", index + 1, count, hint).unwrap();
+                }
+                writeln!(vm.console, "There are no more hints").unwrap();
+            }
+        }
+
+        CommandResult::Sucess
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["hint"]
+    }
+
+    fn get_signatures(&self) -> Vec<Signature> {
+        vec![Signature::new()]
+    }
+
+    fn get_help(&self) -> &str {
+        "Reveals the next hint for the level. Each hint
+         gives away more than the last, and costs points."
+    }
+}
+
+struct ReloadCommand;
+impl Command for ReloadCommand {
+    fn execute(&self, args: Arguments, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        let mut watcher = match vm.watcher.take() {
+            Some(watcher) => watcher,
+            None => {
+                writeln!(vm.console, "There is no level source to reload").unwrap();
+                return CommandResult::InvalidArgs;
+            }
+        };
+
+        let result = if args.signature() == 0 {
+            watcher.reload(vm);
+            CommandResult::Sucess
+        } else {
+            match (args.text(0), args.text(1)) {
+                ("watch", "on") => {
+                    watcher.watching = true;
+                    writeln!(vm.console, "Watching {} for changes", sources(&watcher)).unwrap();
+                    CommandResult::Sucess
+                }
+                ("watch", "off") => {
+                    watcher.watching = false;
+                    writeln!(vm.console, "Stopped watching {}", sources(&watcher)).unwrap();
+                    CommandResult::Sucess
+                }
+                ("zeropage", "keep") => {
+                    watcher.preserve_zero_page = true;
+                    writeln!(vm.console, "The zero page is kept on reload").unwrap();
+                    CommandResult::Sucess
+                }
+                ("zeropage", "reset") => {
+                    watcher.preserve_zero_page = false;
+                    writeln!(vm.console, "The zero page is reset on reload").unwrap();
+                    CommandResult::Sucess
+                }
+                (setting, value) => {
+                    writeln!(vm.console, "Unknown setting '{} {}'", setting, value).unwrap();
+                    CommandResult::InvalidArgs
+                }
+            }
+        };
+
+        vm.watcher = Some(watcher);
+        result
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["reload"]
+    }
+
+    fn get_signatures(&self) -> Vec<Signature> {
+        vec![Signature::new(),
+             Signature::new().arg("setting", ArgumentType::Text).arg("value", ArgumentType::Text)]
+    }
+
+    fn get_help(&self) -> &str {
+        "Reassembles the level sources into their CPUs and
+         banks, and restarts the CPUs. Sources are also
+         reloaded when they change, use 'reload watch off'
+         to stop that. The zero page and the level's memory
+         writes go back to how the level started, unless
+         'reload zeropage keep' is set."
+    }
+}
+
+/// The file names of the sources a watcher reloads, for messages
+fn sources(watcher: &SourceWatcher) -> String {
+    watcher.sources()
+        .iter()
+        .map(|source| source.file_name().unwrap_or(source.as_os_str()).to_string_lossy().into_owned())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+struct CpuCommand;
+impl Command for CpuCommand {
+    fn execute(&self, args: Arguments, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        let action = if args.signature() == 0 { "list" } else { args.text(0) };
+        match (action, args.signature()) {
+            ("list", 0) | ("list", 1) => {
+                let selected = vm.selected_cpu();
+                for index in 0..vm.cpu_count() {
+                    let (source, pc) = vm.with_cpu(index, |vm| {
+                        let source = vm.symbols()
+                            .source()
+                            .and_then(|source| source.file_name())
+                            .map(|name| name.to_string_lossy().into_owned())
+                            .unwrap_or_else(|| "no source".into());
+                        (source, vm.cpu.pc())
+                    });
+                    let marker = if index == selected { '*' } else { ' ' };
+                    writeln!(vm.console, "{} {}: {} (PC {:04X})", marker, index, source, pc).unwrap();
+                }
+                CommandResult::Sucess
+            }
+            ("select", 2) => {
+                let index = args.number(1);
+                if index < 0 || !vm.select_cpu(index as usize) {
+                    writeln!(vm.console, "No cpu {}, there are {}", index, vm.cpu_count()).unwrap();
+                    return CommandResult::InvalidArgs;
+                }
+                writeln!(vm.console, "Selected cpu {}", index).unwrap();
+                CommandResult::Sucess
+            }
+            _ => {
+                writeln!(vm.console, "Expected 'cpu list' or 'cpu select <index>'").unwrap();
+                CommandResult::InvalidArgs
+            }
+        }
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["cpu"]
+    }
+
+    fn get_signatures(&self) -> Vec<Signature> {
+        vec![Signature::new(),
+             Signature::new().arg("list", ArgumentType::Text),
+             Signature::new().arg("select", ArgumentType::Text).arg("index", ArgumentType::Number)]
+    }
+
+    fn get_help(&self) -> &str {
+        "Lists the CPUs of the level with 'cpu list', or
+         selects the CPU with the given <index> with
+         'cpu select <index>'. Registers, memory,
+         breakpoints and the other debugging commands
+         all work on the selected CPU."
+    }
+}
+
+struct DevicesCommand;
+impl Command for DevicesCommand {
+    fn execute(&self, _args: Arguments, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        if vm.interconnect.is_empty() && vm.mappers().is_empty() && vm.protection().is_empty() && vm.sound.is_none() {
+            writeln!(vm.console, "There are no devices").unwrap();
+            return CommandResult::Sucess;
+        }
+
+        let mut lines = Vec::new();
+        for mapper in vm.mappers() {
+            lines.push(format!("Banks at ${:04X}-${:04X}, register ${:04X}: bank {} of {} mapped",
+                               mapper.window,
+                               mapper.window as usize + mapper.size - 1,
+                               mapper.register,
+                               mapper.selected(),
+                               mapper.banks().len()));
+        }
+        for region in vm.protection().regions() {
+            lines.push(format!("Protected ${:04X}-${:04X}: {}{}",
+                               region.start,
+                               region.end,
+                               region.access,
+                               describe_interrupt(region.interrupt)));
+        }
+        if let Some(ref sound) = vm.sound {
+            let capture = match sound.capture() {
+                Some(path) => format!(", capturing to {}", path.display()),
+                None => String::new(),
+            };
+            lines.push(format!("Sound at ${:04X}-${:04X}: {} voices{}",
+                               sound.address,
+                               sound.address as usize + sound::REGISTERS_SIZE - 1,
+                               sound::VOICES,
+                               capture));
+        }
+        for window in vm.interconnect.windows() {
+            let endpoints = window.endpoints.iter().map(describe_endpoint).collect::<Vec<_>>();
+            lines.push(format!("Shared {} bytes at {}{}",
+                               window.size,
+                               endpoints.join(", "),
+                               describe_interrupt(window.interrupt)));
+        }
+        for mailbox in vm.interconnect.mailboxes() {
+            lines.push(format!("Mailbox {} -> {}{}, {}",
+                               describe_endpoint(&mailbox.from),
+                               describe_endpoint(&mailbox.to),
+                               describe_interrupt(mailbox.interrupt),
+                               if mailbox.is_full() { "full" } else { "empty" }));
+        }
+        for line in lines {
+            writeln!(vm.console, "{}", line).unwrap();
+        }
+
+        CommandResult::Sucess
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["devices"]
+    }
+
+    fn get_signatures(&self) -> Vec<Signature> {
+        vec![Signature::new()]
+    }
+
+    fn get_help(&self) -> &str {
+        "Lists the bank switched windows and protected
+         memory of the selected cpu, the sound device,
+         the memory shared between the cpus and the
+         mailboxes they send messages through."
+    }
+}
+
+struct AudioCaptureCommand;
+impl Command for AudioCaptureCommand {
+    fn execute(&self, args: Arguments, _system: &CommandSystem, vm: &mut VirtualMachine) -> CommandResult {
+        let mut sound = match vm.sound.take() {
+            Some(sound) => sound,
+            None => {
+                writeln!(vm.console, "This level has no sound device").unwrap();
+                return CommandResult::InvalidArgs;
+            }
+        };
+
+        let result = if args.signature() == 0 {
+            match sound.capture() {
+                Some(path) => writeln!(vm.console, "Capturing audio to {}", path.display()).unwrap(),
+                None => writeln!(vm.console, "Audio is not being captured").unwrap(),
+            }
+            CommandResult::Sucess
+        } else {
+            match (args.text(0), args.signature()) {
+                ("off", 1) => {
+                    match sound.stop_capture() {
+                        Ok(Some((path, seconds))) => {
+                            writeln!(vm.console, "Captured {:.2} seconds of audio to {}", seconds, path.display()).unwrap();
+                            CommandResult::Sucess
+                        }
+                        Ok(None) => {
+                            writeln!(vm.console, "Audio is not being captured").unwrap();
+                            CommandResult::Sucess
+                        }
+                        Err(err) => {
+                            writeln!(vm.console, "Unable to finish the audio capture: {}", err).unwrap();
+                            CommandResult::InvalidArgs
+                        }
+                    }
+                }
+                ("on", 2) => {
+                    let file = args.text(1).to_owned();
+                    match sound.start_capture(&file) {
+                        Ok(()) => {
+                            writeln!(vm.console, "Capturing audio to {} at {} Hz", file, sound.sample_rate()).unwrap();
+                            CommandResult::Sucess
+                        }
+                        Err(err) => {
+                            writeln!(vm.console, "Unable to capture audio to {}: {}", file, err).unwrap();
+                            CommandResult::InvalidArgs
+                        }
+                    }
+                }
+                (action, _) => {
+                    writeln!(vm.console, "Unknown action '{}', expected 'on <file>' or 'off'", action).unwrap();
+                    CommandResult::InvalidArgs
+                }
+            }
+        };
+
+        vm.sound = Some(sound);
+        result
+    }
+
+    fn get_names(&self) -> Vec<&str> {
+        vec!["audiocap"]
+    }
+
+    fn get_signatures(&self) -> Vec<Signature> {
+        vec![Signature::new(),
+             Signature::new().arg("action", ArgumentType::Text),
+             Signature::new().arg("action", ArgumentType::Text).remainder("file", ArgumentType::Text)]
+    }
+
+    fn get_help(&self) -> &str {
+        "Writes the sound of the level to a WAV file,
+         e.g. 'audiocap on beep.wav', whether or not it
+         can be heard. 'audiocap off' finishes the file,
+         as does leaving the level. Replaying a session
+         with --audiocap <file> captures all of it."
+    }
+}
+
+fn describe_endpoint(endpoint: &Endpoint) -> String {
+    format!("cpu {}:${:04X}", endpoint.cpu, endpoint.address)
+}
+
+fn describe_interrupt(interrupt: Option<Interrupt>) -> &'static str {
+    match interrupt {
+        Some(Interrupt::Irq) => " (irq)",
+        Some(Interrupt::Nmi) => " (nmi)",
+        None => "",
+    }
+}
+
 struct ExitCommand;
 impl Command for ExitCommand {
-    fn execute(&self, _args: Vec<String>, _system: &CommandSystem, _vm: &mut VirtualMachine) -> CommandResult {
+    fn execute(&self, _args: Arguments, _system: &CommandSystem, _vm: &mut VirtualMachine) -> CommandResult {
         std::process::exit(0);
     }
 
@@ -468,3 +1568,112 @@ impl Command for ExitCommand {
         "Quits the game"
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rs6502::Cpu;
+
+    use super::*;
+
+    fn parts(line: &str) -> Vec<String> {
+        split_arguments(line)
+    }
+
+    fn config() -> Configuration {
+        let mut config = Configuration::default();
+        config.set_alias("go".into(), "break".into());
+        config.set_alias("run".into(), "break $10; continue".into());
+        config.set_macro("fill".into(),
+                         Macro {
+                             parameters: 2,
+                             body: "memset $1 $2; memdmp $1 $10".into(),
+                         });
+        config
+    }
+
+    #[test]
+    fn appends_arguments_to_aliases() {
+        assert_eq!(Some(Ok(vec!["break $C000".to_owned()])), expand(&parts("go $C000"), &config()));
+        assert_eq!(Some(Ok(vec!["break $10".to_owned(), "continue 1".to_owned()])),
+                   expand(&parts("run 1"), &config()));
+    }
+
+    #[test]
+    fn substitutes_macro_arguments_and_leaves_hex_numbers_alone() {
+        assert_eq!(Some(Ok(vec!["memset $0200 FF".to_owned(), "memdmp $0200 $10".to_owned()])),
+                   expand(&parts("fill $0200 FF"), &config()));
+        assert_eq!("$1 $3", substitute("$1 $3", &["$1".to_owned(), "two".to_owned()]));
+    }
+
+    #[test]
+    fn reports_macros_given_the_wrong_number_of_arguments() {
+        assert_eq!(Some(Err("Macro 'fill' expects 2 arguments, found 1".to_owned())),
+                   expand(&parts("fill $0200"), &config()));
+    }
+
+    #[test]
+    fn expands_nothing_that_is_neither_an_alias_nor_a_macro() {
+        assert_eq!(None, expand(&parts("step"), &config()));
+    }
+
+    #[test]
+    fn splits_commands_on_semicolons() {
+        assert_eq!(vec!["step".to_owned(), "registers".to_owned()],
+                   split_commands(" step ;; registers ; "));
+        assert!(split_commands(";").is_empty());
+    }
+
+    #[test]
+    fn runs_built_in_commands_over_aliases_of_the_same_name() {
+        let mut vm = VirtualMachine::headless(Cpu::new(), None).unwrap();
+        vm.console.config_mut().set_alias("clear".into(), "print 1".into());
+        let system = CommandSystem::new();
+
+        system.execute("clear", &mut vm);
+
+        assert!(vm.console.lines().is_empty());
+    }
+
+    #[test]
+    fn monitors_ranges_no_longer_than_the_maximum() {
+        let mut vm = VirtualMachine::headless(Cpu::new(), None).unwrap();
+        let system = CommandSystem::new();
+
+        system.execute("monitor $0300 $0200", &mut vm);
+        system.execute("monitor $0200 $0300", &mut vm);
+        system.execute("monitor $0200 $02FF", &mut vm);
+
+        assert_eq!(vec![(0x0200, 0x02FF)], vm.monitor.ranges());
+    }
+
+    #[test]
+    fn reveals_one_hint_at_a_time_then_repeats_them_all() {
+        let mut vm = VirtualMachine::headless(Cpu::new(), None).unwrap();
+        vm.load_hints(vec!["Look at $0200".into(), "Set it to 1".into()]);
+        let system = CommandSystem::new();
+
+        system.execute("hint", &mut vm);
+        system.execute("hint", &mut vm);
+        system.execute("hint", &mut vm);
+
+        assert_eq!(vec!["Hint 1/2: Look at $0200",
+                        "Hint 2/2: Set it to 1",
+                        "Hint 1/2: Look at $0200",
+                        "Hint 2/2: Set it to 1",
+                        "There are no more hints",
+                        ""],
+                   vm.console.lines());
+        assert_eq!(2, vm.revealed_hints().len());
+    }
+
+    #[test]
+    fn says_when_a_level_has_no_hints() {
+        let mut vm = VirtualMachine::headless(Cpu::new(), None).unwrap();
+        let system = CommandSystem::new();
+
+        system.execute("hint", &mut vm);
+
+        assert_eq!(vec!["This level has no hints", ""], vm.console.lines());
+        assert!(vm.reveal_hint().is_none());
+    }
+}
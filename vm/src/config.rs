@@ -1,22 +1,43 @@
 
 use sdl2::keyboard::Scancode;
 use rustc_serialize::json;
+use std::collections::BTreeMap;
 use std::path::Path;
 use std::fs::File;
 use std::io::{Read, Write};
 use std::io;
 
+use app_dirs::AppInfo;
+
+pub const APP_INFO: AppInfo = AppInfo { name: "hakka", author: "simon-whitehead" };
+pub const CONFIG_FILE: &'static str = "config.json";
+/// Console commands run at startup, stored next to the configuration file
+pub const STARTUP_SCRIPT: &'static str = "hakkarc";
+
 const DEFAULT_CONSOLE_TOGGLE: Scancode = Scancode::Grave;
 
 #[derive(RustcDecodable, RustcEncodable, Debug)]
 pub struct Configuration {
     console_toggle: i32,
+    // Optional so configuration files written before these existed still load
+    aliases: Option<BTreeMap<String, String>>,
+    macros: Option<BTreeMap<String, Macro>>,
+}
+
+/// A named sequence of console commands, separated by ';'. The arguments
+/// the macro is invoked with replace `$1`, `$2`, etc. in the body
+#[derive(RustcDecodable, RustcEncodable, Clone, Debug)]
+pub struct Macro {
+    pub parameters: usize,
+    pub body: String,
 }
 
 impl Configuration {
     pub fn default() -> Configuration {
         Configuration {
-            console_toggle: DEFAULT_CONSOLE_TOGGLE as i32
+            console_toggle: DEFAULT_CONSOLE_TOGGLE as i32,
+            aliases: None,
+            macros: None,
         }
     }
 
@@ -39,6 +60,54 @@ impl Configuration {
     pub fn get_scancode(&self) -> Scancode {
         Scancode::from_i32(self.console_toggle).unwrap_or(DEFAULT_CONSOLE_TOGGLE)
     }
+
+    pub fn get_alias(&self, name: &str) -> Option<&str> {
+        self.aliases.as_ref().and_then(|aliases| aliases.get(name)).map(|alias| &alias[..])
+    }
+
+    pub fn get_macro(&self, name: &str) -> Option<&Macro> {
+        self.macros.as_ref().and_then(|macros| macros.get(name))
+    }
+
+    pub fn aliases(&self) -> Vec<(&str, &str)> {
+        self.aliases
+            .iter()
+            .flat_map(|aliases| aliases.iter())
+            .map(|(name, expansion)| (&name[..], &expansion[..]))
+            .collect()
+    }
+
+    pub fn macros(&self) -> Vec<(&str, &Macro)> {
+        self.macros
+            .iter()
+            .flat_map(|macros| macros.iter())
+            .map(|(name, definition)| (&name[..], definition))
+            .collect()
+    }
+
+    /// Defines an alias, replacing any alias or macro with the same name
+    pub fn set_alias(&mut self, name: String, expansion: String) {
+        self.remove_definition(&name);
+        let mut aliases = self.aliases.take().unwrap_or_else(BTreeMap::new);
+        aliases.insert(name, expansion);
+        self.aliases = Some(aliases);
+    }
+
+    /// Defines a macro, replacing any alias or macro with the same name
+    pub fn set_macro(&mut self, name: String, definition: Macro) {
+        self.remove_definition(&name);
+        let mut macros = self.macros.take().unwrap_or_else(BTreeMap::new);
+        macros.insert(name, definition);
+        self.macros = Some(macros);
+    }
+
+    /// Removes the alias or macro with the given name. Returns false if
+    /// there was neither
+    pub fn remove_definition(&mut self, name: &str) -> bool {
+        let alias = self.aliases.as_mut().and_then(|aliases| aliases.remove(name)).is_some();
+        let definition = self.macros.as_mut().and_then(|macros| macros.remove(name)).is_some();
+        alias || definition
+    }
 }
 
 #[derive(Debug)]
@@ -1,6 +1,6 @@
 
 use std;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::io::Write;
 
 use sdl2::event::Event;
@@ -16,10 +16,7 @@ use app_dirs::*;
 
 use position::Position;
 use text::Text;
-use config::{Configuration, ConfigError};
-
-const APP_INFO: AppInfo = AppInfo { name: "hakka", author: "simon-whitehead" };
-const CONFIG_FILE: &'static str = "config.json";
+use config::{Configuration, ConfigError, APP_INFO, CONFIG_FILE};
 
 const BORDER_COLOR: Color = Color::RGBA(255, 255, 255, 64);
 
@@ -34,9 +31,10 @@ pub struct Console<'a> {
     visible_start_time: u32, /* Used to ensure that the KeyDown event that opens the console does not trigger text input */
 
     config: Configuration,
+    config_file: Option<PathBuf>,
 
-    font_file: &'a str,
-    leader: Text,
+    /// What the console is drawn with, if it is drawn at all
+    view: Option<View<'a>>,
     input_buffer: String,
     last_command: String,
     command_history: Vec<String>,
@@ -44,9 +42,15 @@ pub struct Console<'a> {
     cursor_position: usize,
     buffer: Vec<String>,
     backbuffer_y: i32,
+    size: (u32, u32),
+}
+
+/// The fonts and textures a console is drawn with
+struct View<'a> {
+    font_file: &'a str,
+    leader: Text,
     texture: Texture,
     ttf_context: &'a Sdl2TtfContext,
-    size: (u32, u32),
     font: Font<'a>,
 }
 
@@ -109,12 +113,7 @@ impl<'a> Console<'a> {
             }
         };
 
-        Console {
-            visible: false,
-            visible_start_time: 0,
-
-            config: config,
-
+        let view = View {
             font_file: font_file,
             leader: Text::new(ttf_context,
                               &mut renderer,
@@ -123,6 +122,32 @@ impl<'a> Console<'a> {
                               FONT_SIZE,
                               FONT_COLOR,
                               font_file),
+            texture: texture,
+            ttf_context: ttf_context,
+            font: font,
+        };
+
+        let mut console = Console::with_config(config, Some(config_file), Some(view));
+        console.size = (width / 2, height);
+        console
+    }
+
+    /// Creates a console that isn't drawn, with the default configuration.
+    /// It takes commands and keeps its output like any other console, so
+    /// the machine can run without a window, such as in tests
+    pub fn headless() -> Console<'a> {
+        Console::with_config(Configuration::default(), None, None)
+    }
+
+    fn with_config(config: Configuration, config_file: Option<PathBuf>, view: Option<View<'a>>) -> Console<'a> {
+        Console {
+            visible: false,
+            visible_start_time: 0,
+
+            config: config,
+            config_file: config_file,
+
+            view: view,
             input_buffer: "".into(),
             last_command: "".into(),
             command_history: Vec::new(),
@@ -130,10 +155,7 @@ impl<'a> Console<'a> {
             cursor_position: 0,
             buffer: Vec::new(),
             backbuffer_y: 0,
-            texture: texture,
-            ttf_context: ttf_context,
-            size: (width / 2, height),
-            font: font,
+            size: (0, 0),
             input_blocked: false,
         }
     }
@@ -250,6 +272,28 @@ impl<'a> Console<'a> {
         }
     }
 
+    pub fn config(&self) -> &Configuration {
+        &self.config
+    }
+
+    pub fn config_mut(&mut self) -> &mut Configuration {
+        &mut self.config
+    }
+
+    /// Writes the configuration back to the file it was loaded from, if it
+    /// was loaded from one
+    pub fn save_config(&self) -> Result<(), ConfigError> {
+        match self.config_file {
+            Some(ref config_file) => self.config.store(config_file),
+            None => Ok(()),
+        }
+    }
+
+    /// Every line written to the console so far
+    pub fn lines(&self) -> &[String] {
+        &self.buffer
+    }
+
     pub fn clear(&mut self) {
         self.buffer.clear();
     }
@@ -342,22 +386,30 @@ impl<'a> Console<'a> {
 
     /// Renders the Console
     pub fn render(&mut self, mut renderer: &mut Renderer) {
-        if self.visible {
+        if !self.visible {
+            return;
+        }
+
+        {
+            let view = match self.view {
+                Some(ref mut view) => view,
+                None => return,
+            };
 
             renderer.set_blend_mode(BlendMode::Blend);
-            self.texture.set_blend_mode(BlendMode::Blend);
-            renderer.copy(&self.texture,
+            view.texture.set_blend_mode(BlendMode::Blend);
+            renderer.copy(&view.texture,
                       None,
                       Some(Rect::new(0, 0, self.size.0, self.size.1)))
                 .unwrap();
-            self.generate_backbuffer_texture(&mut renderer);
+            view.render_buffer(&mut renderer, &self.buffer, self.size, self.backbuffer_y);
 
             if !self.input_blocked {
-                self.render_leader(&mut renderer);
+                view.render_leader(&mut renderer, self.size);
                 // Insert the cursor via a dodgy vertical line
                 let cursor_x =
                     60 + PADDING as i16 +
-                    self.font.size_of(&self.input_buffer[..self.cursor_position]).unwrap().0 as i16;
+                    view.font.size_of(&self.input_buffer[..self.cursor_position]).unwrap().0 as i16;
                 // Draw a dodgy cursor
                 renderer.thick_line(cursor_x,
                                 self.size.1 as i16 - FONT_SIZE as i16 - PADDING as i16,
@@ -368,30 +420,30 @@ impl<'a> Console<'a> {
                     .unwrap();
 
                 if !self.input_buffer.is_empty() {
-                    let text = Text::new(self.ttf_context,
+                    let text = Text::new(view.ttf_context,
                                          &mut renderer,
                                          &self.input_buffer[..],
                                          Position::XY(60 + PADDING,
                                                       self.size.1 as i32 - FONT_SIZE as i32 - PADDING),
                                          FONT_SIZE,
                                          FONT_COLOR,
-                                         self.font_file);
+                                         view.font_file);
                     text.render(&mut renderer);
                 }
             } else {
-                let text = Text::new(self.ttf_context,
+                let text = Text::new(view.ttf_context,
                                      &mut renderer,
                                      "Press Ctrl+C or ENTER to cancel",
                                      Position::XY(PADDING,
                                                   self.size.1 as i32 - FONT_SIZE as i32 - PADDING),
                                      FONT_SIZE,
                                      FONT_COLOR,
-                                     self.font_file);
+                                     view.font_file);
                 text.render(&mut renderer);
             }
-
-            self.render_border(&mut renderer);
         }
+
+        self.render_border(&mut renderer);
     }
 
     fn render_border(&self, mut renderer: &mut Renderer) {
@@ -418,24 +470,26 @@ impl<'a> Console<'a> {
                         BORDER_COLOR)
             .unwrap();
     }
+}
 
-    fn render_leader(&self, mut renderer: &mut Renderer) {
+impl<'a> View<'a> {
+    fn render_leader(&self, mut renderer: &mut Renderer, size: (u32, u32)) {
         // Render a black background behind it so the buffer scrolling looks
         // nicer.
-        let rect_y = self.size.1 as i32 - FONT_SIZE as i32 - PADDING;
+        let rect_y = size.1 as i32 - FONT_SIZE as i32 - PADDING;
         renderer.set_draw_color(Color::RGBA(0, 0, 0, 255));
-        renderer.fill_rect(Rect::new(0, rect_y, self.size.0, rect_y as u32)).unwrap();
+        renderer.fill_rect(Rect::new(0, rect_y, size.0, rect_y as u32)).unwrap();
         self.leader.render(&mut renderer);
     }
 
-    fn generate_backbuffer_texture(&mut self, mut renderer: &mut Renderer) {
-        let mut main_surface = Surface::new(self.size.0,
-                                            (self.size.1 - (FONT_SIZE as u32)),
+    fn render_buffer(&self, mut renderer: &mut Renderer, buffer: &[String], size: (u32, u32), backbuffer_y: i32) {
+        let mut main_surface = Surface::new(size.0,
+                                            (size.1 - (FONT_SIZE as u32)),
                                             PixelFormatEnum::RGBA8888)
             .unwrap();
         let mut counter = 2;
         // TODO: Make the line render limit here configurable
-        for (index, line) in self.buffer.iter().rev().take(200).enumerate() {
+        for (index, line) in buffer.iter().rev().take(200).enumerate() {
             // index 0 is the last line, b/c the iterator is reversed. writeln!
             // outputs a newline at the end of what is written, creating a new
             // string in the buffer, which we do not want to render
@@ -443,7 +497,7 @@ impl<'a> Console<'a> {
                 continue;
             }
 
-            let y_pos = self.size.1 as i32 - (FONT_SIZE as i32 * counter) + self.backbuffer_y;
+            let y_pos = size.1 as i32 - (FONT_SIZE as i32 * counter) + backbuffer_y;
             counter += 1;
 
             if line.trim().is_empty() {
@@ -456,7 +510,7 @@ impl<'a> Console<'a> {
                 .unwrap();
             surface.blit(None,
                       &mut main_surface,
-                      Some(Rect::new(PADDING, y_pos - PADDING, self.size.1, FONT_SIZE as u32)))
+                      Some(Rect::new(PADDING, y_pos - PADDING, size.1, FONT_SIZE as u32)))
                 .unwrap();
         }
         let texture = renderer.create_texture_from_surface(&main_surface)
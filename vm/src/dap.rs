@@ -0,0 +1,705 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+
+use rustc_serialize::base64::{FromBase64, ToBase64, STANDARD};
+use rustc_serialize::json::{Json, ToJson};
+
+use expression;
+use paths::{file_name, same_file};
+use symbols::SymbolTable;
+use vm::VirtualMachine;
+
+const THREAD_ID: u64 = 1;
+
+const REGISTERS_REFERENCE: u64 = 1;
+const FLAGS_REFERENCE: u64 = 2;
+const ZERO_PAGE_REFERENCE: u64 = 3;
+
+/// The most instructions a single `disassemble` request gets, which is more
+/// than the address space holds
+const MAX_INSTRUCTIONS: i64 = 0x10000;
+
+/// Serves the virtual machine over the Debug Adapter Protocol, so editors can
+/// set breakpoints in the level source, step through it and inspect the CPU.
+///
+/// Like the GDB server, this never blocks: `poll` should be called once per
+/// frame to accept a client and answer its requests.
+pub struct DapServer {
+    listener: TcpListener,
+    client: Option<TcpStream>,
+    buffer: Vec<u8>,
+    seq: u64,
+    /// The addresses of the breakpoints set by the client, which are
+    /// replaced whenever it sends a new set
+    breakpoints: Vec<usize>,
+    /// Whether the client was last told the program is running
+    running: bool,
+    /// Reported to the client the next time execution stops
+    stop_reason: &'static str,
+}
+
+impl DapServer {
+    /// Starts listening on the given port of the loopback interface
+    pub fn bind(port: u16) -> io::Result<DapServer> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+
+        Ok(DapServer {
+            listener: listener,
+            client: None,
+            buffer: Vec::new(),
+            seq: 1,
+            breakpoints: Vec::new(),
+            running: true,
+            stop_reason: "breakpoint",
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.client.is_some()
+    }
+
+    pub fn poll(&mut self, vm: &mut VirtualMachine) {
+        if self.client.is_none() {
+            self.accept(vm);
+        }
+        if self.client.is_none() {
+            return;
+        }
+
+        if let Err(err) = self.receive() {
+            writeln!(vm.console, "Debug adapter client disconnected ({})", err).unwrap();
+            self.disconnect(vm);
+            return;
+        }
+
+        while let Some(message) = next_message(&mut self.buffer) {
+            self.handle(&message, vm);
+            if self.client.is_none() {
+                writeln!(vm.console, "Debug adapter client detached").unwrap();
+                return;
+            }
+        }
+
+        // Let the client know when execution stopped or resumed, whether
+        // that was its own doing or caused by console commands
+        if vm.is_debugging() && !vm.is_stepping() {
+            if self.running {
+                self.running = false;
+                let reason = self.stop_reason;
+                self.stop_reason = "breakpoint";
+                self.send_event("stopped",
+                                object(vec![("reason", reason.to_json()),
+                                            ("threadId", THREAD_ID.to_json()),
+                                            ("allThreadsStopped", true.to_json())]));
+            }
+        } else if !vm.is_debugging() && !self.running {
+            self.running = true;
+            self.send_event("continued",
+                            object(vec![("threadId", THREAD_ID.to_json()),
+                                        ("allThreadsContinued", true.to_json())]));
+        }
+    }
+
+    fn accept(&mut self, vm: &mut VirtualMachine) {
+        match self.listener.accept() {
+            Ok((stream, address)) => {
+                if stream.set_nonblocking(true).is_err() {
+                    return;
+                }
+                writeln!(vm.console, "Debug adapter client connected from {}", address).unwrap();
+                self.client = Some(stream);
+                self.buffer.clear();
+                self.running = !vm.is_debugging();
+                self.stop_reason = "breakpoint";
+            }
+            Err(ref err) if err.kind() == ErrorKind::WouldBlock => (),
+            Err(err) => {
+                writeln!(vm.console, "Unable to accept debug adapter client: {}", err).unwrap()
+            }
+        }
+    }
+
+    /// Drops the client, removing its breakpoints and resuming execution
+    pub fn close(&mut self, vm: &mut VirtualMachine) {
+        if self.client.is_some() {
+            self.send_event("terminated", object(vec![]));
+        }
+        self.disconnect(vm);
+        vm.continue_execution();
+    }
+
+    fn disconnect(&mut self, vm: &mut VirtualMachine) {
+        for address in self.breakpoints.drain(..) {
+            if vm.has_breakpoint(address) {
+                vm.toggle_breakpoint(address);
+            }
+        }
+        self.client = None;
+        self.buffer.clear();
+    }
+
+    /// Reads everything the client has sent so far into the buffer
+    fn receive(&mut self) -> io::Result<()> {
+        let mut chunk = [0; 1024];
+        loop {
+            let read = match self.client {
+                Some(ref mut client) => client.read(&mut chunk),
+                None => return Ok(()),
+            };
+            match read {
+                Ok(0) => return Err(io::Error::new(ErrorKind::UnexpectedEof, "connection closed")),
+                Ok(length) => self.buffer.extend_from_slice(&chunk[..length]),
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn send(&mut self, mut message: BTreeMap<String, Json>) {
+        message.insert("seq".into(), self.seq.to_json());
+        self.seq += 1;
+
+        let body = Json::Object(message).to_string();
+        let packet = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+        let failed = match self.client {
+            Some(ref mut client) => client.write_all(packet.as_bytes()).is_err(),
+            None => false,
+        };
+        if failed {
+            self.client = None;
+            self.buffer.clear();
+        }
+    }
+
+    fn send_event(&mut self, event: &str, body: Json) {
+        let mut message = BTreeMap::new();
+        message.insert("type".into(), "event".to_json());
+        message.insert("event".into(), event.to_json());
+        message.insert("body".into(), body);
+        self.send(message);
+    }
+
+    fn respond(&mut self, request: &Json, result: Result<Json, String>) {
+        let mut message = BTreeMap::new();
+        message.insert("type".into(), "response".to_json());
+        message.insert("request_seq".into(),
+                       request.find("seq").cloned().unwrap_or(Json::Null));
+        message.insert("command".into(),
+                       request.find("command").cloned().unwrap_or(Json::Null));
+        match result {
+            Ok(body) => {
+                message.insert("success".into(), true.to_json());
+                message.insert("body".into(), body);
+            }
+            Err(err) => {
+                message.insert("success".into(), false.to_json());
+                message.insert("message".into(), err.to_json());
+            }
+        }
+        self.send(message);
+    }
+
+    fn handle(&mut self, request: &Json, vm: &mut VirtualMachine) {
+        let command = request.find("command").and_then(|command| command.as_string()).unwrap_or("");
+        let empty = Json::Object(BTreeMap::new());
+        let arguments = request.find("arguments").unwrap_or(&empty);
+
+        let result = match command {
+            "initialize" => {
+                let capabilities = object(vec![("supportsConfigurationDoneRequest", true.to_json()),
+                                               ("supportsSetVariable", true.to_json()),
+                                               ("supportsEvaluateForHovers", true.to_json()),
+                                               ("supportsReadMemoryRequest", true.to_json()),
+                                               ("supportsWriteMemoryRequest", true.to_json()),
+                                               ("supportsDisassembleRequest", true.to_json())]);
+                self.respond(request, Ok(capabilities));
+                self.send_event("initialized", object(vec![]));
+                return;
+            }
+            "launch" | "attach" | "configurationDone" => Ok(object(vec![])),
+            "setExceptionBreakpoints" => Ok(object(vec![("breakpoints", Json::Array(vec![]))])),
+            "setBreakpoints" => self.set_breakpoints(arguments, vm),
+            "threads" => {
+                let thread = object(vec![("id", THREAD_ID.to_json()), ("name", "6502".to_json())]);
+                Ok(object(vec![("threads", Json::Array(vec![thread]))]))
+            }
+            "stackTrace" => Ok(stack_trace(vm)),
+            "scopes" => Ok(scopes()),
+            "variables" => variables(arguments, vm),
+            "setVariable" => set_variable(arguments, vm),
+            "evaluate" => evaluate(arguments, vm),
+            "readMemory" => read_memory(arguments, vm.cpu.memory()),
+            "writeMemory" => write_memory(arguments, vm),
+            "disassemble" => disassemble(arguments, vm),
+            "continue" => {
+                vm.continue_execution();
+                self.running = true;
+                Ok(object(vec![("allThreadsContinued", true.to_json())]))
+            }
+            "next" | "stepIn" => {
+                vm.step_execution();
+                self.running = true;
+                self.stop_reason = "step";
+                Ok(object(vec![]))
+            }
+            // Single steps are all the VM can do, so it can't run until the
+            // subroutine returns
+            "stepOut" => Err("Stepping out isn't supported, continue to a breakpoint after the call instead".into()),
+            "pause" => {
+                vm.break_execution();
+                self.stop_reason = "pause";
+                Ok(object(vec![]))
+            }
+            "disconnect" => {
+                self.respond(request, Ok(object(vec![])));
+                self.disconnect(vm);
+                vm.continue_execution();
+                return;
+            }
+            _ => Err(format!("Unsupported request '{}'", command)),
+        };
+
+        self.respond(request, result);
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Json, vm: &mut VirtualMachine) -> Result<Json, String> {
+        for address in self.breakpoints.drain(..) {
+            if vm.has_breakpoint(address) {
+                vm.toggle_breakpoint(address);
+            }
+        }
+
+        let (response, addresses) = breakpoints(arguments, vm.symbols());
+        for address in addresses {
+            if !vm.has_breakpoint(address) {
+                vm.toggle_breakpoint(address);
+                self.breakpoints.push(address);
+            }
+        }
+        Ok(response)
+    }
+}
+
+fn object(fields: Vec<(&str, Json)>) -> Json {
+    Json::Object(fields.into_iter().map(|(name, value)| (name.to_owned(), value)).collect())
+}
+
+/// Takes the next complete message out of the buffer. Messages consist
+/// of a `Content-Length` header, an empty line and a JSON body
+fn next_message(buffer: &mut Vec<u8>) -> Option<Json> {
+    loop {
+        let header_end = match find(buffer, b"\r\n\r\n") {
+            Some(header_end) => header_end,
+            None => return None,
+        };
+
+        let length = String::from_utf8_lossy(&buffer[..header_end])
+            .lines()
+            .filter_map(|line| {
+                let mut parts = line.splitn(2, ':');
+                match (parts.next(), parts.next()) {
+                    (Some(name), Some(value)) if name.trim().eq_ignore_ascii_case("Content-Length") => {
+                        value.trim().parse::<usize>().ok()
+                    }
+                    _ => None,
+                }
+            })
+            .next();
+
+        let body_start = header_end + 4;
+        let length = match length {
+            Some(length) => length,
+            None => {
+                // Skip headers we can't make sense of
+                buffer.drain(..body_start);
+                continue;
+            }
+        };
+        if buffer.len() < body_start + length {
+            return None;
+        }
+
+        let message = buffer.drain(..body_start + length).skip(body_start).collect::<Vec<_>>();
+        if let Ok(message) = Json::from_str(&String::from_utf8_lossy(&message)) {
+            return Some(message);
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// Memory references handed to the client are hexadecimal addresses
+fn reference(address: usize) -> String {
+    format!("0x{:04X}", address)
+}
+
+fn parse_reference(reference: &str) -> Option<i64> {
+    let digits = reference.trim_start_matches("0x").trim_start_matches('$');
+    i64::from_str_radix(digits, 16).ok()
+}
+
+fn source(path: &Path) -> Json {
+    object(vec![("name", file_name(path).to_json()), ("path", path.to_string_lossy().into_owned().to_json())])
+}
+
+/// Finds the code at the lines the client wants breakpoints at. Returns the
+/// response, and the addresses to break at
+fn breakpoints(arguments: &Json, symbols: &SymbolTable) -> (Json, Vec<usize>) {
+    let path = arguments.find_path(&["source", "path"]).and_then(|path| path.as_string());
+    let matches_source = match (path, symbols.source()) {
+        (Some(path), Some(source)) => same_file(Path::new(path), source),
+        _ => false,
+    };
+
+    let requested = arguments.find("breakpoints")
+        .and_then(|breakpoints| breakpoints.as_array())
+        .map(|breakpoints| breakpoints.iter().filter_map(|b| b.find("line")).cloned().collect())
+        .unwrap_or_else(Vec::new);
+
+    let mut result = Vec::new();
+    let mut addresses = Vec::new();
+    for line in requested {
+        let line = line.as_u64().unwrap_or(0) as usize;
+        let location = if matches_source { symbols.address_of_line(line) } else { None };
+
+        match location {
+            Some((line, address)) => {
+                let address = address as usize;
+                addresses.push(address);
+                result.push(object(vec![("verified", true.to_json()),
+                                        ("line", (line as u64).to_json()),
+                                        ("instructionReference", reference(address).to_json())]));
+            }
+            None => {
+                result.push(object(vec![("verified", false.to_json()),
+                                        ("line", (line as u64).to_json()),
+                                        ("message", "No code at this line".to_json())]));
+            }
+        }
+    }
+
+    (object(vec![("breakpoints", Json::Array(result))]), addresses)
+}
+
+fn stack_trace(vm: &VirtualMachine) -> Json {
+    let pc = vm.cpu.pc();
+    let name = vm.symbols().label_before(pc).unwrap_or("PC").to_owned();
+
+    let mut frame = vec![("id", 0u64.to_json()),
+                         ("name", name.to_json()),
+                         ("instructionPointerReference", reference(pc as usize).to_json())];
+    match (vm.symbols().source(), vm.symbols().line_of_address(pc)) {
+        (Some(path), Some(line)) => {
+            frame.push(("source", source(path)));
+            frame.push(("line", (line as u64).to_json()));
+            frame.push(("column", 1u64.to_json()));
+        }
+        _ => {
+            frame.push(("line", 0u64.to_json()));
+            frame.push(("column", 0u64.to_json()));
+        }
+    }
+
+    object(vec![("stackFrames", Json::Array(vec![object(frame)])), ("totalFrames", 1u64.to_json())])
+}
+
+fn scopes() -> Json {
+    let scope = |name: &str, reference: u64| {
+        object(vec![("name", name.to_json()),
+                    ("variablesReference", reference.to_json()),
+                    ("expensive", false.to_json())])
+    };
+
+    object(vec![("scopes",
+                 Json::Array(vec![scope("Registers", REGISTERS_REFERENCE),
+                                  scope("Flags", FLAGS_REFERENCE),
+                                  scope("Zero page", ZERO_PAGE_REFERENCE)]))])
+}
+
+fn variable(name: &str, value: String) -> Json {
+    object(vec![("name", name.to_json()),
+                ("value", value.to_json()),
+                ("variablesReference", 0u64.to_json())])
+}
+
+fn variables(arguments: &Json, vm: &VirtualMachine) -> Result<Json, String> {
+    let scope = arguments.find("variablesReference").and_then(|r| r.as_u64()).unwrap_or(0);
+
+    let variables = match scope {
+        REGISTERS_REFERENCE => {
+            vm.registers()
+                .into_iter()
+                .map(|(name, value)| if name == "PC" {
+                    variable(name, format!("${:04X}", value))
+                } else {
+                    variable(name, format!("${:02X} ({})", value, value))
+                })
+                .collect()
+        }
+        FLAGS_REFERENCE => {
+            vm.flags()
+                .into_iter()
+                .filter(|&(letter, _, _)| letter != '-')
+                .map(|(letter, name, set)| variable(&format!("{} ({})", letter, name), set.to_string()))
+                .collect()
+        }
+        ZERO_PAGE_REFERENCE => {
+            vm.cpu.memory()[..0x100]
+                .chunks(0x10)
+                .enumerate()
+                .map(|(row, chunk)| {
+                    let address = row * 0x10;
+                    let bytes = chunk.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>();
+                    object(vec![("name", format!("${:02X}", address).to_json()),
+                                ("value", bytes.join(" ").to_json()),
+                                ("variablesReference", 0u64.to_json()),
+                                ("memoryReference", reference(address).to_json())])
+                })
+                .collect()
+        }
+        _ => return Err(format!("Unknown variables reference {}", scope)),
+    };
+
+    Ok(object(vec![("variables", Json::Array(variables))]))
+}
+
+fn set_variable(arguments: &Json, vm: &mut VirtualMachine) -> Result<Json, String> {
+    let scope = arguments.find("variablesReference").and_then(|r| r.as_u64()).unwrap_or(0);
+    let name = arguments.find("name").and_then(|n| n.as_string()).unwrap_or("").to_owned();
+    let value = arguments.find("value").and_then(|v| v.as_string()).unwrap_or("").to_owned();
+
+    match scope {
+        REGISTERS_REFERENCE => {
+            let number = expression::evaluate(&value, vm).map_err(|err| err.to_string())?;
+            if !vm.set_register(&name, number as u16) {
+                return Err(format!("Unknown register '{}'", name));
+            }
+            let value = vm.registers()
+                .into_iter()
+                .find(|&(register, _)| register == name)
+                .map(|(_, value)| value)
+                .unwrap_or(0);
+            Ok(object(vec![("value", format!("${:02X} ({})", value, value).to_json())]))
+        }
+        FLAGS_REFERENCE => {
+            let set = match &value.to_lowercase()[..] {
+                "true" | "1" => true,
+                "false" | "0" => false,
+                _ => return Err(format!("Expected true or false, found '{}'", value)),
+            };
+            let letter = name.chars().next().unwrap_or(' ');
+            if !vm.set_flag(letter, set) {
+                return Err(format!("Unknown flag '{}'", name));
+            }
+            Ok(object(vec![("value", set.to_string().to_json())]))
+        }
+        _ => Err("Only registers and flags can be changed".into()),
+    }
+}
+
+fn evaluate(arguments: &Json, vm: &VirtualMachine) -> Result<Json, String> {
+    let input = arguments.find("expression").and_then(|e| e.as_string()).unwrap_or("");
+    let value = expression::evaluate(input, vm).map_err(|err| err.to_string())?;
+
+    let result = if value >= 0 && value <= 0xFFFF {
+        format!("${:X} ({})", value, value)
+    } else {
+        value.to_string()
+    };
+    Ok(object(vec![("result", result.to_json()), ("variablesReference", 0u64.to_json())]))
+}
+
+/// Resolves the `memoryReference` and `offset` arguments of memory requests
+fn memory_start(arguments: &Json) -> Result<i64, String> {
+    let reference = arguments.find("memoryReference").and_then(|r| r.as_string()).unwrap_or("");
+    let base = parse_reference(reference).ok_or(format!("Invalid memory reference '{}'", reference))?;
+    let offset = arguments.find("offset").and_then(|o| o.as_i64()).unwrap_or(0);
+    base.checked_add(offset).ok_or_else(|| format!("Offset {} is out of range", offset))
+}
+
+/// Reads a count argument, which can't be negative
+fn count_argument(arguments: &Json, name: &str) -> Result<i64, String> {
+    let count = arguments.find(name).and_then(|c| c.as_i64()).unwrap_or(0);
+    if count < 0 {
+        return Err(format!("'{}' can't be negative", name));
+    }
+    Ok(count)
+}
+
+fn read_memory(arguments: &Json, memory: &[u8]) -> Result<Json, String> {
+    let start = memory_start(arguments)?;
+    let count = count_argument(arguments, "count")?;
+    let end = start.checked_add(count).ok_or_else(|| format!("Count {} is out of range", count))?;
+
+    let size = memory.len() as i64;
+    let first = start.max(0).min(size) as usize;
+    let last = end.max(0).min(size) as usize;
+    let data = memory[first..last].to_base64(STANDARD);
+
+    Ok(object(vec![("address", reference(first).to_json()),
+                   ("data", data.to_json()),
+                   ("unreadableBytes", (count as u64 - (last - first) as u64).to_json())]))
+}
+
+fn write_memory(arguments: &Json, vm: &mut VirtualMachine) -> Result<Json, String> {
+    let start = memory_start(arguments)?;
+    let data = arguments.find("data").and_then(|d| d.as_string()).unwrap_or("");
+    let bytes = data.from_base64().map_err(|err| err.to_string())?;
+
+    if start < 0 || start as usize + bytes.len() > vm.cpu.memory().len() {
+        return Err("Write outside addressable range".into());
+    }
+    if let Some(region) = vm.write_protected(start as usize, bytes.len()) {
+        return Err(format!("Memory at {:04X}-{:04X} is {}", region.start, region.end, region.access));
+    }
+    vm.write_memory(start as usize, &bytes);
+
+    Ok(object(vec![("bytesWritten", (bytes.len() as u64).to_json())]))
+}
+
+fn disassemble(arguments: &Json, vm: &VirtualMachine) -> Result<Json, String> {
+    let start = memory_start(arguments)?;
+    let instruction_offset = arguments.find("instructionOffset").and_then(|o| o.as_i64()).unwrap_or(0);
+    let count = count_argument(arguments, "instructionCount")?.min(MAX_INSTRUCTIONS);
+
+    let instructions = vm.instructions();
+    let first = (instructions.iter()
+            .position(|&(address, _)| address as i64 >= start)
+            .unwrap_or(instructions.len()) as i64)
+        .checked_add(instruction_offset)
+        .and_then(|first| first.checked_add(count).map(|_| first))
+        .ok_or_else(|| format!("Instruction offset {} is out of range", instruction_offset))?;
+
+    // The client expects exactly as many instructions as it asked for, so
+    // pad with placeholders outside the loaded code
+    let result = (first..first + count)
+        .map(|index| {
+            if index < 0 || index >= instructions.len() as i64 {
+                return object(vec![("address", reference(0).to_json()),
+                                   ("instruction", "??".to_json()),
+                                   ("presentationHint", "invalid".to_json())]);
+            }
+
+            let (address, ref line) = instructions[index as usize];
+            // Lines look like "C000 20 22 C0 JSR $C022"
+            let text = line.splitn(2, ' ').nth(1).unwrap_or("").trim().to_owned();
+            let mut instruction = vec![("address", reference(address as usize).to_json()),
+                                       ("instruction", text.to_json())];
+            if let Some(symbol) = vm.symbols().label_before(address) {
+                instruction.push(("symbol", symbol.to_json()));
+            }
+            if let (Some(path), Some(line)) = (vm.symbols().source(), vm.symbols().line_of_address(address)) {
+                instruction.push(("location", source(path)));
+                instruction.push(("line", (line as u64).to_json()));
+            }
+            object(instruction)
+        })
+        .collect();
+
+    Ok(object(vec![("instructions", Json::Array(result))]))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use rustc_serialize::base64::FromBase64;
+    use rustc_serialize::json::Json;
+
+    use symbols::SymbolTable;
+
+    use super::*;
+
+    fn packet(body: &str) -> Vec<u8> {
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body).into_bytes()
+    }
+
+    fn command(message: &Json) -> &str {
+        message.find("command").and_then(|command| command.as_string()).unwrap()
+    }
+
+    #[test]
+    fn takes_messages_out_of_the_buffer() {
+        let mut buffer = packet(r#"{"command":"threads"}"#);
+        buffer.extend(packet(r#"{"command":"pause"}"#));
+
+        assert_eq!(command(&next_message(&mut buffer).unwrap()), "threads");
+        assert_eq!(command(&next_message(&mut buffer).unwrap()), "pause");
+        assert!(next_message(&mut buffer).is_none());
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn waits_for_the_rest_of_a_message() {
+        let message = packet(r#"{"command":"threads"}"#);
+        let mut buffer = message[..10].to_vec();
+        assert!(next_message(&mut buffer).is_none());
+
+        buffer.extend_from_slice(&message[10..message.len() - 1]);
+        assert!(next_message(&mut buffer).is_none());
+
+        buffer.push(message[message.len() - 1]);
+        assert_eq!(command(&next_message(&mut buffer).unwrap()), "threads");
+    }
+
+    #[test]
+    fn skips_messages_without_a_length() {
+        let mut buffer = b"Content-Type: application/json\r\n\r\n".to_vec();
+        buffer.extend(b"content-length: 21\r\n\r\n{\"command\":\"threads\"}".iter());
+        assert_eq!(command(&next_message(&mut buffer).unwrap()), "threads");
+    }
+
+    #[test]
+    fn reads_memory_up_to_the_end_of_the_address_space() {
+        let mut memory = vec![0; 0x10000];
+        memory[0xFFFE] = 0x12;
+        memory[0xFFFF] = 0x34;
+
+        let arguments = Json::from_str(r#"{"memoryReference":"0xFFF0","offset":14,"count":4}"#).unwrap();
+        let response = read_memory(&arguments, &memory).unwrap();
+        assert_eq!(response.find("address").unwrap().as_string(), Some("0xFFFE"));
+        let data = response.find("data").unwrap().as_string().unwrap().from_base64().unwrap();
+        assert_eq!(data, vec![0x12, 0x34]);
+        assert_eq!(response.find("unreadableBytes").unwrap().as_u64(), Some(2));
+
+        let arguments = Json::from_str(r#"{"memoryReference":"0x0000","count":-1}"#).unwrap();
+        assert_eq!(read_memory(&arguments, &memory).unwrap_err(), "'count' can't be negative");
+        let arguments = Json::from_str(r#"{"memoryReference":"nowhere","count":1}"#).unwrap();
+        assert_eq!(read_memory(&arguments, &memory).unwrap_err(),
+                   "Invalid memory reference 'nowhere'");
+    }
+
+    #[test]
+    fn sets_breakpoints_at_the_code_of_a_line() {
+        let path = env::temp_dir().join(format!("hakka-dap-{}.asm", ::std::process::id()));
+        fs::write(&path, "START:\n  LDA #$01\n\n  ; Waits\n  JMP START\n").unwrap();
+        let symbols = SymbolTable::from_file(&path, 0xC000).unwrap();
+
+        let request = format!(r#"{{"source":{{"path":{}}},"breakpoints":[{{"line":2}},{{"line":3}},{{"line":9}}]}}"#,
+                              Json::String(path.to_string_lossy().into_owned()));
+        let (response, addresses) = breakpoints(&Json::from_str(&request).unwrap(), &symbols);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(addresses, vec![0xC000, 0xC002]);
+        let results = response.find("breakpoints").unwrap().as_array().unwrap();
+        let lines = results.iter().map(|b| b.find("line").unwrap().as_u64().unwrap()).collect::<Vec<_>>();
+        assert_eq!(lines, vec![2, 5, 9]);
+        assert_eq!(results[1].find("instructionReference").unwrap().as_string(), Some("0xC002"));
+        assert_eq!(results[2].find("verified").unwrap().as_boolean(), Some(false));
+
+        let request = r#"{"source":{"path":"other.asm"},"breakpoints":[{"line":2}]}"#;
+        let (_, addresses) = breakpoints(&Json::from_str(request).unwrap(), &symbols);
+        assert!(addresses.is_empty());
+    }
+}
@@ -0,0 +1,229 @@
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Tab stops used to line up the column marker under the source line
+const TAB_WIDTH: usize = 4;
+
+/// An assembly error, pointing at the place in the source it was found at
+/// when the assembler says where that is:
+///
+/// ```text
+/// level.asm:12:5: Unexpected token
+///    12 | LDA %00
+///       |     ^
+/// ```
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub file: PathBuf,
+    /// The line the error is on, counting from 1
+    pub line: Option<usize>,
+    /// The column the error is at, counting from 1
+    pub column: Option<usize>,
+    pub message: String,
+    /// The offending line of source, without its line ending
+    pub source_line: Option<String>,
+}
+
+impl Diagnostic {
+    /// Describes an error from assembling the given file.
+    ///
+    /// The assembler doesn't export its error type, and only describes its
+    /// errors through `Debug` as text ending in the line and column they were
+    /// found at, such as "Unexpected token. Line 12 col 5". Errors about
+    /// labels don't say where they are, so the first line using the label is
+    /// looked up instead.
+    pub fn from_assembler_error<P, E>(file: P, error: &E) -> Diagnostic
+        where P: AsRef<Path>,
+              E: fmt::Debug
+    {
+        let file = file.as_ref();
+        let source = read_source(file);
+        let (message, mut line, mut column) = split_location(&assembler_message(error));
+
+        if line.is_none() {
+            if let (Some(label), Some(ref source)) = (label_of(&message), source.as_ref()) {
+                if let Some((label_line, label_column)) = find_label_use(source, &label) {
+                    line = Some(label_line);
+                    column = Some(label_column);
+                }
+            }
+        }
+
+        let source_line = match (line, source) {
+            (Some(line), Some(source)) if line > 0 => source.lines().nth(line - 1).map(|text| text.to_owned()),
+            _ => None,
+        };
+
+        Diagnostic {
+            file: file.to_path_buf(),
+            line: line,
+            column: column,
+            message: message,
+            source_line: source_line,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+            if let Some(column) = self.column {
+                write!(f, ":{}", column)?;
+            }
+        }
+        write!(f, ": {}", self.message)?;
+
+        if let (Some(line), Some(ref source_line)) = (self.line, self.source_line.as_ref()) {
+            let gutter = line.to_string().len() + 2;
+            write!(f, "\n{:>width$} | {}", line, expand_tabs(source_line), width = gutter)?;
+            if let Some(column) = self.column {
+                let prefix = source_line.chars().take(column.saturating_sub(1)).collect::<String>();
+                let offset = expand_tabs(&prefix).len();
+                write!(f, "\n{:>width$} | {:>offset$}", "", "^", width = gutter, offset = offset + 1)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn read_source(file: &Path) -> Option<String> {
+    let mut source = String::new();
+    File::open(file).and_then(|mut file| file.read_to_string(&mut source)).ok().map(|_| source)
+}
+
+/// The message inside an assembler error, which only implements `Debug`
+fn assembler_message<E>(error: &E) -> String
+    where E: fmt::Debug
+{
+    let debug = format!("{:?}", error);
+    let start = match debug.find("message: \"") {
+        Some(index) => index + "message: \"".len(),
+        None => return debug,
+    };
+    let end = debug.rfind('"').unwrap_or(debug.len());
+    if end <= start {
+        return debug;
+    }
+
+    debug[start..end].replace("\\\"", "\"").replace("\\\\", "\\")
+}
+
+/// Splits the location off the end of an assembler message, which comes
+/// as ". Line 12, col 5", ". Line 12 col 5" or just ". Line 12"
+fn split_location(message: &str) -> (String, Option<usize>, Option<usize>) {
+    let index = match message.rfind(". Line ") {
+        Some(index) => index,
+        None => return (message.to_owned(), None, None),
+    };
+
+    let location = message[index + ". Line ".len()..].replace(',', "");
+    let mut parts = location.split_whitespace();
+    let line = parts.next().and_then(|line| line.parse().ok());
+    let column = match (parts.next(), parts.next()) {
+        (Some("col"), Some(column)) => column.parse().ok(),
+        _ => None,
+    };
+
+    match line {
+        Some(line) => (message[..index].to_owned(), Some(line), column),
+        None => (message.to_owned(), None, None),
+    }
+}
+
+/// The label an "Unknown label: 'X'" or "Branch too far: Attempted jump to
+/// X at C000" error is about
+fn label_of(message: &str) -> Option<String> {
+    if message.starts_with("Unknown label: '") {
+        return message.split('\'').nth(1).map(|label| label.to_owned());
+    }
+    if let Some(index) = message.find("Attempted jump to ") {
+        return message[index + "Attempted jump to ".len()..]
+            .split_whitespace()
+            .next()
+            .map(|label| label.to_owned());
+    }
+    None
+}
+
+/// The line and column of the first use of a label as an operand, skipping
+/// comments and the label's own definition
+fn find_label_use(source: &str, label: &str) -> Option<(usize, usize)> {
+    if label.is_empty() {
+        return None;
+    }
+    for (number, line) in source.lines().enumerate() {
+        let code = line.split(';').next().unwrap_or("");
+        let mut search = 0;
+        while let Some(found) = code[search..].find(label) {
+            let start = search + found;
+            let end = start + label.len();
+            search = end;
+
+            let is_word = |c: char| c.is_alphanumeric() || c == '_';
+            let before = code[..start].chars().last();
+            let after = code[end..].chars().next();
+            if before.map_or(false, &is_word) || after.map_or(false, &is_word) {
+                continue;
+            }
+            // A label is defined at the start of a line, and used after the
+            // instruction
+            if code[..start].trim().is_empty() {
+                continue;
+            }
+            return Some((number + 1, code[..start].chars().count() + 1));
+        }
+    }
+    None
+}
+
+fn expand_tabs(text: &str) -> String {
+    text.replace('\t', &" ".repeat(TAB_WIDTH))
+}
+
+#[cfg(test)]
+mod tests {
+    use rs6502::Assembler;
+
+    use super::*;
+
+    fn assembler_error(code: &str) -> String {
+        match Assembler::new().assemble_string(code, 0xC000) {
+            Ok(_) => panic!("'{}' assembled", code),
+            Err(err) => assembler_message(&err),
+        }
+    }
+
+    #[test]
+    fn reads_the_message_out_of_assembler_errors() {
+        assert_eq!(assembler_error("JMP MISSING"), "Unknown label: 'MISSING'");
+
+        let message = assembler_error("LDA #$00\nLDA %00");
+        assert_eq!(split_location(&message), ("Unexpected token".to_owned(), Some(2), Some(5)));
+    }
+
+    #[test]
+    fn splits_locations() {
+        assert_eq!(split_location("Unexpected token. Line 12, col 5"),
+                   ("Unexpected token".to_owned(), Some(12), Some(5)));
+        assert_eq!(split_location("Unexpected token. Line 12 col 5"),
+                   ("Unexpected token".to_owned(), Some(12), Some(5)));
+        assert_eq!(split_location("Instruction expected. Line 3"),
+                   ("Instruction expected".to_owned(), Some(3), None));
+        assert_eq!(split_location("Unknown label: 'X'"), ("Unknown label: 'X'".to_owned(), None, None));
+    }
+
+    #[test]
+    fn finds_label_uses() {
+        let source = "MAIN:\n    JMP MAIN_LOOP ; MAIN\n    JMP MAIN\n";
+        assert_eq!(label_of("Unknown label: 'MAIN'"), Some("MAIN".to_owned()));
+        assert_eq!(find_label_use(source, "MAIN"), Some((3, 9)));
+        assert_eq!(find_label_use(source, "OTHER"), None);
+
+        assert_eq!(label_of("Unknown label: ''"), Some("".to_owned()));
+        assert_eq!(find_label_use(source, ""), None);
+    }
+}
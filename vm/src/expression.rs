@@ -0,0 +1,485 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use vm::VirtualMachine;
+
+/// Evaluates an expression against the current state of the virtual machine.
+///
+/// Numbers are hexadecimal by default and may be prefixed with `$` or `0x`
+/// (hexadecimal), `%` (binary) or `#` (decimal). Register names (`A`, `X`,
+/// `Y`, `S`, `PC`) and labels from the loaded symbol table evaluate to their
+/// values, `[addr]` reads a byte from memory and `w[addr]` reads a little
+/// endian word. The usual arithmetic and bitwise operators are supported, as
+/// are `<` and `>` to take the low or high byte of a value. Comparisons
+/// (`==`, `!=`, `<`, `<=`, `>`, `>=`) and the logical `&&`, `||` and `!`
+/// evaluate to 1 or 0, so expressions can also be used as conditions.
+///
+/// Registers and labels take precedence over bare hexadecimal numbers, so
+/// `A` is the accumulator and `C` a label named C if there is one. Numbers
+/// spelled like a register or label need a `$` or `0x` prefix, as in `$A`.
+pub fn evaluate(input: &str, vm: &VirtualMachine) -> Result<i64, ExpressionError> {
+    evaluate_in(input, vm)
+}
+
+/// What expressions read memory, registers and labels from
+pub trait Machine {
+    fn memory(&self) -> &[u8];
+    fn register(&self, name: &str) -> Option<u16>;
+    fn symbol(&self, name: &str) -> Option<u16>;
+}
+
+impl<'a> Machine for VirtualMachine<'a> {
+    fn memory(&self) -> &[u8] {
+        self.cpu.memory()
+    }
+
+    fn register(&self, name: &str) -> Option<u16> {
+        self.cpu.register(name)
+    }
+
+    fn symbol(&self, name: &str) -> Option<u16> {
+        VirtualMachine::symbol(self, name)
+    }
+}
+
+/// Evaluates an expression against anything expressions can read from, as
+/// `evaluate` does against the virtual machine
+pub fn evaluate_in<M>(input: &str, machine: &M) -> Result<i64, ExpressionError>
+    where M: Machine
+{
+    let mut evaluator = Evaluator {
+        chars: input.chars().peekable(),
+        machine: machine,
+    };
+
+    let value = evaluator.expression()?;
+    evaluator.skip_whitespace();
+    if let Some(c) = evaluator.chars.next() {
+        return Err(ExpressionError::UnexpectedCharacter(c));
+    }
+
+    Ok(value)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ExpressionError {
+    UnexpectedEnd,
+    UnexpectedCharacter(char),
+    InvalidNumber(String),
+    UnknownSymbol(String),
+    AddressOutOfRange(i64),
+    DivisionByZero,
+}
+
+impl fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExpressionError::UnexpectedEnd => write!(f, "Unexpected end of expression"),
+            ExpressionError::UnexpectedCharacter(c) => write!(f, "Unexpected character '{}'", c),
+            ExpressionError::InvalidNumber(ref number) => write!(f, "Invalid number '{}'", number),
+            ExpressionError::UnknownSymbol(ref symbol) => write!(f, "Unknown symbol '{}'", symbol),
+            ExpressionError::AddressOutOfRange(address) => {
+                write!(f, "Address {:X} outside addressable range", address)
+            }
+            ExpressionError::DivisionByZero => write!(f, "Division by zero"),
+        }
+    }
+}
+
+struct Evaluator<'a, M: 'a> {
+    chars: Peekable<Chars<'a>>,
+    machine: &'a M,
+}
+
+impl<'a, M> Evaluator<'a, M>
+    where M: Machine
+{
+    // Each level handles the operators of a single precedence, from loosest
+    // to tightest binding
+
+    fn expression(&mut self) -> Result<i64, ExpressionError> {
+        let mut value = self.logical_and()?;
+        while self.consume_pair('|', '|') {
+            let right = self.logical_and()?;
+            value = (value != 0 || right != 0) as i64;
+        }
+        Ok(value)
+    }
+
+    fn logical_and(&mut self) -> Result<i64, ExpressionError> {
+        let mut value = self.comparison()?;
+        while self.consume_pair('&', '&') {
+            let right = self.comparison()?;
+            value = (value != 0 && right != 0) as i64;
+        }
+        Ok(value)
+    }
+
+    fn comparison(&mut self) -> Result<i64, ExpressionError> {
+        let mut value = self.or()?;
+        loop {
+            // Shifts start with the same characters, so leave those alone
+            if self.consume_pair('=', '=') {
+                value = (value == self.or()?) as i64;
+            } else if self.consume_pair('!', '=') {
+                value = (value != self.or()?) as i64;
+            } else if self.consume_pair('<', '=') {
+                value = (value <= self.or()?) as i64;
+            } else if self.consume_pair('>', '=') {
+                value = (value >= self.or()?) as i64;
+            } else if self.consume_single('<') {
+                value = (value < self.or()?) as i64;
+            } else if self.consume_single('>') {
+                value = (value > self.or()?) as i64;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn or(&mut self) -> Result<i64, ExpressionError> {
+        let mut value = self.xor()?;
+        while self.consume_single('|') {
+            value |= self.xor()?;
+        }
+        Ok(value)
+    }
+
+    fn xor(&mut self) -> Result<i64, ExpressionError> {
+        let mut value = self.and()?;
+        while self.consume('^') {
+            value ^= self.and()?;
+        }
+        Ok(value)
+    }
+
+    fn and(&mut self) -> Result<i64, ExpressionError> {
+        let mut value = self.shift()?;
+        while self.consume_single('&') {
+            value &= self.shift()?;
+        }
+        Ok(value)
+    }
+
+    fn shift(&mut self) -> Result<i64, ExpressionError> {
+        let mut value = self.sum()?;
+        loop {
+            if self.consume_pair('<', '<') {
+                value = value.wrapping_shl(self.sum()? as u32);
+            } else if self.consume_pair('>', '>') {
+                value = value.wrapping_shr(self.sum()? as u32);
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn sum(&mut self) -> Result<i64, ExpressionError> {
+        let mut value = self.product()?;
+        loop {
+            if self.consume('+') {
+                value = value.wrapping_add(self.product()?);
+            } else if self.consume('-') {
+                value = value.wrapping_sub(self.product()?);
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn product(&mut self) -> Result<i64, ExpressionError> {
+        let mut value = self.unary()?;
+        loop {
+            if self.consume('*') {
+                value = value.wrapping_mul(self.unary()?);
+            } else if self.consume('/') {
+                let divisor = self.unary()?;
+                if divisor == 0 {
+                    return Err(ExpressionError::DivisionByZero);
+                }
+                value = value.wrapping_div(divisor);
+            } else if self.consume('%') {
+                let divisor = self.unary()?;
+                if divisor == 0 {
+                    return Err(ExpressionError::DivisionByZero);
+                }
+                value = value.wrapping_rem(divisor);
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<i64, ExpressionError> {
+        if self.consume('-') {
+            Ok(self.unary()?.wrapping_neg())
+        } else if self.consume('~') {
+            Ok(!self.unary()?)
+        } else if self.consume('!') {
+            Ok((self.unary()? == 0) as i64)
+        } else if self.consume('<') {
+            Ok(self.unary()? & 0xFF)
+        } else if self.consume('>') {
+            Ok((self.unary()? >> 8) & 0xFF)
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<i64, ExpressionError> {
+        self.skip_whitespace();
+        let c = match self.chars.peek() {
+            Some(c) => *c,
+            None => return Err(ExpressionError::UnexpectedEnd),
+        };
+
+        match c {
+            '(' => {
+                self.chars.next();
+                let value = self.expression()?;
+                self.expect(')')?;
+                Ok(value)
+            }
+            '[' => {
+                self.chars.next();
+                let address = self.address()?;
+                self.expect(']')?;
+                Ok(self.machine.memory()[address] as i64)
+            }
+            '$' => {
+                self.chars.next();
+                self.number(16)
+            }
+            '%' => {
+                self.chars.next();
+                self.number(2)
+            }
+            '#' => {
+                self.chars.next();
+                if self.chars.peek() == Some(&'$') {
+                    self.chars.next();
+                    self.number(16)
+                } else {
+                    self.number(10)
+                }
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let word = self.word();
+
+                if word == "w" && self.chars.peek() == Some(&'[') {
+                    self.chars.next();
+                    let address = self.address()?;
+                    self.expect(']')?;
+                    let low = self.machine.memory()[address] as i64;
+                    let high = self.machine.memory()[(address + 1) % self.machine.memory().len()] as i64;
+                    return Ok(low | high << 8);
+                }
+
+                if let Some(value) = self.register(&word) {
+                    return Ok(value);
+                }
+
+                if let Some(address) = self.machine.symbol(&word) {
+                    return Ok(address as i64);
+                }
+
+                if word.starts_with("0x") || word.starts_with("0X") {
+                    return parse_number(&word[2..], 16);
+                }
+
+                parse_number(&word, 16).map_err(|_| ExpressionError::UnknownSymbol(word))
+            }
+            c => Err(ExpressionError::UnexpectedCharacter(c)),
+        }
+    }
+
+    fn address(&mut self) -> Result<usize, ExpressionError> {
+        let address = self.expression()?;
+        if address < 0 || address as usize >= self.machine.memory().len() {
+            return Err(ExpressionError::AddressOutOfRange(address));
+        }
+        Ok(address as usize)
+    }
+
+    fn register(&self, name: &str) -> Option<i64> {
+        let name = match &name.to_uppercase()[..] {
+            "SP" => "S".to_owned(),
+            name => name.to_owned(),
+        };
+        self.machine.register(&name).map(|value| value as i64)
+    }
+
+    fn number(&mut self, radix: u32) -> Result<i64, ExpressionError> {
+        let word = self.word();
+        parse_number(&word, radix)
+    }
+
+    fn word(&mut self) -> String {
+        let mut word = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if !c.is_alphanumeric() && c != '_' {
+                break;
+            }
+            word.push(c);
+            self.chars.next();
+        }
+        word
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().map_or(false, |c| c.is_whitespace()) {
+            self.chars.next();
+        }
+    }
+
+    fn consume(&mut self, expected: char) -> bool {
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&expected) {
+            self.chars.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Consumes `expected` unless it is the start of a two character
+    /// operator such as `&&`, `<<` or `<=`
+    fn consume_single(&mut self, expected: char) -> bool {
+        self.skip_whitespace();
+        let mut lookahead = self.chars.clone();
+        if lookahead.next() != Some(expected) {
+            return false;
+        }
+        match lookahead.peek() {
+            Some(&next) if next == expected || next == '=' => false,
+            _ => {
+                self.chars.next();
+                true
+            }
+        }
+    }
+
+    fn consume_pair(&mut self, first: char, second: char) -> bool {
+        self.skip_whitespace();
+        let mut lookahead = self.chars.clone();
+        if lookahead.next() == Some(first) && lookahead.next() == Some(second) {
+            self.chars = lookahead;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ExpressionError> {
+        if self.consume(expected) {
+            return Ok(());
+        }
+        match self.chars.next() {
+            Some(c) => Err(ExpressionError::UnexpectedCharacter(c)),
+            None => Err(ExpressionError::UnexpectedEnd),
+        }
+    }
+}
+
+fn parse_number(digits: &str, radix: u32) -> Result<i64, ExpressionError> {
+    i64::from_str_radix(digits, radix).map_err(|_| ExpressionError::InvalidNumber(digits.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{evaluate_in, ExpressionError, Machine};
+
+    struct TestMachine {
+        memory: Vec<u8>,
+    }
+
+    impl Machine for TestMachine {
+        fn memory(&self) -> &[u8] {
+            &self.memory
+        }
+
+        fn register(&self, name: &str) -> Option<u16> {
+            match name {
+                "A" => Some(0x12),
+                "X" => Some(0x34),
+                "PC" => Some(0xC000),
+                _ => None,
+            }
+        }
+
+        fn symbol(&self, name: &str) -> Option<u16> {
+            match name {
+                "Y_0" => Some(0x0010),
+                "C" => Some(0x0300),
+                _ => None,
+            }
+        }
+    }
+
+    fn evaluate(input: &str) -> Result<i64, ExpressionError> {
+        let mut memory = vec![0; 0x10000];
+        memory[0x10] = 0xFF;
+        memory[0x11] = 0x01;
+        memory[0xFFFF] = 0x34;
+        memory[0x0000] = 0x12;
+        evaluate_in(input, &TestMachine { memory: memory })
+    }
+
+    #[test]
+    fn numbers_are_hexadecimal_unless_prefixed() {
+        assert_eq!(evaluate("10"), Ok(0x10));
+        assert_eq!(evaluate("$10"), Ok(0x10));
+        assert_eq!(evaluate("0x10"), Ok(0x10));
+        assert_eq!(evaluate("%101"), Ok(5));
+        assert_eq!(evaluate("#10"), Ok(10));
+        assert_eq!(evaluate("#$10"), Ok(0x10));
+    }
+
+    #[test]
+    fn registers_and_labels_take_precedence_over_bare_hexadecimal() {
+        assert_eq!(evaluate("A"), Ok(0x12));
+        assert_eq!(evaluate("a"), Ok(0x12));
+        assert_eq!(evaluate("$A"), Ok(0x0A));
+        assert_eq!(evaluate("C"), Ok(0x0300));
+        assert_eq!(evaluate("0xC"), Ok(0x0C));
+        assert_eq!(evaluate("B"), Ok(0x0B));
+    }
+
+    #[test]
+    fn operators_bind_by_precedence() {
+        assert_eq!(evaluate("2 + 3 * 4"), Ok(0x0E));
+        assert_eq!(evaluate("(2 + 3) * 4"), Ok(0x14));
+        assert_eq!(evaluate("1 + 1 << 2"), Ok(8));
+        assert_eq!(evaluate("6 & 3 | 8"), Ok(0x0A));
+        assert_eq!(evaluate("1 | 2 ^ 3"), Ok(1));
+        assert_eq!(evaluate("1 + 1 == 2"), Ok(1));
+        assert_eq!(evaluate("1 < 2 && 3 > 4 || 1"), Ok(1));
+        assert_eq!(evaluate("!0 + 1"), Ok(2));
+        assert_eq!(evaluate("-1 + 2"), Ok(1));
+        assert_eq!(evaluate(">$1234 + <$1234"), Ok(0x46));
+        assert_eq!(evaluate("7 % 4 / 2"), Ok(1));
+    }
+
+    #[test]
+    fn memory_reads_bytes_and_little_endian_words() {
+        assert_eq!(evaluate("[Y_0]"), Ok(0xFF));
+        assert_eq!(evaluate("w[Y_0]"), Ok(0x01FF));
+        assert_eq!(evaluate("w[Y_0] - #10"), Ok(0x01F5));
+        assert_eq!(evaluate("w[$FFFF]"), Ok(0x1234));
+    }
+
+    #[test]
+    fn errors_are_reported() {
+        assert_eq!(evaluate(""), Err(ExpressionError::UnexpectedEnd));
+        assert_eq!(evaluate("1 +"), Err(ExpressionError::UnexpectedEnd));
+        assert_eq!(evaluate("(1"), Err(ExpressionError::UnexpectedEnd));
+        assert_eq!(evaluate("1)"), Err(ExpressionError::UnexpectedCharacter(')')));
+        assert_eq!(evaluate("ZZ"), Err(ExpressionError::UnknownSymbol("ZZ".into())));
+        assert_eq!(evaluate("%2"), Err(ExpressionError::InvalidNumber("2".into())));
+        assert_eq!(evaluate("[10000]"), Err(ExpressionError::AddressOutOfRange(0x10000)));
+        assert_eq!(evaluate("[-1]"), Err(ExpressionError::AddressOutOfRange(-1)));
+        assert_eq!(evaluate("1 / 0"), Err(ExpressionError::DivisionByZero));
+        assert_eq!(evaluate("1 % 0"), Err(ExpressionError::DivisionByZero));
+    }
+}
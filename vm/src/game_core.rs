@@ -1,8 +1,13 @@
 
+use std::io;
 use std::io::Write;
+use std::path::Path;
+use std::time::Instant;
 
 use vm::VirtualMachine;
 use command::{CommandSystem, UnblockEvent, CommandResult};
+use recording::{Input, Recorder, Recording, Replay};
+use statistics::Statistics;
 
 use sdl2::render::Renderer;
 use sdl2::ttf::Sdl2TtfContext;
@@ -11,25 +16,110 @@ use sdl2::keyboard::{Keycode, LCTRLMOD, RCTRLMOD};
 
 use rs6502::Cpu;
 
+use app_dirs::{app_root, AppDataType};
+
+use config::{APP_INFO, STARTUP_SCRIPT};
+
 pub struct GameCore<'a> {
     pub vm: VirtualMachine<'a>,
     pub command_system: CommandSystem,
     unblock_event: Option<UnblockEvent>,
+    recorder: Option<Recorder>,
+    replay: Option<Replay>,
+    statistics: Statistics,
+    started: Instant,
 }
 
 impl<'a> GameCore<'a> {
     pub fn new(ttf_context: &'a Sdl2TtfContext,
                mut renderer: &mut Renderer,
                font_file: &'a str)
-               -> GameCore<'a>
+               -> Result<GameCore<'a>, String>
    {
         let cpu = Cpu::new();
-        let vm = VirtualMachine::new(cpu, 150, &ttf_context, &mut renderer, font_file);
+        let vm = VirtualMachine::new(cpu, 150, &ttf_context, &mut renderer, font_file)?;
+
+        Ok(GameCore::with_vm(vm))
+    }
 
+    fn with_vm(vm: VirtualMachine<'a>) -> GameCore<'a> {
         GameCore {
             vm: vm,
             command_system: CommandSystem::new(),
             unblock_event: None,
+            recorder: None,
+            replay: None,
+            statistics: Statistics::default(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Runs the console commands in the startup script kept in the
+    /// configuration directory, if there is one. Call this once the level
+    /// code and symbols are loaded, so the commands can refer to them
+    pub fn run_startup_script(&mut self) {
+        let script = match app_root(AppDataType::UserConfig, &APP_INFO) {
+            Ok(root) => root.join(STARTUP_SCRIPT),
+            Err(_) => return,
+        };
+        if !script.exists() {
+            return;
+        }
+
+        if let Err(err) = self.command_system.execute_file(&script, &mut self.vm) {
+            writeln!(self.vm.console, "Unable to execute {}: {}", STARTUP_SCRIPT, err).unwrap();
+        }
+    }
+
+    fn record(&mut self, input: Input) {
+        let failed = match self.recorder {
+            Some(ref mut recorder) => recorder.record(self.vm.cycle_count(), &input).is_err(),
+            None => false,
+        };
+        if failed {
+            writeln!(self.vm.console, "Unable to write the recording, recording stopped").unwrap();
+            self.recorder = None;
+        }
+    }
+
+    fn execute_command(&mut self, cmd: String) {
+        self.statistics.commands += 1;
+        let (result, unblock_event) = self.command_system.execute(cmd, &mut self.vm);
+
+        if let CommandResult::NotFound = result {
+            writeln!(self.vm.console, "Command not recognized, type 'help' for a list of commands").unwrap();
+        }
+
+        if unblock_event.is_some() {
+            self.unblock_event = unblock_event;
+            self.vm.console.input_blocked = true;
+        } else {
+            self.unblock_event = None;
+        }
+    }
+
+    /// Runs part of an update that may hack the VM, counting the bytes of
+    /// memory it changes and the breakpoints it sets
+    fn hack<F>(&mut self, f: F)
+        where F: FnOnce(&mut GameCore<'a>)
+    {
+        let bytes_written = self.vm.bytes_written();
+        let breakpoints = self.vm.breakpoints_set();
+
+        f(self);
+
+        self.statistics.bytes_written += self.vm.bytes_written() - bytes_written;
+        self.statistics.breakpoints += self.vm.breakpoints_set() - breakpoints;
+    }
+
+    /// How the level has been played since it started
+    pub fn statistics(&self) -> Statistics {
+        let elapsed = self.started.elapsed();
+        Statistics {
+            hints: self.vm.revealed_hints().len() as u32,
+            cycles: self.vm.cpu_cycles(),
+            milliseconds: elapsed.as_secs() * 1000 + elapsed.subsec_nanos() as u64 / 1_000_000,
+            ..self.statistics.clone()
         }
     }
 
@@ -50,23 +140,135 @@ impl<'a> GameCore<'a> {
         }
     }
 
+    /// Writes a byte the game loop feeds into the memory of the first CPU,
+    /// such as a key code.
+    /// Writes that change memory are recorded when recording, and all of
+    /// them are ignored while a replay provides the inputs instead
+    pub fn write_input(&mut self, address: usize, value: u8) {
+        if self.replay.is_some() {
+            return;
+        }
+        let changed = self.vm.with_cpu(0, |vm| {
+            let changed = vm.cpu.memory()[address] != value;
+            vm.cpu.memory_mut()[address] = value;
+            changed
+        });
+        if changed {
+            self.record(Input::Memory(address, value));
+        }
+    }
+
+    /// Starts recording every input and console command to a file
+    pub fn start_recording<P>(&mut self, path: P) -> io::Result<()>
+        where P: AsRef<Path>
+    {
+        self.recorder = Some(Recorder::create(path)?);
+        Ok(())
+    }
+
+    /// Feeds the recorded inputs into the VM at the cycles they were
+    /// recorded at, instead of the live ones
+    pub fn start_replay(&mut self, recording: Recording) {
+        writeln!(self.vm.console, "Replaying {} recorded inputs", recording.len()).unwrap();
+        self.replay = Some(Replay::new(recording));
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        self.replay.is_some()
+    }
+
     pub fn update(&mut self) {
-        if let Some(cmd) = self.vm.console.get_next_command() {
-            let (result, unblock_event) = self.command_system.execute(cmd, &mut self.vm);
+        self.hack(|core| {
+            if let Some(cmd) = core.vm.console.get_next_command() {
+                core.record(Input::Command(cmd.clone()));
+                core.execute_command(cmd);
+            }
+        });
 
-            if let CommandResult::NotFound = result {
-                writeln!(self.vm.console, "Command not recognized, type 'help' for a list of commands").unwrap();
+        let inputs = match self.replay {
+            Some(ref mut replay) => replay.inputs_until(self.vm.cycle_count()),
+            None => Vec::new(),
+        };
+        for input in inputs {
+            match input {
+                Input::Memory(address, value) => self.vm.with_cpu(0, |vm| vm.cpu.memory_mut()[address] = value),
+                Input::Command(cmd) => {
+                    writeln!(self.vm.console, "replay> {}", cmd).unwrap();
+                    self.hack(|core| core.execute_command(cmd));
+                }
             }
+        }
+        if self.replay.as_ref().map_or(false, |replay| replay.is_finished()) {
+            writeln!(self.vm.console, "Replay finished").unwrap();
+            self.replay = None;
+        }
 
-            if unblock_event.is_some() {
-                self.unblock_event = unblock_event;
-                self.vm.console.input_blocked = true;
-            } else {
-                self.unblock_event = None;
+        self.hack(|core| {
+            // The servers need the VM they are stored in, so take each out while it
+            // handles its client
+            if let Some(mut server) = core.vm.gdb_server.take() {
+                server.poll(&mut core.vm);
+                core.vm.gdb_server = Some(server);
+            }
+            if let Some(mut server) = core.vm.dap_server.take() {
+                server.poll(&mut core.vm);
+                core.vm.dap_server = Some(server);
             }
+
+            if let Some(mut scripts) = core.vm.scripts.take() {
+                scripts.run_frame(&mut core.vm);
+                core.vm.scripts = Some(scripts);
+            }
+        });
+
+        if let Some(mut watcher) = self.vm.watcher.take() {
+            watcher.poll(&mut self.vm);
+            self.vm.watcher = Some(watcher);
         }
 
+        let console_visible = self.vm.console.visible;
         self.vm.cycle();
+
+        if let Some(address) = self.vm.take_breakpoint_hit() {
+            self.hack(|core| {
+                if let Some(mut scripts) = core.vm.scripts.take() {
+                    scripts.run_breakpoint(&mut core.vm, address);
+                    core.vm.scripts = Some(scripts);
+                }
+            });
+            // A script resumed execution straight away, so put away the
+            // console the breakpoint opened
+            if !self.vm.is_debugging() && self.vm.console.visible && !console_visible {
+                self.vm.console.toggle(0);
+            }
+        }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use processor::tests::StubProcessor;
+
+    use super::*;
+
+    #[test]
+    fn counts_the_hacks_of_every_command_once() {
+        let path = env::temp_dir().join(format!("hakka-hacks-{}.txt", ::std::process::id()));
+        fs::write(&path, "0 cmd memset 0200 01 02\n0 cmd break 0600\n1 cmd memset 0200 01 03\n").unwrap();
+        let recording = Recording::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let mut core = GameCore::with_vm(VirtualMachine::headless(StubProcessor::new(), 2).unwrap());
+        core.start_replay(recording);
+        core.update();
+        core.update();
+
+        let statistics = core.statistics();
+        assert_eq!(3, statistics.commands);
+        assert_eq!(3, statistics.bytes_written);
+        assert_eq!(1, statistics.breakpoints);
+    }
+}
@@ -0,0 +1,165 @@
+use sdl2::event::Event;
+use sdl2::render::Renderer;
+use sdl2::{EventPump, TimerSubsystem};
+
+/// How many updates may run back to back to catch up after a slow frame,
+/// before the loop gives up on catching up and drops the time instead
+const MAX_CATCH_UP_UPDATES: u32 = 5;
+
+/// A game driven by `GameLoop`
+pub trait Game {
+    /// Handles an input event. Returns false to stop running the game
+    fn process_event(&mut self, event: &Event) -> bool;
+
+    /// Advances the game by exactly one fixed step: level logic and a
+    /// single cycle of the virtual machine
+    fn update(&mut self);
+
+    /// Draws the current state. This may run more or less often than
+    /// `update`, so it must not change the game state
+    fn render(&mut self, renderer: &mut Renderer);
+}
+
+/// Runs a game with a fixed timestep. The game is updated a fixed number of
+/// times per second no matter how fast the machine renders, so every update
+/// does the same amount of work and the game behaves the same everywhere.
+pub struct GameLoop {
+    updates_per_second: u32,
+}
+
+impl GameLoop {
+    /// Creates a loop that updates the game the given number of times per
+    /// second. Panics if that is zero
+    pub fn new(updates_per_second: u32) -> GameLoop {
+        assert!(updates_per_second > 0, "The game must be updated at least once a second");
+        GameLoop { updates_per_second: updates_per_second }
+    }
+
+    /// Runs the game until it asks to stop
+    pub fn run<G>(&self,
+                  timer: &mut TimerSubsystem,
+                  events: &mut EventPump,
+                  renderer: &mut Renderer,
+                  game: &mut G)
+        where G: Game
+    {
+        let mut timestep = Timestep::new(self.updates_per_second);
+        let mut last = timer.ticks();
+
+        loop {
+            for event in events.poll_iter() {
+                if !game.process_event(&event) {
+                    return;
+                }
+            }
+
+            let now = timer.ticks();
+            let updates = timestep.advance(now.wrapping_sub(last));
+            last = now;
+
+            for _ in 0..updates {
+                game.update();
+            }
+
+            if updates > 0 {
+                game.render(renderer);
+            } else {
+                timer.delay(timestep.until_next_update());
+            }
+        }
+    }
+}
+
+/// Counts the time that passed in the updates it is worth. Time is kept in
+/// milliseconds times the update rate, so rates that don't divide a second
+/// into whole milliseconds, such as 60, don't drift
+struct Timestep {
+    updates_per_second: u64,
+    /// Time not yet spent on updates. An update is worth a thousand
+    lag: u64,
+}
+
+impl Timestep {
+    fn new(updates_per_second: u32) -> Timestep {
+        Timestep {
+            updates_per_second: updates_per_second as u64,
+            lag: 0,
+        }
+    }
+
+    /// Adds the milliseconds that passed, returning how many updates are
+    /// due. Time that would take more than `MAX_CATCH_UP_UPDATES` to catch
+    /// up on is dropped
+    fn advance(&mut self, milliseconds: u32) -> u32 {
+        self.lag += milliseconds as u64 * self.updates_per_second;
+
+        let mut updates = 0;
+        while self.lag >= 1000 {
+            if updates == MAX_CATCH_UP_UPDATES {
+                self.lag = 0;
+                break;
+            }
+            self.lag -= 1000;
+            updates += 1;
+        }
+        updates
+    }
+
+    /// The milliseconds until the next update is due, rounded up
+    fn until_next_update(&self) -> u32 {
+        ((1000 - self.lag + self.updates_per_second - 1) / self.updates_per_second) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs a timestep for a second of frames of the given length, returning
+    /// how many updates it was worth
+    fn updates_in_a_second(updates_per_second: u32, frame: u32) -> u32 {
+        let mut timestep = Timestep::new(updates_per_second);
+        let mut updates = 0;
+        let mut elapsed = 0;
+        while elapsed < 1000 {
+            updates += timestep.advance(frame);
+            elapsed += frame;
+        }
+        updates
+    }
+
+    #[test]
+    fn updates_as_often_as_asked_for_over_a_second() {
+        assert_eq!(60, updates_in_a_second(60, 1));
+        assert_eq!(60, updates_in_a_second(60, 10));
+        assert_eq!(60, updates_in_a_second(60, 20));
+        assert_eq!(30, updates_in_a_second(30, 1));
+        assert_eq!(144, updates_in_a_second(144, 1));
+    }
+
+    #[test]
+    fn waits_until_the_next_update() {
+        let mut timestep = Timestep::new(60);
+        assert_eq!(17, timestep.until_next_update());
+
+        assert_eq!(0, timestep.advance(10));
+        assert_eq!(7, timestep.until_next_update());
+
+        assert_eq!(1, timestep.advance(7));
+        assert_eq!(17, timestep.until_next_update());
+    }
+
+    #[test]
+    fn drops_time_it_cannot_catch_up_on() {
+        let mut timestep = Timestep::new(60);
+
+        assert_eq!(MAX_CATCH_UP_UPDATES, timestep.advance(1000));
+        assert_eq!(1, timestep.advance(17));
+    }
+
+    #[test]
+    #[should_panic]
+    fn needs_at_least_one_update_a_second() {
+        GameLoop::new(0);
+    }
+}
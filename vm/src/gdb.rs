@@ -0,0 +1,542 @@
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+
+use processor::Processor;
+use vm::VirtualMachine;
+
+/// SIGINT, reported when the client interrupted execution
+const SIGNAL_INTERRUPT: u8 = 2;
+/// SIGTRAP, reported when execution stopped at a breakpoint or after a step
+const SIGNAL_TRAP: u8 = 5;
+
+/// Serves the virtual machine over the GDB remote serial protocol.
+///
+/// The server never blocks: `poll` should be called once per frame to accept
+/// clients and answer any packets they sent since the last call.
+pub struct GdbServer {
+    listener: TcpListener,
+    client: Option<TcpStream>,
+    buffer: Vec<u8>,
+    /// Set while the client waits for execution to stop after a continue
+    /// or step request
+    awaiting_stop: bool,
+}
+
+impl GdbServer {
+    /// Starts listening on the given port of the loopback interface
+    pub fn bind(port: u16) -> io::Result<GdbServer> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+
+        Ok(GdbServer {
+            listener: listener,
+            client: None,
+            buffer: Vec::new(),
+            awaiting_stop: false,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.client.is_some()
+    }
+
+    pub fn poll(&mut self, vm: &mut VirtualMachine) {
+        if self.client.is_none() {
+            self.accept(vm);
+        }
+        if self.client.is_none() {
+            return;
+        }
+
+        if let Err(err) = self.receive() {
+            writeln!(vm.console, "GDB client disconnected ({})", err).unwrap();
+            self.disconnect();
+            return;
+        }
+
+        while let Some(packet) = next_packet(&mut self.buffer) {
+            let reply = match packet {
+                Packet::Interrupt => {
+                    vm.break_execution();
+                    self.awaiting_stop = false;
+                    Some(format!("S{:02x}", SIGNAL_INTERRUPT))
+                }
+                Packet::Command(command) => self.handle(&command, vm),
+                Packet::Corrupt => {
+                    self.send_raw(b"-");
+                    continue;
+                }
+            };
+
+            self.send_raw(b"+");
+            if let Some(reply) = reply {
+                self.send_packet(&reply);
+            }
+
+            if self.client.is_none() {
+                writeln!(vm.console, "GDB client detached").unwrap();
+                return;
+            }
+        }
+
+        if self.awaiting_stop && vm.is_debugging() && !vm.is_stepping() {
+            self.awaiting_stop = false;
+            self.send_packet(&format!("S{:02x}", SIGNAL_TRAP));
+        }
+    }
+
+    fn accept(&mut self, vm: &mut VirtualMachine) {
+        match self.listener.accept() {
+            Ok((stream, address)) => {
+                if stream.set_nonblocking(true).is_err() {
+                    return;
+                }
+                writeln!(vm.console, "GDB client connected from {}", address).unwrap();
+                self.client = Some(stream);
+                self.buffer.clear();
+                self.awaiting_stop = false;
+
+                // Debuggers expect the target to be halted once attached
+                vm.break_execution();
+            }
+            Err(ref err) if err.kind() == ErrorKind::WouldBlock => (),
+            Err(err) => writeln!(vm.console, "Unable to accept GDB client: {}", err).unwrap(),
+        }
+    }
+
+    fn disconnect(&mut self) {
+        self.client = None;
+        self.buffer.clear();
+        self.awaiting_stop = false;
+    }
+
+    /// Reads everything the client has sent so far into the buffer
+    fn receive(&mut self) -> io::Result<()> {
+        let mut chunk = [0; 1024];
+        loop {
+            let read = match self.client {
+                Some(ref mut client) => client.read(&mut chunk),
+                None => return Ok(()),
+            };
+            match read {
+                Ok(0) => return Err(io::Error::new(ErrorKind::UnexpectedEof, "connection closed")),
+                Ok(length) => self.buffer.extend_from_slice(&chunk[..length]),
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) {
+        let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        let packet = format!("${}#{:02x}", data, checksum);
+        self.send_raw(packet.as_bytes());
+    }
+
+    fn send_raw(&mut self, data: &[u8]) {
+        let failed = match self.client {
+            Some(ref mut client) => client.write_all(data).is_err(),
+            None => false,
+        };
+        if failed {
+            self.disconnect();
+        }
+    }
+
+    /// Handles a single command, returning the reply to send back. Unknown
+    /// commands get an empty reply, as the protocol requires
+    fn handle(&mut self, command: &str, vm: &mut VirtualMachine) -> Option<String> {
+        let (kind, body) = command.split_at(command.chars().next().map_or(0, |c| c.len_utf8()));
+
+        let reply = match kind {
+            "?" => format!("S{:02x}", SIGNAL_TRAP),
+            "g" => read_registers(&*vm.cpu),
+            "G" => write_registers(body, &mut *vm.cpu),
+            "p" => read_register(body, &*vm.cpu),
+            "P" => write_register(body, &mut *vm.cpu),
+            "m" => read_memory(body, vm),
+            "M" => write_memory(body, vm),
+            "Z" => set_breakpoint(body, vm, true),
+            "z" => set_breakpoint(body, vm, false),
+            "c" => {
+                if !body.is_empty() {
+                    if let Some(address) = parse_hex(body) {
+                        vm.cpu.set_pc(address as u16);
+                    }
+                }
+                vm.continue_execution();
+                self.awaiting_stop = true;
+                return None;
+            }
+            "s" => {
+                if !body.is_empty() {
+                    if let Some(address) = parse_hex(body) {
+                        vm.cpu.set_pc(address as u16);
+                    }
+                }
+                vm.step_execution();
+                self.awaiting_stop = true;
+                return None;
+            }
+            "D" => {
+                vm.continue_execution();
+                self.send_raw(b"+");
+                self.send_packet("OK");
+                self.disconnect();
+                return None;
+            }
+            "k" => {
+                vm.continue_execution();
+                self.disconnect();
+                return None;
+            }
+            "H" => "OK".into(),
+            "T" => "OK".into(),
+            "q" => query(body),
+            _ => String::new(),
+        };
+
+        Some(reply)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Packet {
+    Command(String),
+    Interrupt,
+    Corrupt,
+}
+
+/// Takes the next complete packet out of the buffer, skipping over the
+/// acknowledgements sent by the client
+fn next_packet(buffer: &mut Vec<u8>) -> Option<Packet> {
+    loop {
+        match buffer.first().cloned() {
+            None => return None,
+            Some(b'+') | Some(b'-') => {
+                buffer.remove(0);
+            }
+            Some(0x03) => {
+                buffer.remove(0);
+                return Some(Packet::Interrupt);
+            }
+            Some(b'$') => break,
+            Some(_) => {
+                buffer.remove(0);
+            }
+        }
+    }
+
+    // A packet looks like $<data>#<two hex digit checksum>
+    let end = match buffer.iter().position(|b| *b == b'#') {
+        Some(end) if end + 2 < buffer.len() => end,
+        _ => return None,
+    };
+    let packet = buffer.drain(..end + 3).collect::<Vec<_>>();
+    let data = &packet[1..end];
+    let checksum = String::from_utf8_lossy(&packet[end + 1..end + 3]).into_owned();
+
+    let expected = data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    match u8::from_str_radix(&checksum, 16) {
+        Ok(checksum) if checksum == expected => {
+            Some(Packet::Command(String::from_utf8_lossy(&unescape(data)).into_owned()))
+        }
+        _ => Some(Packet::Corrupt),
+    }
+}
+
+/// Undoes the escaping of `#`, `$` and `}` in packet data, which are sent
+/// as `}` followed by the character xor 0x20. The checksum covers the data
+/// as it was sent
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len());
+    let mut bytes = data.iter();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            b'}' => {
+                if let Some(&escaped) = bytes.next() {
+                    result.push(escaped ^ 0x20);
+                }
+            }
+            byte => result.push(byte),
+        }
+    }
+    result
+}
+
+fn query(body: &str) -> String {
+    if body.starts_with("Supported") {
+        "PacketSize=1000".into()
+    } else if body == "Attached" {
+        "1".into()
+    } else if body == "C" {
+        "QC1".into()
+    } else if body == "fThreadInfo" {
+        "m1".into()
+    } else if body == "sThreadInfo" {
+        "l".into()
+    } else {
+        String::new()
+    }
+}
+
+/// The registers are exchanged with the client in the order the CPU lists
+/// them, with the status flags packed into a byte just before the program
+/// counter: A, X, Y, S, P and PC on the 6502. All are a single byte wide,
+/// except for the little endian program counter.
+fn register_count(cpu: &Processor) -> usize {
+    cpu.registers().len() + 1
+}
+
+fn register_value(index: usize, cpu: &Processor) -> Option<String> {
+    let registers = cpu.registers();
+    let status = registers.len() - 1;
+    if index < status {
+        Some(format!("{:02x}", registers[index].1 as u8))
+    } else if index == status {
+        Some(format!("{:02x}", status_byte(cpu)))
+    } else if index == status + 1 {
+        let pc = cpu.pc();
+        Some(format!("{:02x}{:02x}", pc & 0xFF, pc >> 8))
+    } else {
+        None
+    }
+}
+
+fn set_register_value(index: usize, bytes: &[u8], cpu: &mut Processor) -> bool {
+    if bytes.is_empty() {
+        return false;
+    }
+    let registers = cpu.registers();
+    let status = registers.len() - 1;
+    if index < status {
+        cpu.set_register(registers[index].0, bytes[0] as u16)
+    } else if index == status {
+        set_status_byte(bytes[0], cpu);
+        true
+    } else if index == status + 1 && bytes.len() >= 2 {
+        cpu.set_pc(bytes[0] as u16 | (bytes[1] as u16) << 8);
+        true
+    } else {
+        false
+    }
+}
+
+/// The flags packed into a byte, the first flag in the highest bit
+fn status_byte(cpu: &Processor) -> u8 {
+    cpu.flags().iter().fold(0, |byte, &(_, _, set)| byte << 1 | set as u8)
+}
+
+fn set_status_byte(byte: u8, cpu: &mut Processor) {
+    let flags = cpu.flags();
+    for (index, &(letter, _, _)) in flags.iter().enumerate() {
+        let bit = flags.len() - 1 - index;
+        cpu.set_flag(letter, (byte as u32 >> bit) & 1 != 0);
+    }
+}
+
+fn read_registers(cpu: &Processor) -> String {
+    (0..register_count(cpu)).filter_map(|index| register_value(index, cpu)).collect()
+}
+
+fn write_registers(body: &str, cpu: &mut Processor) -> String {
+    let count = register_count(cpu);
+    let bytes = match decode_hex(body) {
+        Some(ref bytes) if bytes.len() >= count + 1 => bytes.clone(),
+        _ => return "E01".into(),
+    };
+
+    for index in 0..count {
+        set_register_value(index, &bytes[index..], cpu);
+    }
+    "OK".into()
+}
+
+fn read_register(body: &str, cpu: &Processor) -> String {
+    parse_hex(body)
+        .and_then(|index| register_value(index, cpu))
+        .unwrap_or_else(|| "E01".into())
+}
+
+fn write_register(body: &str, cpu: &mut Processor) -> String {
+    let mut parts = body.splitn(2, '=');
+    let index = parts.next().and_then(parse_hex);
+    let bytes = parts.next().and_then(decode_hex);
+
+    match (index, bytes) {
+        (Some(index), Some(bytes)) if set_register_value(index, &bytes, cpu) => "OK".into(),
+        _ => "E01".into(),
+    }
+}
+
+/// Parses the `addr,length` part of memory packets, making sure the range
+/// lies within memory of the given size
+fn parse_range(range: &str, size: usize) -> Option<(usize, usize)> {
+    let mut parts = range.splitn(2, ',');
+    let start = parts.next().and_then(parse_hex);
+    let length = parts.next().and_then(parse_hex);
+
+    match (start, length) {
+        (Some(start), Some(length)) if start.checked_add(length).map_or(false, |end| end <= size) => {
+            Some((start, length))
+        }
+        _ => None,
+    }
+}
+
+fn read_memory(body: &str, vm: &VirtualMachine) -> String {
+    match parse_range(body, vm.cpu.memory().len()) {
+        Some((start, length)) => {
+            vm.cpu.memory()[start..start + length].iter().map(|b| format!("{:02x}", b)).collect()
+        }
+        None => "E01".into(),
+    }
+}
+
+fn write_memory(body: &str, vm: &mut VirtualMachine) -> String {
+    let mut parts = body.splitn(2, ':');
+    let range = parts.next().and_then(|range| parse_range(range, vm.cpu.memory().len()));
+    let bytes = parts.next().and_then(decode_hex);
+
+    match (range, bytes) {
+        (Some((start, length)), Some(bytes)) if bytes.len() == length => {
+            if vm.write_protected(start, length).is_some() {
+                return "E01".into();
+            }
+            vm.write_memory(start, &bytes);
+            "OK".into()
+        }
+        _ => "E01".into(),
+    }
+}
+
+/// Handles `Z`/`z` packets of the form `type,addr,kind`. Software and
+/// hardware breakpoints are treated the same; watchpoints are unsupported.
+fn set_breakpoint(body: &str, vm: &mut VirtualMachine, enabled: bool) -> String {
+    let parts = body.split(',').collect::<Vec<_>>();
+    if parts.len() < 2 || (parts[0] != "0" && parts[0] != "1") {
+        return String::new();
+    }
+
+    match parse_hex(parts[1]) {
+        Some(address) if address <= 0xFFFF => {
+            if vm.has_breakpoint(address) != enabled {
+                vm.toggle_breakpoint(address);
+            }
+            "OK".into()
+        }
+        _ => "E01".into(),
+    }
+}
+
+fn parse_hex(input: &str) -> Option<usize> {
+    usize::from_str_radix(input, 16).ok()
+}
+
+fn decode_hex(input: &str) -> Option<Vec<u8>> {
+    if input.len() % 2 != 0 {
+        return None;
+    }
+
+    input.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            String::from_utf8(pair.to_vec())
+                .ok()
+                .and_then(|pair| u8::from_str_radix(&pair, 16).ok())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rs6502::Cpu;
+
+    use processor::Processor;
+
+    use super::*;
+
+    fn command(data: &str) -> Option<Packet> {
+        Some(Packet::Command(data.into()))
+    }
+
+    #[test]
+    fn takes_packets_with_a_valid_checksum() {
+        let mut buffer = b"+$m0,4#fd$?#3f".to_vec();
+        assert_eq!(next_packet(&mut buffer), command("m0,4"));
+        assert_eq!(next_packet(&mut buffer), command("?"));
+        assert_eq!(next_packet(&mut buffer), None);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn rejects_packets_with_a_bad_checksum() {
+        let mut buffer = b"$m0,4#00$?#3f".to_vec();
+        assert_eq!(next_packet(&mut buffer), Some(Packet::Corrupt));
+        assert_eq!(next_packet(&mut buffer), command("?"));
+    }
+
+    #[test]
+    fn waits_for_the_rest_of_a_packet() {
+        let mut buffer = b"$m0,".to_vec();
+        assert_eq!(next_packet(&mut buffer), None);
+        buffer.extend_from_slice(b"4#f");
+        assert_eq!(next_packet(&mut buffer), None);
+        buffer.extend_from_slice(b"d\x03");
+        assert_eq!(next_packet(&mut buffer), command("m0,4"));
+        assert_eq!(next_packet(&mut buffer), Some(Packet::Interrupt));
+    }
+
+    #[test]
+    fn unescapes_packet_data() {
+        // "X0,1:#" with the # escaped as }\x03
+        let mut buffer = b"$X0,1:}\x03#".to_vec();
+        let checksum = buffer[1..buffer.len() - 1].iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        buffer.extend_from_slice(format!("{:02x}", checksum).as_bytes());
+        assert_eq!(next_packet(&mut buffer), command("X0,1:#"));
+    }
+
+    #[test]
+    fn parses_memory_ranges_within_memory() {
+        assert_eq!(parse_range("c000,10", 0x10000), Some((0xC000, 0x10)));
+        assert_eq!(parse_range("fff0,10", 0x10000), Some((0xFFF0, 0x10)));
+        assert_eq!(parse_range("fff0,11", 0x10000), None);
+        assert_eq!(parse_range("ffffffffffffffff,2", 0x10000), None);
+        assert_eq!(parse_range("c000", 0x10000), None);
+        assert_eq!(parse_range("zz,1", 0x10000), None);
+    }
+
+    #[test]
+    fn decodes_hex_bytes() {
+        assert_eq!(decode_hex("00ff7A"), Some(vec![0x00, 0xFF, 0x7A]));
+        assert_eq!(decode_hex(""), Some(vec![]));
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("zz"), None);
+    }
+
+    #[test]
+    fn encodes_registers_with_the_status_before_the_program_counter() {
+        let mut cpu = Cpu::new();
+        cpu.set_register("A", 0x12);
+        cpu.set_register("X", 0x34);
+        cpu.set_register("Y", 0x56);
+        cpu.set_pc(0xC002);
+        // NV-BDIZC, with the interrupts disabled and carry set
+        cpu.set_flag('C', true);
+        assert_eq!(read_registers(&cpu), "123456ff0502c0");
+        assert_eq!(read_register("5", &cpu), "02c0");
+        assert_eq!(read_register("6", &cpu), "E01");
+
+        assert_eq!(write_registers("0102038081c0d0", &mut cpu), "OK");
+        assert_eq!(cpu.registers(), vec![("A", 0x01), ("X", 0x02), ("Y", 0x03), ("S", 0x80), ("PC", 0xD0C0)]);
+        assert_eq!(status_byte(&cpu), 0x81);
+        assert_eq!(write_register("4=03", &mut cpu), "OK");
+        assert_eq!(status_byte(&cpu), 0x03);
+        assert_eq!(write_register("7=00", &mut cpu), "E01");
+    }
+}
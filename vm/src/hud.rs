@@ -0,0 +1,84 @@
+use sdl2::gfx::primitives::DrawRenderer;
+use sdl2::pixels::Color;
+use sdl2::render::Renderer;
+use sdl2::ttf::Sdl2TtfContext;
+
+use position::Position;
+use text::Text;
+
+const BACKGROUND_COLOR: Color = Color::RGBA(0, 0, 0, 192);
+const BORDER_COLOR: Color = Color::RGBA(255, 255, 255, 64);
+const FONT_COLOR: Color = Color::RGBA(45, 200, 45, 255);
+const FONT_SIZE: u16 = 14;
+
+const PADDING: i32 = 10;
+const WIDTH: i32 = 280;
+
+/// A compact overview of the CPU state, shown in the top right corner of
+/// the window while execution is stopped
+pub struct DebugHud<'a> {
+    ttf_context: &'a Sdl2TtfContext,
+    font_file: &'a str,
+    lines: Vec<String>,
+    texts: Vec<Text>,
+}
+
+impl<'a> DebugHud<'a> {
+    pub fn new(ttf_context: &'a Sdl2TtfContext, font_file: &'a str) -> DebugHud<'a> {
+        DebugHud {
+            ttf_context: ttf_context,
+            font_file: font_file,
+            lines: Vec::new(),
+            texts: Vec::new(),
+        }
+    }
+
+    /// Sets the lines shown in the HUD. The text is only re-rendered when
+    /// the lines differ from the previous ones
+    pub fn update(&mut self, renderer: &mut Renderer, lines: Vec<String>) {
+        if lines == self.lines {
+            return;
+        }
+
+        let x = renderer.window().unwrap().size().0 as i32 - WIDTH;
+        let mut y = PADDING * 2;
+        self.texts.clear();
+        for line in &lines {
+            let text = Text::new(self.ttf_context,
+                                 renderer,
+                                 &line[..],
+                                 Position::XY(x + PADDING, y),
+                                 FONT_SIZE,
+                                 FONT_COLOR,
+                                 self.font_file);
+            y += text.size().1 as i32;
+            self.texts.push(text);
+        }
+        self.lines = lines;
+    }
+
+    pub fn render(&self, renderer: &mut Renderer) {
+        if self.texts.is_empty() {
+            return;
+        }
+
+        let x = renderer.window().unwrap().size().0 as i32 - WIDTH;
+        let height = self.texts.iter().map(|text| text.size().1 as i32).sum::<i32>() + PADDING * 2;
+        renderer.box_(x as i16,
+                  PADDING as i16,
+                  (x + WIDTH - PADDING) as i16,
+                  (PADDING + height) as i16,
+                  BACKGROUND_COLOR)
+            .unwrap();
+        renderer.rectangle(x as i16,
+                       PADDING as i16,
+                       (x + WIDTH - PADDING) as i16,
+                       (PADDING + height) as i16,
+                       BORDER_COLOR)
+            .unwrap();
+
+        for text in &self.texts {
+            text.render(renderer);
+        }
+    }
+}
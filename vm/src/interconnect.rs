@@ -0,0 +1,259 @@
+use processor::Processor;
+
+/// Where a device is mapped: an address in the memory of one CPU
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Endpoint {
+    /// The index of the CPU, where 0 runs the level source
+    pub cpu: usize,
+    pub address: u16,
+}
+
+/// The interrupt raised on the other CPUs when a device is written to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interrupt {
+    Irq,
+    Nmi,
+}
+
+/// A block of memory shared between CPUs. Writing to it on one CPU writes
+/// the same bytes on every other CPU, at the address each one maps it at
+#[derive(Clone, Debug)]
+pub struct SharedWindow {
+    pub endpoints: Vec<Endpoint>,
+    pub size: usize,
+    /// Raised on the other CPUs when one writes to the window
+    pub interrupt: Option<Interrupt>,
+    /// The contents as of the last sync, to find out what was written since
+    contents: Option<Vec<u8>>,
+}
+
+/// A one way message channel between two CPUs, mapped as two registers at
+/// each end: the data byte, followed by a status byte.
+///
+/// The sender writes the data and then a nonzero status to send it. The
+/// message is copied to the receiver's data register and the receiver's
+/// status becomes `$01`. The receiver acknowledges by writing `$00` to its
+/// status, which clears the sender's status for the next message.
+#[derive(Clone, Debug)]
+pub struct Mailbox {
+    pub from: Endpoint,
+    pub to: Endpoint,
+    /// Raised on the receiver when a message arrives
+    pub interrupt: Option<Interrupt>,
+    full: bool,
+}
+
+/// The devices connecting the CPUs of a level
+#[derive(Clone, Debug, Default)]
+pub struct Interconnect {
+    windows: Vec<SharedWindow>,
+    mailboxes: Vec<Mailbox>,
+}
+
+impl SharedWindow {
+    pub fn new(endpoints: Vec<Endpoint>, size: usize, interrupt: Option<Interrupt>) -> SharedWindow {
+        SharedWindow {
+            endpoints: endpoints,
+            size: size,
+            interrupt: interrupt,
+            contents: None,
+        }
+    }
+
+    /// The bytes mapped at an endpoint
+    fn read(&self, endpoint: &Endpoint, cpus: &[&mut Processor]) -> Vec<u8> {
+        let start = endpoint.address as usize;
+        cpus[endpoint.cpu].memory()[start..start + self.size].to_vec()
+    }
+
+    /// Picks up writes made by the given CPU, and copies them to the others
+    fn sync(&mut self, cpu: usize, cpus: &mut [&mut Processor], interrupted: &mut Vec<usize>) {
+        let first = self.endpoints[0];
+        if self.contents.is_none() {
+            let contents = self.read(&first, cpus);
+            self.contents = Some(contents);
+            self.copy_to_all(cpus, None);
+        }
+
+        // This runs after every step, so the endpoints are gone through by
+        // index rather than cloned to copy into the other CPUs
+        for index in 0..self.endpoints.len() {
+            let endpoint = self.endpoints[index];
+            if endpoint.cpu != cpu {
+                continue;
+            }
+            let bytes = self.read(&endpoint, cpus);
+            if self.contents.as_ref() == Some(&bytes) {
+                continue;
+            }
+            self.contents = Some(bytes);
+            self.copy_to_all(cpus, Some(endpoint));
+            if let Some(interrupt) = self.interrupt {
+                for other in self.endpoints.iter().filter(|other| other.cpu != cpu) {
+                    if raise(&mut *cpus[other.cpu], interrupt) {
+                        interrupted.push(other.cpu);
+                    }
+                }
+            }
+        }
+    }
+
+    fn copy_to_all(&self, cpus: &mut [&mut Processor], except: Option<Endpoint>) {
+        let contents = match self.contents {
+            Some(ref contents) => contents,
+            None => return,
+        };
+        for endpoint in self.endpoints.iter().filter(|endpoint| Some(**endpoint) != except) {
+            let start = endpoint.address as usize;
+            cpus[endpoint.cpu].memory_mut()[start..start + self.size].copy_from_slice(contents);
+        }
+    }
+}
+
+impl Mailbox {
+    pub fn new(from: Endpoint, to: Endpoint, interrupt: Option<Interrupt>) -> Mailbox {
+        Mailbox {
+            from: from,
+            to: to,
+            interrupt: interrupt,
+            full: false,
+        }
+    }
+
+    /// Whether a message was sent and not yet acknowledged
+    pub fn is_full(&self) -> bool {
+        self.full
+    }
+
+    /// Delivers a message the given CPU sent, or passes on its
+    /// acknowledgement
+    fn sync(&mut self, cpu: usize, cpus: &mut [&mut Processor], interrupted: &mut Vec<usize>) {
+        let from = self.from.address as usize;
+        let to = self.to.address as usize;
+
+        if cpu == self.from.cpu && !self.full && cpus[self.from.cpu].memory()[from + 1] != 0 {
+            self.full = true;
+            let data = cpus[self.from.cpu].memory()[from];
+            cpus[self.to.cpu].memory_mut()[to] = data;
+            cpus[self.to.cpu].memory_mut()[to + 1] = 0x01;
+            if let Some(interrupt) = self.interrupt {
+                if raise(&mut *cpus[self.to.cpu], interrupt) {
+                    interrupted.push(self.to.cpu);
+                }
+            }
+        } else if cpu == self.to.cpu && self.full && cpus[self.to.cpu].memory()[to + 1] == 0 {
+            self.full = false;
+            cpus[self.from.cpu].memory_mut()[from + 1] = 0x00;
+        }
+    }
+}
+
+impl Interconnect {
+    pub fn new(windows: Vec<SharedWindow>, mailboxes: Vec<Mailbox>) -> Interconnect {
+        Interconnect {
+            windows: windows,
+            mailboxes: mailboxes,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.windows.is_empty() && self.mailboxes.is_empty()
+    }
+
+    pub fn windows(&self) -> &[SharedWindow] {
+        &self.windows
+    }
+
+    pub fn mailboxes(&self) -> &[Mailbox] {
+        &self.mailboxes
+    }
+
+    /// Picks up what the CPU with the given index wrote to the devices since
+    /// the last sync, and passes it on to the other CPUs. Returns the CPUs
+    /// that took an interrupt for it
+    pub fn sync(&mut self, cpu: usize, cpus: &mut [&mut Processor]) -> Vec<usize> {
+        let mut interrupted = Vec::new();
+        for window in &mut self.windows {
+            window.sync(cpu, cpus, &mut interrupted);
+        }
+        for mailbox in &mut self.mailboxes {
+            mailbox.sync(cpu, cpus, &mut interrupted);
+        }
+        interrupted
+    }
+}
+
+fn raise(cpu: &mut Processor, interrupt: Interrupt) -> bool {
+    match interrupt {
+        Interrupt::Irq => cpu.irq(),
+        Interrupt::Nmi => cpu.nmi(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use processor::Processor;
+    use processor::tests::StubProcessor;
+
+    use super::*;
+
+    fn endpoint(cpu: usize, address: u16) -> Endpoint {
+        Endpoint {
+            cpu: cpu,
+            address: address,
+        }
+    }
+
+    /// Two CPUs, the second with an interrupt handler
+    fn cpus() -> (StubProcessor, StubProcessor) {
+        let mut receiver = StubProcessor::new();
+        receiver.handler = Some(0x8000);
+        (StubProcessor::new(), receiver)
+    }
+
+    #[test]
+    fn shared_writes_show_up_on_the_other_cpu() {
+        let window = SharedWindow::new(vec![endpoint(0, 0x0300), endpoint(1, 0x0400)], 4, Some(Interrupt::Irq));
+        let mut interconnect = Interconnect::new(vec![window], Vec::new());
+        let (mut first, mut second) = cpus();
+
+        assert!(interconnect.sync(0, &mut [&mut first, &mut second]).is_empty());
+        first.memory[0x0302] = 0x42;
+        assert_eq!(interconnect.sync(0, &mut [&mut first, &mut second]), vec![1]);
+        assert_eq!(&second.memory[0x0400..0x0404], &[0x00, 0x00, 0x42, 0x00]);
+        assert_eq!(second.pc(), 0x8000);
+
+        // And back. The first CPU has no handler, so it isn't interrupted
+        second.memory[0x0400] = 0x17;
+        assert!(interconnect.sync(1, &mut [&mut first, &mut second]).is_empty());
+        assert_eq!(&first.memory[0x0300..0x0304], &[0x17, 0x00, 0x42, 0x00]);
+    }
+
+    #[test]
+    fn mailboxes_deliver_and_acknowledge_messages() {
+        let mailbox = Mailbox::new(endpoint(0, 0x10), endpoint(1, 0x20), Some(Interrupt::Irq));
+        let mut interconnect = Interconnect::new(Vec::new(), vec![mailbox]);
+        let (mut sender, mut receiver) = cpus();
+
+        sender.memory[0x10] = 0x99;
+        assert!(interconnect.sync(0, &mut [&mut sender, &mut receiver]).is_empty());
+        assert!(!interconnect.mailboxes()[0].is_full());
+
+        // Sending sets the receiver's status
+        sender.memory[0x11] = 0xFF;
+        assert_eq!(interconnect.sync(0, &mut [&mut sender, &mut receiver]), vec![1]);
+        assert_eq!(&receiver.memory[0x20..0x22], &[0x99, 0x01]);
+        assert!(interconnect.mailboxes()[0].is_full());
+
+        // Nothing more is sent until the message is acknowledged
+        sender.memory[0x10] = 0x98;
+        assert!(interconnect.sync(0, &mut [&mut sender, &mut receiver]).is_empty());
+        assert_eq!(receiver.memory[0x20], 0x99);
+
+        // Acknowledging clears the sender's status
+        receiver.memory[0x21] = 0x00;
+        assert!(interconnect.sync(1, &mut [&mut sender, &mut receiver]).is_empty());
+        assert_eq!(sender.memory[0x11], 0x00);
+        assert!(!interconnect.mailboxes()[0].is_full());
+    }
+}
@@ -0,0 +1,932 @@
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use rustc_serialize::json::{Json, ParserError};
+
+use expression::{evaluate, ExpressionError};
+use interconnect::{Endpoint, Interrupt, Mailbox, SharedWindow};
+use protection::{Access, Region};
+use sound;
+use vm::VirtualMachine;
+
+/// 16 colors of a red, green and blue byte each
+const PALETTE_SIZE: usize = 16 * 3;
+
+/// The x and y words, the image and the flags of a sprite table entry
+const SPRITE_ENTRY_SIZE: usize = 6;
+
+const SPRITE_VISIBLE: u8 = 0x01;
+const SPRITE_FLIP_HORIZONTAL: u8 = 0x02;
+const SPRITE_FLIP_VERTICAL: u8 = 0x04;
+
+/// Everything needed to play a level, read from a JSON manifest.
+///
+/// Addresses and values are expressions (see `expression::evaluate`), so
+/// they can use the labels and variables of the level source. Conditions are
+/// expressions that hold when they evaluate to anything but zero. They all
+/// refer to the first CPU, which runs the level source:
+///
+/// ```json
+/// {
+///     "name": "Training 1",
+///     "source": "level.asm",
+///     "load_address": "$C000",
+///     "memory": [{ "address": "Y_0", "word": "$1FF" }],
+///     "inputs": [{ "key": "Up", "address": "KEY", "pressed": "#38", "released": "0" }],
+///     "sprites": [{ "texture": "ship.png", "x": "w[X_0]", "y": "w[Y_0]" }],
+///     "banners": [{ "text": "FINISH", "y": 0, "color": [0, 144, 192] }],
+///     "rules": [{ "when": "w[Y_0] < $100", "write": [{ "address": "Y_0", "word": "$100" }] }],
+///     "win": "w[Y_0] <= $8C",
+///     "lose": "[$10] != 0",
+///     "hints": ["Look at what happens after SBC", "The clamp at $190 isn't in the code"],
+///     "cpus": [{ "source": "radar.asm", "load_address": "$C000" }],
+///     "shared": [{ "size": 16, "at": [{ "cpu": 0, "address": "$0300" }, { "cpu": 1, "address": "$0300" }] }],
+///     "mailboxes": [{ "from": { "cpu": 0, "address": "$10" }, "to": { "cpu": 1, "address": "$10" }, "interrupt": "irq" }],
+///     "mappers": [{ "window": "$8000", "size": "$2000", "register": "$FF00", "banks": [{ "source": "bank0.asm" }, { "ram": true }] }],
+///     "protected": [{ "start": "$C000", "end": "$FFFF", "access": "read-only", "interrupt": "irq" }],
+///     "display": { "map": "$0400", "palette": "$0700", "columns": 20, "rows": 15, "tile_size": 32, "tiles": "tiles.png" },
+///     "sprite_table": { "address": "$0600", "count": 8, "images": ["ship.png", "ship-flame.png"] },
+///     "sound": "$D400"
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Level {
+    pub name: String,
+    pub directory: PathBuf,
+    /// The assembly source, relative to the manifest
+    pub source: PathBuf,
+    pub load_address: u16,
+    /// Written into memory before the level starts
+    pub memory: Vec<MemoryWrite>,
+    pub inputs: Vec<InputMapping>,
+    pub sprites: Vec<SpriteBinding>,
+    pub banners: Vec<Banner>,
+    /// Level logic that runs outside of the VM every frame
+    pub rules: Vec<Rule>,
+    pub win: Option<String>,
+    pub lose: Option<String>,
+    /// Revealed one at a time by the `hint` command, so each should give
+    /// away more than the one before
+    pub hints: Vec<String>,
+    /// More CPUs running alongside the first one, each with its own memory
+    pub cpus: Vec<Coprocessor>,
+    /// Memory shared between the CPUs
+    pub shared: Vec<SharedWindow>,
+    /// Message channels between the CPUs
+    pub mailboxes: Vec<Mailbox>,
+    /// Bank switched windows in the memory of the first CPU
+    pub mappers: Vec<MapperLayout>,
+    /// Memory the code of the first CPU, the console and the debuggers may
+    /// not use freely
+    pub protected: Vec<Region>,
+    /// Drawn from memory behind everything else
+    pub display: Option<TileDisplay>,
+    /// Sprites drawn from memory, in front of everything else
+    pub sprite_table: Option<SpriteTable>,
+    /// The address of the registers of the sound device
+    pub sound: Option<u16>,
+}
+
+/// A CPU running its own code next to the level source
+#[derive(Clone, Debug)]
+pub struct Coprocessor {
+    /// The assembly source, relative to the manifest
+    pub source: PathBuf,
+    pub load_address: u16,
+    /// Written into its memory before the level starts. The expressions
+    /// refer to the labels of this CPU's source
+    pub memory: Vec<MemoryWrite>,
+}
+
+/// A window of memory switching between banks, selected by writing the bank
+/// number to a register
+#[derive(Clone, Debug)]
+pub struct MapperLayout {
+    pub window: u16,
+    pub size: usize,
+    pub register: u16,
+    pub banks: Vec<BankContents>,
+}
+
+/// What a bank holds when the level starts
+#[derive(Clone, Debug, PartialEq)]
+pub enum BankContents {
+    /// Code assembled at the address of the window, from a source relative
+    /// to the manifest
+    Rom(PathBuf),
+    /// Empty RAM
+    Ram,
+}
+
+/// A tile map and a palette in the memory of the first CPU, drawn every
+/// frame.
+///
+/// The map has a byte for each cell, row by row. The low nibble of a cell
+/// picks its color from the palette, where color 0 is transparent, and the
+/// high nibble picks its tile. The palette holds 16 colors of a red, green
+/// and blue byte each.
+#[derive(Clone, Debug)]
+pub struct TileDisplay {
+    pub map: u16,
+    pub palette: u16,
+    pub columns: u32,
+    pub rows: u32,
+    /// The width and height of a cell on screen, in pixels
+    pub tile_size: u32,
+    /// Where the top left cell is drawn on screen
+    pub x: i32,
+    pub y: i32,
+    /// A texture from the assets folder with up to 16 square tiles side by
+    /// side, drawn in white so they can be tinted with the color of a cell.
+    /// Without one, every cell is a solid block of its color
+    pub tiles: Option<String>,
+}
+
+/// A table of sprites in the memory of the first CPU, drawn every frame.
+///
+/// Each entry is 6 bytes: the x and y position on screen as little endian
+/// words, the index of its image and its flags. Flag bit 0 shows the
+/// sprite, bit 1 flips it horizontally and bit 2 vertically.
+#[derive(Clone, Debug)]
+pub struct SpriteTable {
+    pub address: u16,
+    pub count: u32,
+    /// The textures the entries pick from, in the assets folder
+    pub images: Vec<String>,
+}
+
+/// An entry of a sprite table, as it is in memory right now
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpriteEntry {
+    pub x: i16,
+    pub y: i16,
+    pub image: u8,
+    pub visible: bool,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+}
+
+/// Bytes written to memory, either as a list of bytes or a single little
+/// endian word
+#[derive(Clone, Debug)]
+pub struct MemoryWrite {
+    pub address: String,
+    pub values: Vec<String>,
+    pub word: bool,
+}
+
+/// Writes `pressed` to `address` when a key goes down, and `released` when
+/// it comes back up
+#[derive(Clone, Debug)]
+pub struct InputMapping {
+    /// The SDL name of the key, e.g. "Up" or "Space"
+    pub key: String,
+    pub address: String,
+    pub pressed: String,
+    pub released: String,
+}
+
+/// A texture drawn at a position read from memory
+#[derive(Clone, Debug)]
+pub struct SpriteBinding {
+    /// The texture file, relative to the assets folder
+    pub texture: String,
+    pub x: String,
+    pub y: String,
+    pub visible: Option<String>,
+}
+
+/// A strip of color across the screen with some text on it
+#[derive(Clone, Debug)]
+pub struct Banner {
+    pub text: String,
+    pub y: i32,
+    pub color: (u8, u8, u8),
+}
+
+/// Memory writes made every frame while a condition holds
+#[derive(Clone, Debug)]
+pub struct Rule {
+    pub when: String,
+    pub writes: Vec<MemoryWrite>,
+}
+
+#[derive(Debug)]
+pub enum LevelError {
+    File(io::Error),
+    Parse(ParserError),
+    Invalid(String),
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LevelError::File(ref err) => write!(f, "{}", err),
+            LevelError::Parse(ref err) => write!(f, "{}", err),
+            LevelError::Invalid(ref message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<io::Error> for LevelError {
+    fn from(e: io::Error) -> LevelError {
+        LevelError::File(e)
+    }
+}
+impl From<ParserError> for LevelError {
+    fn from(e: ParserError) -> LevelError {
+        LevelError::Parse(e)
+    }
+}
+
+impl Level {
+    pub fn load<P>(path: P) -> Result<Level, LevelError>
+        where P: AsRef<Path>
+    {
+        let path = path.as_ref();
+        let mut file = File::open(path)?;
+        let mut buffer = String::new();
+        file.read_to_string(&mut buffer)?;
+
+        let directory = path.parent().map(|parent| parent.to_path_buf()).unwrap_or_else(PathBuf::new);
+        Level::parse(&buffer, directory)
+    }
+
+    /// Reads a manifest, whose paths are relative to the given directory
+    fn parse(manifest: &str, directory: PathBuf) -> Result<Level, LevelError> {
+        let json = Json::from_str(manifest)?;
+
+        let level = Level {
+            name: required(&json, "name")?,
+            directory: directory,
+            source: PathBuf::from(required(&json, "source")?),
+            load_address: load_address(&json)?,
+            memory: list(&json, "memory", memory_write)?,
+            inputs: list(&json, "inputs", input_mapping)?,
+            sprites: list(&json, "sprites", sprite_binding)?,
+            banners: list(&json, "banners", banner)?,
+            rules: list(&json, "rules", rule)?,
+            win: optional(&json, "win")?,
+            lose: optional(&json, "lose")?,
+            hints: list(&json, "hints", hint)?,
+            cpus: list(&json, "cpus", coprocessor)?,
+            shared: list(&json, "shared", shared_window)?,
+            mailboxes: list(&json, "mailboxes", mailbox)?,
+            mappers: list(&json, "mappers", mapper_layout)?,
+            protected: list(&json, "protected", region)?,
+            display: match json.find("display") {
+                Some(display) => Some(tile_display(display).map_err(|err| invalid(format!("display: {}", err)))?),
+                None => None,
+            },
+            sprite_table: match json.find("sprite_table") {
+                Some(table) => Some(sprite_table(table).map_err(|err| invalid(format!("sprite_table: {}", err)))?),
+                None => None,
+            },
+            sound: sound_registers(&json)?,
+        };
+
+        let cpus = level.cpus.len() + 1;
+        let endpoints = level.shared
+            .iter()
+            .flat_map(|window| window.endpoints.iter())
+            .chain(level.mailboxes.iter().flat_map(|mailbox| vec![&mailbox.from, &mailbox.to]));
+        for endpoint in endpoints {
+            if endpoint.cpu >= cpus {
+                return Err(invalid(format!("There is no cpu {}, the level has {}", endpoint.cpu, cpus)));
+            }
+        }
+        Ok(level)
+    }
+
+    /// Identifies the level, e.g. to record progress. This is the name of
+    /// the folder the manifest is in
+    pub fn id(&self) -> String {
+        self.directory
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| self.name.clone())
+    }
+
+    /// The full path of the assembly source
+    pub fn source_path(&self) -> PathBuf {
+        self.directory.join(&self.source)
+    }
+
+    /// The full path of the assembly source of a coprocessor
+    pub fn coprocessor_source_path(&self, coprocessor: &Coprocessor) -> PathBuf {
+        self.directory.join(&coprocessor.source)
+    }
+
+    /// The full path of the assembly source of a bank
+    pub fn bank_source_path(&self, source: &Path) -> PathBuf {
+        self.directory.join(source)
+    }
+
+    /// Evaluates every expression in the level once, so mistakes such as
+    /// unknown labels show up when the level is loaded rather than mid game
+    pub fn validate(&self, vm: &VirtualMachine) -> Result<(), String> {
+        let mut expressions = Vec::new();
+        for write in self.memory.iter().chain(self.rules.iter().flat_map(|rule| rule.writes.iter())) {
+            expressions.push(("memory address", &write.address));
+            for value in &write.values {
+                expressions.push(("memory value", value));
+            }
+        }
+        for input in &self.inputs {
+            expressions.push(("input address", &input.address));
+            expressions.push(("input value", &input.pressed));
+            expressions.push(("input value", &input.released));
+        }
+        for sprite in &self.sprites {
+            expressions.push(("sprite x", &sprite.x));
+            expressions.push(("sprite y", &sprite.y));
+            if let Some(ref visible) = sprite.visible {
+                expressions.push(("sprite visibility", visible));
+            }
+        }
+        for rule in &self.rules {
+            expressions.push(("rule condition", &rule.when));
+        }
+        if let Some(ref win) = self.win {
+            expressions.push(("win condition", win));
+        }
+        if let Some(ref lose) = self.lose {
+            expressions.push(("lose condition", lose));
+        }
+
+        for (what, expression) in expressions {
+            evaluate(expression, vm).map_err(|err| format!("Invalid {} '{}': {}", what, expression, err))?;
+        }
+        Ok(())
+    }
+}
+
+impl SpriteTable {
+    /// Reads every entry of the table from memory
+    pub fn entries(&self, memory: &[u8]) -> Vec<SpriteEntry> {
+        let start = self.address as usize;
+        memory[start..start + self.count as usize * SPRITE_ENTRY_SIZE]
+            .chunks(SPRITE_ENTRY_SIZE)
+            .map(|entry| {
+                let flags = entry[5];
+                SpriteEntry {
+                    x: (entry[0] as u16 | (entry[1] as u16) << 8) as i16,
+                    y: (entry[2] as u16 | (entry[3] as u16) << 8) as i16,
+                    image: entry[4],
+                    visible: flags & SPRITE_VISIBLE != 0,
+                    flip_horizontal: flags & SPRITE_FLIP_HORIZONTAL != 0,
+                    flip_vertical: flags & SPRITE_FLIP_VERTICAL != 0,
+                }
+            })
+            .collect()
+    }
+}
+
+impl MemoryWrite {
+    /// The address and bytes to write
+    pub fn evaluate(&self, vm: &VirtualMachine) -> Result<(usize, Vec<u8>), ExpressionError> {
+        let address = evaluate(&self.address, vm)?;
+        let mut bytes = Vec::new();
+        for value in &self.values {
+            let value = evaluate(value, vm)?;
+            bytes.push(value as u8);
+            if self.word {
+                bytes.push((value >> 8) as u8);
+            }
+        }
+
+        if address < 0 || address as usize + bytes.len() > 0x10000 {
+            return Err(ExpressionError::AddressOutOfRange(address));
+        }
+        Ok((address as usize, bytes))
+    }
+}
+
+fn invalid<S>(message: S) -> LevelError
+    where S: Into<String>
+{
+    LevelError::Invalid(message.into())
+}
+
+/// Reads an expression, which may be written as a string or a plain number
+fn expression(json: &Json) -> Option<String> {
+    match *json {
+        Json::String(ref value) => Some(value.clone()),
+        Json::I64(value) => Some(format!("#{}", value)),
+        Json::U64(value) => Some(format!("#{}", value)),
+        _ => None,
+    }
+}
+
+fn optional(json: &Json, name: &str) -> Result<Option<String>, LevelError> {
+    match json.find(name) {
+        Some(value) => expression(value).map(Some).ok_or_else(|| invalid(format!("'{}' must be a string", name))),
+        None => Ok(None),
+    }
+}
+
+fn required(json: &Json, name: &str) -> Result<String, LevelError> {
+    optional(json, name)?.ok_or_else(|| invalid(format!("Missing '{}'", name)))
+}
+
+fn list<T, F>(json: &Json, name: &str, parse: F) -> Result<Vec<T>, LevelError>
+    where F: Fn(&Json) -> Result<T, LevelError>
+{
+    let items = match json.find(name) {
+        Some(items) => items.as_array().ok_or_else(|| invalid(format!("'{}' must be a list", name)))?,
+        None => return Ok(Vec::new()),
+    };
+
+    let mut result = Vec::new();
+    for (index, item) in items.iter().enumerate() {
+        result.push(parse(item).map_err(|err| invalid(format!("{} #{}: {}", name, index, err)))?);
+    }
+    Ok(result)
+}
+
+fn load_address(json: &Json) -> Result<u16, LevelError> {
+    parse_number(&required(json, "load_address")?)
+        .ok_or_else(|| invalid("'load_address' must be a number, such as \"$C000\""))
+}
+
+fn parse_number(value: &str) -> Option<u16> {
+    if value.starts_with('$') {
+        u16::from_str_radix(&value[1..], 16).ok()
+    } else if value.starts_with('#') {
+        value[1..].parse().ok()
+    } else {
+        u16::from_str_radix(value, 16).ok()
+    }
+}
+
+fn memory_write(json: &Json) -> Result<MemoryWrite, LevelError> {
+    let address = required(json, "address")?;
+
+    if let Some(word) = optional(json, "word")? {
+        return Ok(MemoryWrite {
+            address: address,
+            values: vec![word],
+            word: true,
+        });
+    }
+
+    let bytes = json.find("bytes")
+        .and_then(|bytes| bytes.as_array())
+        .ok_or_else(|| invalid("Expected 'word' or a list of 'bytes'"))?;
+    let values = bytes.iter()
+        .map(|byte| expression(byte).ok_or_else(|| invalid("'bytes' must be strings")))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(MemoryWrite {
+        address: address,
+        values: values,
+        word: false,
+    })
+}
+
+fn input_mapping(json: &Json) -> Result<InputMapping, LevelError> {
+    Ok(InputMapping {
+        key: required(json, "key")?,
+        address: required(json, "address")?,
+        pressed: required(json, "pressed")?,
+        released: optional(json, "released")?.unwrap_or_else(|| "0".into()),
+    })
+}
+
+fn sprite_binding(json: &Json) -> Result<SpriteBinding, LevelError> {
+    Ok(SpriteBinding {
+        texture: required(json, "texture")?,
+        x: required(json, "x")?,
+        y: required(json, "y")?,
+        visible: optional(json, "visible")?,
+    })
+}
+
+fn banner(json: &Json) -> Result<Banner, LevelError> {
+    let y = json.find("y").and_then(|y| y.as_i64()).ok_or_else(|| invalid("'y' must be a number"))?;
+    let color = json.find("color")
+        .and_then(|color| color.as_array())
+        .map(|color| color.iter().filter_map(|c| c.as_u64()).map(|c| c as u8).collect::<Vec<_>>())
+        .unwrap_or_else(Vec::new);
+    if color.len() != 3 {
+        return Err(invalid("'color' must be a list of red, green and blue"));
+    }
+
+    Ok(Banner {
+        text: required(json, "text")?,
+        y: y as i32,
+        color: (color[0], color[1], color[2]),
+    })
+}
+
+fn rule(json: &Json) -> Result<Rule, LevelError> {
+    Ok(Rule {
+        when: required(json, "when")?,
+        writes: list(json, "write", memory_write)?,
+    })
+}
+
+fn hint(json: &Json) -> Result<String, LevelError> {
+    json.as_string().map(|hint| hint.to_owned()).ok_or_else(|| invalid("Hints must be strings"))
+}
+
+fn coprocessor(json: &Json) -> Result<Coprocessor, LevelError> {
+    Ok(Coprocessor {
+        source: PathBuf::from(required(json, "source")?),
+        load_address: load_address(json)?,
+        memory: list(json, "memory", memory_write)?,
+    })
+}
+
+fn endpoint(json: &Json, size: u64) -> Result<Endpoint, LevelError> {
+    let cpu = json.find("cpu").and_then(|cpu| cpu.as_u64()).ok_or_else(|| invalid("'cpu' must be a number"))?;
+    let address = parse_number(&required(json, "address")?)
+        .ok_or_else(|| invalid("'address' must be a number, such as \"$0300\""))?;
+    if size > 0x10000 - address as u64 {
+        return Err(invalid(format!("${:04X} is too close to the end of memory", address)));
+    }
+
+    Ok(Endpoint {
+        cpu: cpu as usize,
+        address: address,
+    })
+}
+
+fn interrupt(json: &Json) -> Result<Option<Interrupt>, LevelError> {
+    match optional(json, "interrupt")? {
+        Some(ref interrupt) if interrupt == "irq" => Ok(Some(Interrupt::Irq)),
+        Some(ref interrupt) if interrupt == "nmi" => Ok(Some(Interrupt::Nmi)),
+        Some(interrupt) => Err(invalid(format!("'interrupt' must be \"irq\" or \"nmi\", found \"{}\"", interrupt))),
+        None => Ok(None),
+    }
+}
+
+fn shared_window(json: &Json) -> Result<SharedWindow, LevelError> {
+    let size = json.find("size").and_then(|size| size.as_u64()).ok_or_else(|| invalid("'size' must be a number"))?;
+    let endpoints = list(json, "at", |json| endpoint(json, size))?;
+    if endpoints.len() < 2 {
+        return Err(invalid("Shared memory must be mapped 'at' two places or more"));
+    }
+
+    // Every endpoint fits in memory, so the size fits in the address space
+    Ok(SharedWindow::new(endpoints, size as usize, interrupt(json)?))
+}
+
+fn mailbox(json: &Json) -> Result<Mailbox, LevelError> {
+    // The data and the status register
+    let size = 2;
+    let from = json.find("from").ok_or_else(|| invalid("Missing 'from'")).and_then(|from| endpoint(from, size))?;
+    let to = json.find("to").ok_or_else(|| invalid("Missing 'to'")).and_then(|to| endpoint(to, size))?;
+
+    Ok(Mailbox::new(from, to, interrupt(json)?))
+}
+
+fn mapper_layout(json: &Json) -> Result<MapperLayout, LevelError> {
+    let number = |name: &str| {
+        parse_number(&required(json, name)?)
+            .ok_or_else(|| invalid(format!("'{}' must be a number, such as \"$8000\"", name)))
+    };
+    let window = number("window")?;
+    let size = number("size")? as usize;
+    let register = number("register")?;
+    let banks = list(json, "banks", bank_contents)?;
+
+    if size == 0 || window as usize + size > 0x10000 {
+        return Err(invalid(format!("A window of {} bytes doesn't fit at ${:04X}", size, window)));
+    }
+    if register >= window && (register as usize) < window as usize + size {
+        return Err(invalid(format!("The register at ${:04X} can't be inside the window", register)));
+    }
+    if banks.is_empty() {
+        return Err(invalid("A mapper needs at least one bank"));
+    }
+
+    Ok(MapperLayout {
+        window: window,
+        size: size,
+        register: register,
+        banks: banks,
+    })
+}
+
+fn bank_contents(json: &Json) -> Result<BankContents, LevelError> {
+    if let Some(source) = optional(json, "source")? {
+        return Ok(BankContents::Rom(PathBuf::from(source)));
+    }
+    match json.find("ram").and_then(|ram| ram.as_boolean()) {
+        Some(true) => Ok(BankContents::Ram),
+        _ => Err(invalid("Expected a 'source' or \"ram\": true")),
+    }
+}
+
+fn region(json: &Json) -> Result<Region, LevelError> {
+    let number = |name: &str| {
+        parse_number(&required(json, name)?)
+            .ok_or_else(|| invalid(format!("'{}' must be a number, such as \"$C000\"", name)))
+    };
+    let start = number("start")?;
+    let end = number("end")?;
+    if end < start {
+        return Err(invalid("'end' must not be before 'start'"));
+    }
+    let access = match &required(json, "access")?[..] {
+        "read-only" => Access::ReadOnly,
+        "no-execute" => Access::NoExecute,
+        "privileged" => Access::Privileged,
+        access => {
+            return Err(invalid(format!("'access' must be \"read-only\", \"no-execute\" or \"privileged\", found \"{}\"",
+                                       access)))
+        }
+    };
+
+    Ok(Region {
+        start: start,
+        end: end,
+        access: access,
+        interrupt: interrupt(json)?,
+    })
+}
+
+fn tile_display(json: &Json) -> Result<TileDisplay, LevelError> {
+    let address = |name: &str| {
+        parse_number(&required(json, name)?)
+            .ok_or_else(|| invalid(format!("'{}' must be a number, such as \"$0400\"", name)))
+    };
+    let number = |name: &str| {
+        json.find(name)
+            .and_then(|value| value.as_u64())
+            .ok_or_else(|| invalid(format!("'{}' must be a number", name)))
+    };
+    let columns = number("columns")?;
+    let rows = number("rows")?;
+    let map = address("map")?;
+    let palette = address("palette")?;
+
+    if columns == 0 || rows == 0 {
+        return Err(invalid("The map needs at least one column and one row"));
+    }
+    let cells = columns.checked_mul(rows);
+    if cells.map_or(true, |cells| cells > 0x10000 - map as u64) {
+        return Err(invalid(format!("A map of {} by {} cells doesn't fit at ${:04X}", columns, rows, map)));
+    }
+    if palette as usize + PALETTE_SIZE > 0x10000 {
+        return Err(invalid(format!("The palette doesn't fit at ${:04X}", palette)));
+    }
+    let tile_size = number("tile_size")?;
+    if tile_size == 0 || tile_size > u32::max_value() as u64 {
+        return Err(invalid(format!("A tile size of {} pixels is out of range", tile_size)));
+    }
+
+    Ok(TileDisplay {
+        map: map,
+        palette: palette,
+        // Both fit in the address space, so they fit in 32 bits
+        columns: columns as u32,
+        rows: rows as u32,
+        tile_size: tile_size as u32,
+        x: json.find("x").and_then(|x| x.as_i64()).unwrap_or(0) as i32,
+        y: json.find("y").and_then(|y| y.as_i64()).unwrap_or(0) as i32,
+        tiles: optional(json, "tiles")?,
+    })
+}
+
+fn sprite_table(json: &Json) -> Result<SpriteTable, LevelError> {
+    let address = parse_number(&required(json, "address")?)
+        .ok_or_else(|| invalid("'address' must be a number, such as \"$0600\""))?;
+    let count = json.find("count").and_then(|count| count.as_u64()).ok_or_else(|| invalid("'count' must be a number"))?;
+    let size = count.checked_mul(SPRITE_ENTRY_SIZE as u64);
+    if size.map_or(true, |size| size > 0x10000 - address as u64) {
+        return Err(invalid(format!("A table of {} sprites doesn't fit at ${:04X}", count, address)));
+    }
+    let images = list(json, "images", |image| {
+        image.as_string().map(|image| image.to_owned()).ok_or_else(|| invalid("Images must be strings"))
+    })?;
+
+    Ok(SpriteTable {
+        address: address,
+        count: count as u32,
+        images: images,
+    })
+}
+
+fn sound_registers(json: &Json) -> Result<Option<u16>, LevelError> {
+    let address = match optional(json, "sound")? {
+        Some(address) => parse_number(&address).ok_or_else(|| invalid("'sound' must be a number, such as \"$D400\""))?,
+        None => return Ok(None),
+    };
+    if address as usize + sound::REGISTERS_SIZE > 0x10000 {
+        return Err(invalid(format!("The sound registers don't fit at ${:04X}", address)));
+    }
+    Ok(Some(address))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use rs6502::{Assembler, Cpu};
+
+    use processor::Processor;
+
+    use super::*;
+
+    /// Parses a manifest made of the fields every level has and the given
+    /// ones
+    fn parse(fields: &str) -> Result<Level, LevelError> {
+        let mut manifest = r#"{ "name": "Test", "source": "level.asm", "load_address": "$C000""#.to_owned();
+        if !fields.is_empty() {
+            manifest.push_str(", ");
+            manifest.push_str(fields);
+        }
+        manifest.push('}');
+        Level::parse(&manifest, PathBuf::from("levels/test"))
+    }
+
+    fn error(fields: &str) -> String {
+        match parse(fields) {
+            Ok(_) => panic!("{} parsed", fields),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_a_minimal_manifest() {
+        let level = parse("").unwrap();
+        assert_eq!(level.name, "Test");
+        assert_eq!(level.id(), "test");
+        assert_eq!(level.source_path(), PathBuf::from("levels/test/level.asm"));
+        assert_eq!(level.load_address, 0xC000);
+        assert!(level.memory.is_empty() && level.cpus.is_empty() && level.win.is_none());
+        assert!(level.display.is_none() && level.sprite_table.is_none() && level.sound.is_none());
+
+        assert_eq!(Level::parse("{}", PathBuf::new()).unwrap_err().to_string(), "Missing 'name'");
+        assert_eq!(error(r#""win": []"#), "'win' must be a string");
+        assert_eq!(error(r#""memory": {}"#), "'memory' must be a list");
+    }
+
+    #[test]
+    fn rejects_endpoints_outside_memory() {
+        assert_eq!(error(r#""cpus": [{ "source": "b.asm", "load_address": "$C000" }],
+                            "shared": [{ "size": 16, "at": [{ "cpu": 0, "address": "$0300" },
+                                                            { "cpu": 1, "address": "$FFF8" }] }]"#),
+                   "shared #0: at #1: $FFF8 is too close to the end of memory");
+        assert_eq!(error(r#""shared": [{ "size": 18446744073709551615, "at": [{ "cpu": 0, "address": "$0300" }] }]"#),
+                   "shared #0: at #0: $0300 is too close to the end of memory");
+        assert_eq!(error(r#""shared": [{ "size": 16, "at": [{ "cpu": 0, "address": "$0300" }] }]"#),
+                   "shared #0: Shared memory must be mapped 'at' two places or more");
+        assert_eq!(error(r#""mailboxes": [{ "from": { "cpu": 0, "address": "$FFFF" },
+                                            "to": { "cpu": 0, "address": "$10" } }]"#),
+                   "mailboxes #0: $FFFF is too close to the end of memory");
+    }
+
+    #[test]
+    fn validates_mailboxes() {
+        let level = parse(r#""cpus": [{ "source": "b.asm", "load_address": "$C000" }],
+                             "mailboxes": [{ "from": { "cpu": 0, "address": "$10" },
+                                             "to": { "cpu": 1, "address": "$20" }, "interrupt": "nmi" }]"#)
+            .unwrap();
+        let mailbox = &level.mailboxes[0];
+        assert_eq!((mailbox.from.cpu, mailbox.from.address, mailbox.to.cpu, mailbox.to.address), (0, 0x10, 1, 0x20));
+        assert_eq!(mailbox.interrupt, Some(Interrupt::Nmi));
+
+        assert_eq!(error(r#""mailboxes": [{ "from": { "cpu": 0, "address": "$10" } }]"#),
+                   "mailboxes #0: Missing 'to'");
+        assert_eq!(error(r#""mailboxes": [{ "from": { "cpu": 0, "address": "$10" },
+                                            "to": { "cpu": 0, "address": "$20" }, "interrupt": "brk" }]"#),
+                   "mailboxes #0: 'interrupt' must be \"irq\" or \"nmi\", found \"brk\"");
+        assert_eq!(error(r#""mailboxes": [{ "from": { "cpu": 0, "address": "$10" },
+                                            "to": { "cpu": 1, "address": "$20" } }]"#),
+                   "There is no cpu 1, the level has 1");
+    }
+
+    #[test]
+    fn validates_mappers() {
+        let mapper = |window: &str, size: &str, register: &str, banks: &str| {
+            error(&format!(r#""mappers": [{{ "window": "{}", "size": "{}", "register": "{}", "banks": {} }}]"#,
+                           window,
+                           size,
+                           register,
+                           banks))
+        };
+        assert_eq!(mapper("$F000", "$2000", "$FF00", r#"[{ "ram": true }]"#),
+                   "mappers #0: A window of 8192 bytes doesn't fit at $F000");
+        assert_eq!(mapper("$8000", "$2000", "$9000", r#"[{ "ram": true }]"#),
+                   "mappers #0: The register at $9000 can't be inside the window");
+        assert_eq!(mapper("$8000", "$2000", "$FF00", "[]"),
+                   "mappers #0: A mapper needs at least one bank");
+        assert_eq!(mapper("$8000", "$2000", "$FF00", "[{}]"),
+                   "mappers #0: banks #0: Expected a 'source' or \"ram\": true");
+    }
+
+    #[test]
+    fn validates_protected_regions() {
+        assert_eq!(error(r#""protected": [{ "start": "$C000", "end": "$BFFF", "access": "read-only" }]"#),
+                   "protected #0: 'end' must not be before 'start'");
+        assert_eq!(error(r#""protected": [{ "start": "$C000", "end": "$FFFF", "access": "hidden" }]"#),
+                   "protected #0: 'access' must be \"read-only\", \"no-execute\" or \"privileged\", found \"hidden\"");
+    }
+
+    #[test]
+    fn rejects_displays_that_do_not_fit() {
+        let display = |columns: &str, rows: &str, map: &str, palette: &str| {
+            parse(&format!(r#""display": {{ "map": "{}", "palette": "{}",
+                                            "columns": {}, "rows": {}, "tile_size": 32 }}"#,
+                           map,
+                           palette,
+                           columns,
+                           rows))
+        };
+        let level = display("20", "15", "$0400", "$0700").unwrap();
+        let tiles = level.display.unwrap();
+        assert_eq!((tiles.columns, tiles.rows, tiles.map, tiles.palette), (20, 15, 0x0400, 0x0700));
+
+        assert_eq!(display("256", "256", "$0001", "$0700").unwrap_err().to_string(),
+                   "display: A map of 256 by 256 cells doesn't fit at $0001");
+        // The number of cells overflows 32 and 64 bits
+        assert_eq!(display("65536", "65536", "$0000", "$0700").unwrap_err().to_string(),
+                   "display: A map of 65536 by 65536 cells doesn't fit at $0000");
+        assert_eq!(display("4294967296", "4294967296", "$0000", "$0700").unwrap_err().to_string(),
+                   "display: A map of 4294967296 by 4294967296 cells doesn't fit at $0000");
+        assert_eq!(display("0", "15", "$0400", "$0700").unwrap_err().to_string(),
+                   "display: The map needs at least one column and one row");
+        assert_eq!(display("20", "15", "$0400", "$FFF0").unwrap_err().to_string(),
+                   "display: The palette doesn't fit at $FFF0");
+    }
+
+    #[test]
+    fn rejects_sprite_tables_and_sound_registers_that_do_not_fit() {
+        assert_eq!(error(r#""sprite_table": { "address": "$FFF8", "count": 2 }"#),
+                   "sprite_table: A table of 2 sprites doesn't fit at $FFF8");
+        assert_eq!(error(r#""sprite_table": { "address": "$0600", "count": 3074457345618258603 }"#),
+                   "sprite_table: A table of 3074457345618258603 sprites doesn't fit at $0600");
+        assert_eq!(error(r#""sprite_table": { "address": "$0600", "count": 1, "images": [1] }"#),
+                   "sprite_table: images #0: Images must be strings");
+        assert_eq!(error(r#""sound": "$FFFE""#), "The sound registers don't fit at $FFFE");
+        assert_eq!(error(r#""sound": "here""#), "'sound' must be a number, such as \"$D400\"");
+    }
+
+    #[test]
+    fn decodes_sprite_entries() {
+        let table = SpriteTable {
+            address: 0x0200,
+            count: 2,
+            images: Vec::new(),
+        };
+        let mut memory = vec![0; 0x10000];
+        memory[0x0200..0x020C]
+            .copy_from_slice(&[0x34, 0x12, 0xF6, 0xFF, 0x03, 0x05, 0x00, 0x00, 0x00, 0x00, 0x01, 0x02]);
+
+        assert_eq!(table.entries(&memory),
+                   vec![SpriteEntry {
+                            x: 0x1234,
+                            y: -10,
+                            image: 3,
+                            visible: true,
+                            flip_horizontal: false,
+                            flip_vertical: true,
+                        },
+                        SpriteEntry {
+                            x: 0,
+                            y: 0,
+                            image: 1,
+                            visible: false,
+                            flip_horizontal: true,
+                            flip_vertical: false,
+                        }]);
+    }
+
+    #[test]
+    fn training_level_draws_the_ship_through_its_sprite_table() {
+        let level = Level::load(Path::new(env!("CARGO_MANIFEST_DIR")).join("levels/training-1/level.json")).unwrap();
+        let table = level.sprite_table.clone().unwrap();
+        assert_eq!(table.images, vec!["ship.png", "ship-flame.png"]);
+
+        let mut cpu = Cpu::new();
+        for segment in Assembler::new().assemble_file(level.source_path(), level.load_address).unwrap() {
+            Processor::load(&mut cpu, &segment.code, segment.address).unwrap();
+        }
+        Processor::reset(&mut cpu);
+        // The ship at $0140, $01FF with the up arrow held
+        cpu.memory_mut()[..5].copy_from_slice(&[0x40, 0x01, 0xFF, 0x01, 38]);
+        for _ in 0..500 {
+            Processor::step(&mut cpu).unwrap();
+        }
+
+        let sprites = table.entries(cpu.memory())
+            .iter()
+            .map(|entry| (entry.x, entry.y, entry.image, entry.visible))
+            .collect::<Vec<_>>();
+        assert_eq!(sprites,
+                   vec![(0x140 - 10, 0x1FF + 150, 1, true), (0x140 + 77, 0x1FF + 150, 1, true), (0x140, 0x1FF, 0, true)]);
+
+        // The flames go out when the key is released
+        cpu.memory_mut()[4] = 0;
+        for _ in 0..500 {
+            Processor::step(&mut cpu).unwrap();
+        }
+        assert!(table.entries(cpu.memory()).iter().take(2).all(|entry| !entry.visible));
+    }
+}
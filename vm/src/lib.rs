@@ -4,17 +4,60 @@ extern crate rs6502;
 extern crate rustc_serialize;
 extern crate app_dirs;
 extern crate sdl2;
+extern crate rhai;
+extern crate hound;
 
 mod console;
+mod monitor;
+mod hud;
 mod position;
 mod text;
 mod config;
 mod command;
+mod arguments;
+mod expression;
+mod symbols;
 mod vm;
 mod game_core;
+mod game_loop;
+mod level;
+mod progress;
+mod statistics;
+mod gdb;
+mod dap;
+mod script;
+mod recording;
+mod reload;
+mod paths;
+mod diagnostic;
+mod interconnect;
+mod mapper;
+mod protection;
+mod processor;
+mod sound;
 
 pub use self::position::Position;
 pub use self::text::Text;
 pub use self::vm::VirtualMachine;
-pub use self::command::{CommandSystem, Command};
+pub use self::monitor::MemoryMonitor;
+pub use self::command::{CommandSystem, Command, CommandResult, UnblockEvent};
+pub use self::arguments::{Argument, Arguments, ArgumentType, Signature};
 pub use self::game_core::GameCore;
+pub use self::game_loop::{Game, GameLoop};
+pub use self::level::{BankContents, Banner, Coprocessor, InputMapping, Level, LevelError, MapperLayout, MemoryWrite, Rule,
+                      SpriteBinding, SpriteEntry, SpriteTable, TileDisplay};
+pub use self::progress::{LevelProgress, Progress};
+pub use self::statistics::Statistics;
+pub use self::gdb::GdbServer;
+pub use self::dap::DapServer;
+pub use self::script::ScriptEngine;
+pub use self::recording::{Input, Recorder, Recording, Replay};
+pub use self::reload::{assemble, SourceWatcher};
+pub use self::diagnostic::Diagnostic;
+pub use self::interconnect::{Endpoint, Interconnect, Interrupt, Mailbox, SharedWindow};
+pub use self::mapper::{Bank, Mapper};
+pub use self::protection::{Access, Fault, Protection, Region, Violation};
+pub use self::processor::{Processor, ADDRESS_SPACE};
+pub use self::sound::{SampleQueue, SoundDevice, SAMPLE_RATE};
+pub use self::symbols::SymbolTable;
+pub use self::expression::{evaluate, ExpressionError};
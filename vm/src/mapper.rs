@@ -0,0 +1,257 @@
+use rs6502::CodeSegment;
+
+use processor::Processor;
+use symbols::SymbolTable;
+
+/// One of the banks a mapper switches between
+#[derive(Debug)]
+pub struct Bank {
+    /// What the bank holds while it isn't mapped. While it is, its contents
+    /// are in the window instead
+    contents: Vec<u8>,
+    /// The code assembled into the bank, to disassemble it
+    pub segments: Vec<CodeSegment>,
+    pub symbols: SymbolTable,
+    /// RAM keeps what was written to the window when it is switched out.
+    /// What was written over ROM stays in the window only until another
+    /// bank is mapped, and the ROM comes back unchanged the next time
+    pub writable: bool,
+}
+
+/// Switches a window of memory between banks, so a level can hold more code
+/// and data than fits in the address space.
+///
+/// Writing a bank number to the register maps that bank into the window.
+/// Bank numbers wrap around the number of banks, like the unused high bits
+/// of a bank register on real hardware.
+#[derive(Debug)]
+pub struct Mapper {
+    pub window: u16,
+    pub size: usize,
+    pub register: u16,
+    banks: Vec<Bank>,
+    selected: usize,
+}
+
+impl Bank {
+    /// A bank of ROM holding the given code, which was assembled at the
+    /// address of the window it is mapped into
+    pub fn rom(segments: Vec<CodeSegment>, symbols: SymbolTable, window: u16, size: usize) -> Result<Bank, String> {
+        let mut contents = vec![0; size];
+        for segment in &segments {
+            let start = segment.address as usize;
+            if start < window as usize || start + segment.code.len() > window as usize + size {
+                return Err(format!("Code at ${:04X} doesn't fit the window at ${:04X}", segment.address, window));
+            }
+            let offset = start - window as usize;
+            contents[offset..offset + segment.code.len()].copy_from_slice(&segment.code);
+        }
+
+        Ok(Bank {
+            contents: contents,
+            segments: segments,
+            symbols: symbols,
+            writable: false,
+        })
+    }
+
+    /// A bank of empty RAM
+    pub fn ram(size: usize) -> Bank {
+        Bank {
+            contents: vec![0; size],
+            segments: Vec::new(),
+            symbols: SymbolTable::new(),
+            writable: true,
+        }
+    }
+}
+
+impl Mapper {
+    pub fn new(window: u16, size: usize, register: u16, banks: Vec<Bank>) -> Mapper {
+        Mapper {
+            window: window,
+            size: size,
+            register: register,
+            banks: banks,
+            selected: 0,
+        }
+    }
+
+    pub fn banks(&self) -> &[Bank] {
+        &self.banks
+    }
+
+    /// The bank mapped into the window
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// Whether the address is inside the window
+    pub fn contains(&self, address: usize) -> bool {
+        address >= self.window as usize && address < self.window as usize + self.size
+    }
+
+    /// Maps the first bank into the window and clears the register
+    pub fn install(&mut self, cpu: &mut Processor) {
+        self.selected = 0;
+        self.copy_in(cpu);
+        cpu.memory_mut()[self.register as usize] = 0;
+    }
+
+    /// Switches banks if a different one was written to the register since
+    /// the last sync. Returns true if it did
+    pub fn sync(&mut self, cpu: &mut Processor) -> bool {
+        if self.banks.is_empty() {
+            return false;
+        }
+        let bank = cpu.memory()[self.register as usize] as usize % self.banks.len();
+        if bank == self.selected {
+            return false;
+        }
+
+        let start = self.window as usize;
+        if self.banks[self.selected].writable {
+            let selected = self.selected;
+            self.banks[selected].contents.copy_from_slice(&cpu.memory()[start..start + self.size]);
+        }
+        self.selected = bank;
+        self.copy_in(cpu);
+        true
+    }
+
+    /// Replaces a bank, mapping the new one into the window straight away
+    /// if the old one was mapped. Returns false if there is no such bank
+    pub fn replace(&mut self, index: usize, bank: Bank, cpu: &mut Processor) -> bool {
+        if index >= self.banks.len() {
+            return false;
+        }
+        self.banks[index] = bank;
+        if index == self.selected {
+            self.copy_in(cpu);
+        }
+        true
+    }
+
+    /// Reads a byte of a bank, whether it is mapped or not
+    pub fn read(&self, bank: usize, address: usize, memory: &[u8]) -> u8 {
+        if bank == self.selected {
+            memory[address]
+        } else {
+            self.banks[bank].contents[address - self.window as usize]
+        }
+    }
+
+    fn copy_in(&self, cpu: &mut Processor) {
+        let start = self.window as usize;
+        if let Some(bank) = self.banks.get(self.selected) {
+            cpu.memory_mut()[start..start + self.size].copy_from_slice(&bank.contents);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use processor::tests::StubProcessor;
+
+    use super::*;
+
+    const WINDOW: u16 = 0x8000;
+    const SIZE: usize = 0x100;
+    const REGISTER: u16 = 0x7FFF;
+
+    fn rom(code: Vec<u8>) -> Bank {
+        let segments = vec![CodeSegment {
+                                address: WINDOW,
+                                code: code,
+                            }];
+        Bank::rom(segments, SymbolTable::new(), WINDOW, SIZE).unwrap()
+    }
+
+    /// A mapper with a bank of RAM and two of ROM, installed in a CPU
+    fn mapper(cpu: &mut StubProcessor) -> Mapper {
+        let mut mapper = Mapper::new(WINDOW,
+                                     SIZE,
+                                     REGISTER,
+                                     vec![Bank::ram(SIZE), rom(vec![0xA9, 0x01]), rom(vec![0xA9, 0x02])]);
+        mapper.install(cpu);
+        mapper
+    }
+
+    fn switch(mapper: &mut Mapper, cpu: &mut StubProcessor, bank: u8) -> bool {
+        cpu.memory[REGISTER as usize] = bank;
+        mapper.sync(cpu)
+    }
+
+    #[test]
+    fn wraps_bank_numbers_around_the_number_of_banks() {
+        let mut cpu = StubProcessor::new();
+        let mut mapper = mapper(&mut cpu);
+
+        assert!(switch(&mut mapper, &mut cpu, 5));
+        assert_eq!(2, mapper.selected());
+        assert_eq!(0x02, cpu.memory[WINDOW as usize + 1]);
+
+        assert!(!switch(&mut mapper, &mut cpu, 2));
+    }
+
+    #[test]
+    fn ram_keeps_writes_across_switches_and_rom_does_not() {
+        let mut cpu = StubProcessor::new();
+        let mut mapper = mapper(&mut cpu);
+
+        cpu.memory[WINDOW as usize] = 0x42;
+        switch(&mut mapper, &mut cpu, 1);
+        assert_eq!(0xA9, cpu.memory[WINDOW as usize]);
+
+        // Writes over ROM last while it is mapped
+        cpu.memory[WINDOW as usize] = 0x43;
+        switch(&mut mapper, &mut cpu, 0);
+        assert_eq!(0x42, cpu.memory[WINDOW as usize]);
+
+        switch(&mut mapper, &mut cpu, 1);
+        assert_eq!(0xA9, cpu.memory[WINDOW as usize]);
+    }
+
+    #[test]
+    fn replacing_the_mapped_bank_maps_the_new_one() {
+        let mut cpu = StubProcessor::new();
+        let mut mapper = mapper(&mut cpu);
+        switch(&mut mapper, &mut cpu, 1);
+
+        assert!(mapper.replace(1, rom(vec![0xEA]), &mut cpu));
+        assert_eq!(0xEA, cpu.memory[WINDOW as usize]);
+
+        assert!(mapper.replace(2, rom(vec![0x60]), &mut cpu));
+        assert_eq!(0xEA, cpu.memory[WINDOW as usize]);
+
+        assert!(!mapper.replace(3, rom(vec![0x60]), &mut cpu));
+    }
+
+    #[test]
+    fn rejects_rom_code_outside_of_the_window() {
+        let before = vec![CodeSegment {
+                              address: WINDOW - 1,
+                              code: vec![0xEA, 0xEA],
+                          }];
+        let after = vec![CodeSegment {
+                             address: WINDOW + SIZE as u16 - 1,
+                             code: vec![0xEA, 0xEA],
+                         }];
+
+        assert_eq!("Code at $7FFF doesn't fit the window at $8000",
+                   Bank::rom(before, SymbolTable::new(), WINDOW, SIZE).unwrap_err());
+        assert!(Bank::rom(after, SymbolTable::new(), WINDOW, SIZE).is_err());
+    }
+
+    #[test]
+    fn reads_mapped_banks_from_memory_and_others_from_the_bank() {
+        let mut cpu = StubProcessor::new();
+        let mut mapper = mapper(&mut cpu);
+        switch(&mut mapper, &mut cpu, 1);
+        cpu.memory[WINDOW as usize + 1] = 0x07;
+
+        assert_eq!(0x07, mapper.read(1, WINDOW as usize + 1, &cpu.memory));
+        assert_eq!(0x02, mapper.read(2, WINDOW as usize + 1, &cpu.memory));
+        assert_eq!(0x00, mapper.read(0, WINDOW as usize + 1, &cpu.memory));
+    }
+}
@@ -0,0 +1,382 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use sdl2::gfx::primitives::DrawRenderer;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{Renderer, Texture, TextureQuery};
+use sdl2::ttf::{Font, Sdl2TtfContext, STYLE_BOLD};
+
+const BACKGROUND_COLOR: Color = Color::RGBA(0, 0, 0, 192);
+const BORDER_COLOR: Color = Color::RGBA(255, 255, 255, 64);
+const FONT_COLOR: Color = Color::RGBA(45, 200, 45, 255);
+const HEADER_COLOR: Color = Color::RGBA(200, 200, 200, 255);
+const CHANGED_COLOR: Color = Color::RGBA(255, 220, 60, 255);
+const FONT_SIZE: u16 = 14;
+
+const PADDING: i32 = 10;
+const BYTES_PER_ROW: usize = 8;
+
+/// The number of frames a byte stays highlighted after it changed
+const HIGHLIGHT_FRAMES: u8 = 30;
+
+/// The largest range that can be watched at once
+pub const MAX_RANGE_LENGTH: usize = 0x100;
+
+/// How many ranges can be watched at once. Long ranges don't all fit on
+/// screen together, so the panel is cut off at the bottom of the window
+pub const MAX_RANGES: usize = 4;
+
+/// What a piece of text in the panel is, which decides its color
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Ink {
+    Header,
+    Plain,
+    Changed,
+}
+
+struct WatchedRange {
+    start: usize,
+    end: usize,
+    previous: Vec<u8>,
+    highlight: Vec<u8>,
+}
+
+impl WatchedRange {
+    /// The header and the rows of bytes the range takes up in the panel
+    fn rows(&self) -> usize {
+        1 + (self.previous.len() + BYTES_PER_ROW - 1) / BYTES_PER_ROW
+    }
+
+    fn update(&mut self, memory: &[u8]) {
+        let current = &memory[self.start..self.end + 1];
+        for (index, byte) in current.iter().enumerate() {
+            if self.previous[index] != *byte {
+                self.previous[index] = *byte;
+                self.highlight[index] = HIGHLIGHT_FRAMES;
+            } else if self.highlight[index] > 0 {
+                self.highlight[index] -= 1;
+            }
+        }
+    }
+}
+
+/// How many of the rows of the watched ranges fit in the panel
+#[derive(Debug, PartialEq)]
+struct Layout {
+    /// Rows drawn, headers included
+    shown_rows: usize,
+    /// Ranges with at least their header drawn
+    shown_ranges: usize,
+    hidden_rows: usize,
+}
+
+impl Layout {
+    /// Fits the rows each range takes up into `fitting` lines. Rows that
+    /// don't fit are left out, with the last line saying how many there are
+    fn new(rows: &[usize], fitting: i32) -> Layout {
+        let total_rows = rows.iter().sum::<usize>();
+        let shown_rows = if total_rows as i32 > fitting {
+            (fitting - 1).max(0) as usize
+        } else {
+            total_rows
+        };
+
+        let mut shown_ranges = 0;
+        let mut counted = 0;
+        while counted < shown_rows {
+            counted += rows[shown_ranges];
+            shown_ranges += 1;
+        }
+
+        Layout {
+            shown_rows: shown_rows,
+            shown_ranges: shown_ranges,
+            hidden_rows: total_rows - shown_rows,
+        }
+    }
+
+    /// The lines drawn, including the one counting hidden rows
+    fn lines(&self) -> usize {
+        if self.hidden_rows > 0 {
+            self.shown_rows + 1
+        } else {
+            self.shown_rows
+        }
+    }
+}
+
+/// A panel rendered next to the console that shows the contents of the
+/// watched memory ranges, highlighting bytes that recently changed
+pub struct MemoryMonitor<'a> {
+    ranges: Vec<WatchedRange>,
+    /// The panel isn't drawn without a font
+    font: Option<Font<'a>>,
+    /// Text rendered so far, with its width and height. There are only so
+    /// many bytes and addresses, so the same textures are drawn every frame
+    textures: HashMap<(String, Ink), (Texture, u32, u32)>,
+    x: i32,
+}
+
+impl<'a> MemoryMonitor<'a> {
+    pub fn new(ttf_context: &'a Sdl2TtfContext,
+               renderer: &mut Renderer,
+               font_file: &'a str)
+               -> MemoryMonitor<'a> {
+        let (width, _) = renderer.window().unwrap().size();
+
+        let mut font = ttf_context.load_font(Path::new(font_file), FONT_SIZE).unwrap();
+        font.set_style(STYLE_BOLD);
+
+        MemoryMonitor {
+            ranges: Vec::new(),
+            font: Some(font),
+            textures: HashMap::new(),
+            // The console takes up the left half of the window
+            x: (width / 2) as i32 + PADDING,
+        }
+    }
+
+    /// Creates a monitor that watches ranges but isn't drawn
+    pub fn headless() -> MemoryMonitor<'a> {
+        MemoryMonitor {
+            ranges: Vec::new(),
+            font: None,
+            textures: HashMap::new(),
+            x: 0,
+        }
+    }
+
+    /// Starts watching the memory between `start` and `end` (inclusive).
+    /// Returns false if `MAX_RANGES` are watched already
+    pub fn add_range(&mut self, start: usize, end: usize, memory: &[u8]) -> bool {
+        if self.ranges.len() >= MAX_RANGES {
+            return false;
+        }
+        let previous = memory[start..end + 1].to_vec();
+        let length = previous.len();
+
+        self.ranges.push(WatchedRange {
+            start: start,
+            end: end,
+            previous: previous,
+            highlight: vec![0; length],
+        });
+        true
+    }
+
+    /// Stops watching the range at the given index, returning false if
+    /// there is no such range
+    pub fn remove_range(&mut self, index: usize) -> bool {
+        if index < self.ranges.len() {
+            self.ranges.remove(index);
+            // The headers of the ranges after it are numbered differently now
+            self.textures.clear();
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.ranges.clear();
+        self.textures.clear();
+    }
+
+    /// The start and end address of every watched range
+    pub fn ranges(&self) -> Vec<(usize, usize)> {
+        self.ranges.iter().map(|range| (range.start, range.end)).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Compares the watched ranges against the current memory. Should be
+    /// called once per frame
+    pub fn update(&mut self, memory: &[u8]) {
+        for range in &mut self.ranges {
+            range.update(memory);
+        }
+    }
+
+    pub fn render(&mut self, renderer: &mut Renderer) {
+        let font = match self.font {
+            Some(ref font) => font,
+            None => return,
+        };
+        if self.ranges.is_empty() {
+            return;
+        }
+
+        let line_height = font.height();
+        let byte_width = font.size_of("00 ").unwrap().0 as i32;
+        let address_width = font.size_of("0000: ").unwrap().0 as i32;
+
+        let rows = self.ranges.iter().map(WatchedRange::rows).collect::<Vec<_>>();
+        let (_, window_height) = renderer.window().unwrap().size();
+        let fitting = (window_height as i32 - PADDING * (self.ranges.len() as i32 + 3)) / line_height;
+        let layout = Layout::new(&rows, fitting);
+
+        let width = address_width + byte_width * BYTES_PER_ROW as i32 + PADDING * 2;
+        let height = layout.lines() as i32 * line_height + (layout.shown_ranges as i32 + 1) * PADDING;
+
+        renderer.box_(self.x as i16,
+                  PADDING as i16,
+                  (self.x + width) as i16,
+                  (PADDING + height) as i16,
+                  BACKGROUND_COLOR)
+            .unwrap();
+        renderer.rectangle(self.x as i16,
+                       PADDING as i16,
+                       (self.x + width) as i16,
+                       (PADDING + height) as i16,
+                       BORDER_COLOR)
+            .unwrap();
+
+        let x = self.x + PADDING;
+        let mut y = PADDING * 2;
+        let mut remaining = layout.shown_rows;
+        let textures = &mut self.textures;
+        for (index, range) in self.ranges.iter().enumerate().take(layout.shown_ranges) {
+            render_text(renderer,
+                        font,
+                        textures,
+                        format!("#{} {:04X}-{:04X}", index, range.start, range.end),
+                        Ink::Header,
+                        x,
+                        y);
+            y += line_height;
+            remaining -= 1;
+
+            for (row, chunk) in range.previous.chunks(BYTES_PER_ROW).enumerate().take(remaining) {
+                let address = range.start + row * BYTES_PER_ROW;
+                render_text(renderer, font, textures, format!("{:04X}:", address), Ink::Plain, x, y);
+
+                for (column, byte) in chunk.iter().enumerate() {
+                    let changed = range.highlight[row * BYTES_PER_ROW + column] > 0;
+                    let ink = if changed { Ink::Changed } else { Ink::Plain };
+                    render_text(renderer,
+                                font,
+                                textures,
+                                format!("{:02X}", byte),
+                                ink,
+                                x + address_width + column as i32 * byte_width,
+                                y);
+                }
+                y += line_height;
+                remaining -= 1;
+            }
+            y += PADDING;
+        }
+
+        if layout.hidden_rows > 0 {
+            render_text(renderer,
+                        font,
+                        textures,
+                        format!("... {} more rows", layout.hidden_rows),
+                        Ink::Header,
+                        x,
+                        y - PADDING);
+        }
+    }
+}
+
+/// Draws the text, rendering it into a texture the first time it is drawn
+/// in the given ink
+fn render_text(renderer: &mut Renderer,
+               font: &Font,
+               textures: &mut HashMap<(String, Ink), (Texture, u32, u32)>,
+               text: String,
+               ink: Ink,
+               x: i32,
+               y: i32) {
+    let key = (text, ink);
+    if !textures.contains_key(&key) {
+        let color = match ink {
+            Ink::Header => HEADER_COLOR,
+            Ink::Plain => FONT_COLOR,
+            Ink::Changed => CHANGED_COLOR,
+        };
+        let surface = font.render(&key.0)
+            .blended(color)
+            .unwrap();
+        let texture = renderer.create_texture_from_surface(&surface)
+            .unwrap();
+        let TextureQuery { width, height, .. } = texture.query();
+        textures.insert(key.clone(), (texture, width, height));
+    }
+
+    let (ref texture, width, height) = textures[&key];
+    renderer.copy(texture, None, Some(Rect::new(x, y, width, height))).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn takes_a_header_and_a_row_per_eight_bytes() {
+        let mut monitor = MemoryMonitor::headless();
+        let memory = vec![0; 0x10000];
+        monitor.add_range(0x0200, 0x0200, &memory);
+        monitor.add_range(0x0200, 0x0207, &memory);
+        monitor.add_range(0x0200, 0x0208, &memory);
+        monitor.add_range(0x0200, 0x02FF, &memory);
+
+        let rows = monitor.ranges.iter().map(WatchedRange::rows).collect::<Vec<_>>();
+
+        assert_eq!(vec![2, 2, 3, 33], rows);
+    }
+
+    #[test]
+    fn watches_no_more_than_the_maximum_number_of_ranges() {
+        let mut monitor = MemoryMonitor::headless();
+        let memory = vec![0; 0x10000];
+        for _ in 0..MAX_RANGES {
+            assert!(monitor.add_range(0x0200, 0x0210, &memory));
+        }
+
+        assert!(!monitor.add_range(0x0200, 0x0210, &memory));
+        assert_eq!(MAX_RANGES, monitor.ranges().len());
+    }
+
+    #[test]
+    fn shows_every_row_that_fits() {
+        let layout = Layout::new(&[2, 3], 5);
+
+        assert_eq!(Layout {
+                       shown_rows: 5,
+                       shown_ranges: 2,
+                       hidden_rows: 0,
+                   },
+                   layout);
+        assert_eq!(5, layout.lines());
+    }
+
+    #[test]
+    fn counts_the_rows_that_do_not_fit_on_the_last_line() {
+        let layout = Layout::new(&[2, 33, 2], 10);
+
+        assert_eq!(Layout {
+                       shown_rows: 9,
+                       shown_ranges: 2,
+                       hidden_rows: 28,
+                   },
+                   layout);
+        assert_eq!(10, layout.lines());
+    }
+
+    #[test]
+    fn hides_every_row_in_a_window_too_small_for_any() {
+        let layout = Layout::new(&[2], -3);
+
+        assert_eq!(Layout {
+                       shown_rows: 0,
+                       shown_ranges: 0,
+                       hidden_rows: 2,
+                   },
+                   layout);
+        assert_eq!(1, layout.lines());
+    }
+}
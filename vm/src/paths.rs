@@ -0,0 +1,15 @@
+use std::path::Path;
+
+/// Whether two paths lead to the same file. Paths that can't be resolved,
+/// such as those of deleted files, are the same only if they are equal
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// The name of the file a path leads to, for messages
+pub fn file_name(path: &Path) -> String {
+    path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default()
+}
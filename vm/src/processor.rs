@@ -0,0 +1,419 @@
+use rs6502::{Cpu, Disassembler};
+
+const NMI_VECTOR: usize = 0xFFFA;
+const IRQ_VECTOR: usize = 0xFFFE;
+
+/// Where the stack page starts
+const STACK_START: usize = 0x100;
+
+/// The size of the memory of every CPU, a 16 bit address space
+pub const ADDRESS_SPACE: usize = 0x10000;
+
+/// A CPU core the VM can run and the debuggers can inspect.
+///
+/// The VM only talks to its CPUs through this trait, so cores other than
+/// the 6502 can be plugged in with `VirtualMachine::new` or `add_processor`
+/// and still work with the console, the monitor and the remote debuggers.
+/// Those have to address 64K like the 6502 does: the breakpoints, levels
+/// and banks all assume it, so `memory` must be exactly `ADDRESS_SPACE`
+/// bytes long, which the VM checks as a CPU is added.
+pub trait Processor {
+    /// Executes one instruction, returning how many cycles it took
+    fn step(&mut self) -> Result<u8, String>;
+
+    /// Puts the registers back to their power on state, and the program
+    /// counter at the reset vector
+    fn reset(&mut self);
+
+    /// Copies code into memory at the given address
+    fn load(&mut self, code: &[u8], address: u16) -> Result<(), String>;
+
+    fn memory(&self) -> &[u8];
+
+    fn memory_mut(&mut self) -> &mut [u8];
+
+    /// The names and values of the registers, ending with the program
+    /// counter
+    fn registers(&self) -> Vec<(&'static str, u16)>;
+
+    /// Sets a register by the name `registers` gives it. Returns false if
+    /// there is no such register
+    fn set_register(&mut self, name: &str, value: u16) -> bool;
+
+    /// The letter, name and state of each status flag
+    fn flags(&self) -> Vec<(char, &'static str, bool)>;
+
+    /// Sets a status flag by its letter. Returns false if there is no such
+    /// flag
+    fn set_flag(&mut self, letter: char, set: bool) -> bool;
+
+    fn pc(&self) -> u16;
+
+    fn set_pc(&mut self, pc: u16);
+
+    /// Disassembles code loaded at the given address into lines of text,
+    /// each with its offset from that address
+    fn disassemble(&self, code: &[u8], address: u16) -> Vec<(String, u16)>;
+
+    /// The addresses the next instruction writes to. Cores that can't tell
+    /// return None, as if it could write anywhere
+    fn writes(&self) -> Option<Vec<u16>> {
+        None
+    }
+
+    /// Whether the code has masked interrupts
+    fn interrupts_disabled(&self) -> bool {
+        false
+    }
+
+    /// Raises a maskable interrupt. Returns whether it was taken, which it
+    /// isn't while interrupts are masked or by cores without interrupts
+    fn irq(&mut self) -> bool {
+        false
+    }
+
+    /// Raises a non maskable interrupt. Returns whether it was taken, which
+    /// it isn't by cores without interrupts
+    fn nmi(&mut self) -> bool {
+        false
+    }
+
+    /// The value of a register by the name `registers` gives it
+    fn register(&self, name: &str) -> Option<u16> {
+        self.registers().into_iter().find(|&(register, _)| register == name).map(|(_, value)| value)
+    }
+}
+
+impl Processor for Cpu {
+    fn step(&mut self) -> Result<u8, String> {
+        Cpu::step(self).map_err(|err| format!("{:?}", err))
+    }
+
+    fn reset(&mut self) {
+        Cpu::reset(self)
+    }
+
+    fn load(&mut self, code: &[u8], address: u16) -> Result<(), String> {
+        Cpu::load(self, code, address).map_err(|err| format!("{:?}", err))
+    }
+
+    fn memory(&self) -> &[u8] {
+        &self.memory[..]
+    }
+
+    fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.memory[..]
+    }
+
+    fn registers(&self) -> Vec<(&'static str, u16)> {
+        vec![("A", self.registers.A as u16),
+             ("X", self.registers.X as u16),
+             ("Y", self.registers.Y as u16),
+             ("S", self.stack.pointer as u16),
+             ("PC", self.registers.PC)]
+    }
+
+    fn set_register(&mut self, name: &str, value: u16) -> bool {
+        match name {
+            "A" => self.registers.A = value as u8,
+            "X" => self.registers.X = value as u8,
+            "Y" => self.registers.Y = value as u8,
+            "S" => self.stack.pointer = (value & 0xFF) as usize,
+            "PC" => self.registers.PC = value,
+            _ => return false,
+        }
+        true
+    }
+
+    /// The flags in NV-BDIZC order, the order of the bits in the status
+    /// register
+    fn flags(&self) -> Vec<(char, &'static str, bool)> {
+        let flags = &self.flags;
+        vec![('N', "Sign", flags.sign),
+             ('V', "Overflow", flags.overflow),
+             ('-', "Unused", flags.unused),
+             ('B', "Break", flags.breakpoint),
+             ('D', "Decimal mode", flags.decimal),
+             ('I', "Interrupts disabled", flags.interrupt_disabled),
+             ('Z', "Zero", flags.zero),
+             ('C', "Carry", flags.carry)]
+    }
+
+    fn set_flag(&mut self, letter: char, set: bool) -> bool {
+        let flags = &mut self.flags;
+        match letter.to_ascii_uppercase() {
+            'N' => flags.sign = set,
+            'V' => flags.overflow = set,
+            'B' => flags.breakpoint = set,
+            'D' => flags.decimal = set,
+            'I' => flags.interrupt_disabled = set,
+            'Z' => flags.zero = set,
+            'C' => flags.carry = set,
+            _ => return false,
+        }
+        true
+    }
+
+    fn pc(&self) -> u16 {
+        self.registers.PC
+    }
+
+    fn set_pc(&mut self, pc: u16) {
+        self.registers.PC = pc;
+    }
+
+    fn disassemble(&self, code: &[u8], address: u16) -> Vec<(String, u16)> {
+        Disassembler::with_offset(address).disassemble_with_addresses(code)
+    }
+
+    fn writes(&self) -> Option<Vec<u16>> {
+        let memory = self.memory();
+        let byte = |address: u16| memory[address as usize] as u16;
+        let word = |address: u16| byte(address) | byte(address.wrapping_add(1)) << 8;
+        // Pushes go to the top of the stack and down from there
+        let stack = |offset: usize| (STACK_START + (self.stack.pointer.wrapping_sub(offset) & 0xFF)) as u16;
+
+        let pc = self.registers.PC;
+        let operand = pc.wrapping_add(1);
+        let x = self.registers.X as u16;
+        let y = self.registers.Y as u16;
+        let address = match byte(pc) {
+            // PHA, PHP
+            0x48 | 0x08 => return Some(vec![stack(0)]),
+            // JSR
+            0x20 => return Some(vec![stack(1), stack(0)]),
+            // BRK
+            0x00 => return Some(vec![stack(2), stack(1), stack(0)]),
+            // Stores, increments, decrements and shifts, by addressing mode
+            0x85 | 0x86 | 0x84 | 0xE6 | 0xC6 | 0x06 | 0x46 | 0x26 | 0x66 => byte(operand),
+            0x95 | 0x94 | 0xF6 | 0xD6 | 0x16 | 0x56 | 0x36 | 0x76 => (byte(operand) + x) & 0xFF,
+            0x96 => (byte(operand) + y) & 0xFF,
+            0x8D | 0x8E | 0x8C | 0xEE | 0xCE | 0x0E | 0x4E | 0x2E | 0x6E => word(operand),
+            0x9D | 0xFE | 0xDE | 0x1E | 0x5E | 0x3E | 0x7E => word(operand).wrapping_add(x),
+            0x99 => word(operand).wrapping_add(y),
+            0x81 => word((byte(operand) + x) & 0xFF),
+            0x91 => word(byte(operand)).wrapping_add(y),
+            _ => return Some(Vec::new()),
+        };
+        Some(vec![address])
+    }
+
+    fn interrupts_disabled(&self) -> bool {
+        self.flags.interrupt_disabled
+    }
+
+    fn irq(&mut self) -> bool {
+        // The interrupt is ignored while masked, or without a handler
+        if self.flags.interrupt_disabled || vector(self.memory(), IRQ_VECTOR) == 0 {
+            return false;
+        }
+        Cpu::irq(self);
+        true
+    }
+
+    fn nmi(&mut self) -> bool {
+        if vector(self.memory(), NMI_VECTOR) == 0 {
+            return false;
+        }
+        Cpu::nmi(self);
+        true
+    }
+}
+
+/// The handler address an interrupt vector points at
+fn vector(memory: &[u8], address: usize) -> u16 {
+    memory[address] as u16 | (memory[address + 1] as u16) << 8
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{Processor, ADDRESS_SPACE};
+    use rs6502::Cpu;
+
+    /// A CPU that runs no code of its own. Tests change its memory and
+    /// registers the way an instruction would
+    pub struct StubProcessor {
+        pub memory: Vec<u8>,
+        pub pc: u16,
+        pub stack: u16,
+        pub interrupts_disabled: bool,
+        /// The addresses the next instruction writes to
+        pub writes: Option<Vec<u16>>,
+        /// Where interrupts jump to. Without one they aren't taken
+        pub handler: Option<u16>,
+    }
+
+    impl StubProcessor {
+        pub fn new() -> StubProcessor {
+            StubProcessor {
+                memory: vec![0; ADDRESS_SPACE],
+                pc: 0x0600,
+                stack: 0xFF,
+                interrupts_disabled: false,
+                writes: Some(Vec::new()),
+                handler: None,
+            }
+        }
+
+        /// Pops what an interrupt pushed and returns to the given address
+        pub fn rti(&mut self, pc: u16) {
+            self.stack += 3;
+            self.pc = pc;
+            self.interrupts_disabled = false;
+        }
+
+        fn interrupt(&mut self) -> bool {
+            match self.handler {
+                Some(handler) => {
+                    self.stack -= 3;
+                    self.pc = handler;
+                    self.interrupts_disabled = true;
+                    true
+                }
+                None => false,
+            }
+        }
+    }
+
+    impl Processor for StubProcessor {
+        fn step(&mut self) -> Result<u8, String> {
+            self.pc = self.pc.wrapping_add(1);
+            Ok(2)
+        }
+
+        fn reset(&mut self) {
+            *self = StubProcessor::new();
+        }
+
+        fn load(&mut self, code: &[u8], address: u16) -> Result<(), String> {
+            let start = address as usize;
+            self.memory[start..start + code.len()].copy_from_slice(code);
+            Ok(())
+        }
+
+        fn memory(&self) -> &[u8] {
+            &self.memory
+        }
+
+        fn memory_mut(&mut self) -> &mut [u8] {
+            &mut self.memory
+        }
+
+        fn registers(&self) -> Vec<(&'static str, u16)> {
+            vec![("S", self.stack), ("PC", self.pc)]
+        }
+
+        fn set_register(&mut self, name: &str, value: u16) -> bool {
+            match name {
+                "S" => self.stack = value,
+                "PC" => self.pc = value,
+                _ => return false,
+            }
+            true
+        }
+
+        fn flags(&self) -> Vec<(char, &'static str, bool)> {
+            vec![('I', "Interrupts disabled", self.interrupts_disabled)]
+        }
+
+        fn set_flag(&mut self, letter: char, set: bool) -> bool {
+            if letter != 'I' {
+                return false;
+            }
+            self.interrupts_disabled = set;
+            true
+        }
+
+        fn pc(&self) -> u16 {
+            self.pc
+        }
+
+        fn set_pc(&mut self, pc: u16) {
+            self.pc = pc;
+        }
+
+        fn disassemble(&self, _code: &[u8], _address: u16) -> Vec<(String, u16)> {
+            Vec::new()
+        }
+
+        fn writes(&self) -> Option<Vec<u16>> {
+            self.writes.clone()
+        }
+
+        fn interrupts_disabled(&self) -> bool {
+            self.interrupts_disabled
+        }
+
+        fn irq(&mut self) -> bool {
+            !self.interrupts_disabled && self.interrupt()
+        }
+
+        fn nmi(&mut self) -> bool {
+            self.interrupt()
+        }
+    }
+
+    /// A 6502 about to run the given instruction at $0600, with X at $04,
+    /// Y at $10 and a pointer to $1234 at $80 in the zero page
+    fn cpu(instruction: &[u8]) -> Cpu {
+        let mut cpu = Cpu::new();
+        Processor::load(&mut cpu, instruction, 0x0600).unwrap();
+        cpu.memory[0x80] = 0x34;
+        cpu.memory[0x81] = 0x12;
+        cpu.memory[0x84] = 0x78;
+        cpu.memory[0x85] = 0x56;
+        Processor::set_pc(&mut cpu, 0x0600);
+        cpu.set_register("X", 0x04);
+        cpu.set_register("Y", 0x10);
+        cpu
+    }
+
+    fn writes(instruction: &[u8]) -> Option<Vec<u16>> {
+        Processor::writes(&cpu(instruction))
+    }
+
+    #[test]
+    fn stores_to_the_zero_page() {
+        assert_eq!(Some(vec![0x0042]), writes(&[0x85, 0x42]));
+        assert_eq!(Some(vec![0x0042]), writes(&[0x86, 0x42]));
+        assert_eq!(Some(vec![0x0042]), writes(&[0x84, 0x42]));
+    }
+
+    #[test]
+    fn stores_to_the_zero_page_indexed_wrap_around_within_it() {
+        assert_eq!(Some(vec![0x0046]), writes(&[0x95, 0x42]));
+        assert_eq!(Some(vec![0x0046]), writes(&[0x94, 0x42]));
+        assert_eq!(Some(vec![0x0052]), writes(&[0x96, 0x42]));
+        assert_eq!(Some(vec![0x0002]), writes(&[0x95, 0xFE]));
+        assert_eq!(Some(vec![0x000E]), writes(&[0x96, 0xFE]));
+    }
+
+    #[test]
+    fn stores_to_absolute_addresses() {
+        assert_eq!(Some(vec![0x2000]), writes(&[0x8D, 0x00, 0x20]));
+        assert_eq!(Some(vec![0x2000]), writes(&[0x8E, 0x00, 0x20]));
+        assert_eq!(Some(vec![0x2000]), writes(&[0x8C, 0x00, 0x20]));
+        assert_eq!(Some(vec![0x2004]), writes(&[0x9D, 0x00, 0x20]));
+        assert_eq!(Some(vec![0x2010]), writes(&[0x99, 0x00, 0x20]));
+    }
+
+    #[test]
+    fn stores_through_zero_page_pointers() {
+        // (zp,X) reads the pointer at $7C + X = $80
+        assert_eq!(Some(vec![0x1234]), writes(&[0x81, 0x7C]));
+        // (zp),Y adds Y to the pointer at $84
+        assert_eq!(Some(vec![0x5688]), writes(&[0x91, 0x84]));
+    }
+
+    #[test]
+    fn pushes_write_to_the_top_of_the_stack() {
+        assert_eq!(Some(vec![0x01FF]), writes(&[0x48]));
+        assert_eq!(Some(vec![0x01FE, 0x01FF]), writes(&[0x20, 0x00, 0x20]));
+    }
+
+    #[test]
+    fn other_instructions_write_nothing() {
+        assert_eq!(Some(Vec::new()), writes(&[0xA9, 0x01]));
+        assert_eq!(Some(Vec::new()), writes(&[0xAD, 0x00, 0x20]));
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use app_dirs::{app_root, AppDataType};
+use rustc_serialize::json;
+
+use config::{ConfigError, APP_INFO};
+use statistics::Statistics;
+
+const PROGRESS_FILE: &'static str = "progress.json";
+/// What progress that can't be read is kept as, next to the progress file
+const BACKUP_EXTENSION: &'static str = "json.bak";
+
+/// How far the player got in each level, kept in the user data directory
+/// between runs
+#[derive(RustcDecodable, RustcEncodable, Debug, Default)]
+pub struct Progress {
+    levels: BTreeMap<String, LevelProgress>,
+}
+
+#[derive(RustcDecodable, RustcEncodable, Debug, Default)]
+pub struct LevelProgress {
+    pub passed: bool,
+    /// The statistics of the run with the lowest score, the par to beat
+    pub best: Option<Statistics>,
+}
+
+impl Progress {
+    /// Loads the saved progress. Missing progress starts over, and so does
+    /// progress that can't be read, after it is backed up so the next save
+    /// doesn't lose it
+    pub fn load() -> Progress {
+        match Progress::path() {
+            Some(path) => Progress::load_from(&path),
+            None => Progress::default(),
+        }
+    }
+
+    fn load_from(path: &Path) -> Progress {
+        let mut buffer = String::new();
+        if File::open(path).and_then(|mut file| file.read_to_string(&mut buffer)).is_err() {
+            return Progress::default();
+        }
+
+        match json::decode(&buffer) {
+            Ok(progress) => progress,
+            Err(err) => {
+                let backup = path.with_extension(BACKUP_EXTENSION);
+                match fs::copy(path, &backup) {
+                    Ok(_) => {
+                        println!("{} could not be deserialized, starting over. It was backed up to {} ({:?})",
+                                 PROGRESS_FILE,
+                                 backup.display(),
+                                 err)
+                    }
+                    Err(copy_err) => {
+                        println!("{} could not be deserialized ({:?}) or backed up ({:?})",
+                                 PROGRESS_FILE,
+                                 err,
+                                 copy_err)
+                    }
+                }
+                Progress::default()
+            }
+        }
+    }
+
+    pub fn save(&self) -> Result<(), ConfigError> {
+        let path = match Progress::path() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let encoded = json::as_pretty_json(&self);
+        let mut file = File::create(path)?;
+        writeln!(file, "{}", encoded)?;
+        Ok(())
+    }
+
+    pub fn level(&self, id: &str) -> Option<&LevelProgress> {
+        self.levels.get(id)
+    }
+
+    pub fn is_passed(&self, id: &str) -> bool {
+        self.level(id).map_or(false, |level| level.passed)
+    }
+
+    /// Marks a level as passed, keeping the statistics of the run if they
+    /// beat the best one so far. Returns the previous best run, if any
+    pub fn record_pass(&mut self, id: &str, statistics: Statistics) -> Option<Statistics> {
+        let level = self.levels.entry(id.to_owned()).or_insert_with(LevelProgress::default);
+        let previous = level.best.clone();
+
+        level.passed = true;
+        if previous.as_ref().map_or(true, |best| statistics.score() < best.score()) {
+            level.best = Some(statistics);
+        }
+        previous
+    }
+
+    fn path() -> Option<PathBuf> {
+        app_root(AppDataType::UserData, &APP_INFO).ok().map(|root| root.join(PROGRESS_FILE))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn run(commands: u32) -> Statistics {
+        Statistics { commands: commands, ..Statistics::default() }
+    }
+
+    #[test]
+    fn keeps_the_run_with_the_lowest_score() {
+        let mut progress = Progress::default();
+
+        assert!(progress.record_pass("training-1", run(5)).is_none());
+        assert_eq!(Some(5), progress.record_pass("training-1", run(3)).map(|best| best.commands));
+        assert_eq!(Some(3), progress.record_pass("training-1", run(4)).map(|best| best.commands));
+
+        let level = progress.level("training-1").unwrap();
+        assert!(level.passed);
+        assert_eq!(Some(3), level.best.as_ref().map(|best| best.commands));
+        assert!(!progress.is_passed("training-2"));
+    }
+
+    #[test]
+    fn backs_up_progress_that_cannot_be_read() {
+        let path = env::temp_dir().join(format!("hakka-progress-{}.json", ::std::process::id()));
+        fs::write(&path, "{ \"levels\": [").unwrap();
+
+        let progress = Progress::load_from(&path);
+        let backup = fs::read_to_string(path.with_extension(BACKUP_EXTENSION));
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension(BACKUP_EXTENSION)).unwrap();
+
+        assert!(progress.level("training-1").is_none());
+        assert_eq!("{ \"levels\": [", backup.unwrap());
+    }
+}
@@ -0,0 +1,338 @@
+use std::fmt;
+
+use interconnect::Interrupt;
+use processor::Processor;
+
+/// What the code is kept from doing with a region of memory
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    /// Can't be written
+    ReadOnly,
+    /// Can't be executed
+    NoExecute,
+    /// Can only be written and executed by privileged code, which is code
+    /// handling an interrupt the machine raised
+    Privileged,
+}
+
+/// A range of memory protected from the code running on a CPU
+#[derive(Clone, Debug)]
+pub struct Region {
+    pub start: u16,
+    /// The last address of the region
+    pub end: u16,
+    pub access: Access,
+    /// Raised when the region is violated. Without one, execution stops
+    pub interrupt: Option<Interrupt>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Violation {
+    Write,
+    Execute,
+}
+
+/// A violation of a protected region by the code
+#[derive(Clone, Debug)]
+pub struct Fault {
+    pub violation: Violation,
+    pub access: Access,
+    /// The address that was written to or executed
+    pub address: u16,
+    /// The address of the instruction at fault
+    pub pc: u16,
+    pub interrupt: Option<Interrupt>,
+}
+
+/// Watches the protected regions of a CPU's memory. Writes the code wasn't
+/// allowed to make are undone after each step, and reported as a fault.
+///
+/// Code is privileged from when the machine raises an interrupt the CPU
+/// takes, until the handler returns and the stack pointer is back above
+/// where the interrupt left it. Masking interrupts doesn't make code
+/// privileged, and neither does `BRK` or jumping into a handler. Moving the
+/// program counter from outside the code, as resets and debuggers do, drops
+/// the privilege.
+#[derive(Clone, Debug, Default)]
+pub struct Protection {
+    regions: Vec<Region>,
+    /// The index of the region, the address and the contents before the
+    /// step of every protected byte the step writes to
+    before: Vec<(usize, usize, u8)>,
+    pc: u16,
+    /// The stack pointer as each interrupt being handled was taken, the
+    /// most recent last
+    handlers: Vec<u16>,
+    /// Where the code left the program counter, or the interrupt it took
+    next_pc: Option<u16>,
+    /// Whether the step runs privileged
+    privileged: bool,
+}
+
+impl Region {
+    pub fn contains(&self, address: usize) -> bool {
+        address >= self.start as usize && address <= self.end as usize
+    }
+
+    fn is_write_protected(&self) -> bool {
+        self.access != Access::NoExecute
+    }
+}
+
+impl Protection {
+    pub fn new(regions: Vec<Region>) -> Protection {
+        Protection {
+            regions: regions,
+            before: Vec::new(),
+            pc: 0,
+            handlers: Vec::new(),
+            next_pc: None,
+            privileged: false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    /// The first region the console and debuggers can't write to within the
+    /// given range. They are never privileged
+    pub fn write_protected(&self, start: usize, length: usize) -> Option<&Region> {
+        let end = start + length;
+        self.regions
+            .iter()
+            .filter(|region| region.is_write_protected())
+            .find(|region| start <= region.end as usize && end > region.start as usize)
+    }
+
+    /// Remembers the protected memory the CPU is about to write to. All of
+    /// it when the CPU can't tell where its next instruction writes
+    pub fn before_step(&mut self, cpu: &Processor) {
+        if self.regions.is_empty() {
+            return;
+        }
+
+        self.pc = cpu.pc();
+        if self.next_pc.map_or(false, |pc| pc != self.pc) {
+            self.handlers.clear();
+        }
+        self.privileged = self.is_privileged(cpu);
+
+        let addresses = match cpu.writes() {
+            Some(addresses) => addresses.into_iter().map(|address| address as usize).collect(),
+            None => {
+                self.regions
+                    .iter()
+                    .filter(|region| region.is_write_protected())
+                    .flat_map(|region| region.start as usize..region.end as usize + 1)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        let memory = cpu.memory();
+        let regions = &self.regions;
+        self.before = addresses.into_iter()
+            .filter_map(|address| {
+                regions.iter()
+                    .position(|region| region.is_write_protected() && region.contains(address))
+                    .map(|index| (index, address, memory[address]))
+            })
+            .collect();
+    }
+
+    /// Finds the first violation made by the step, and undoes any writes
+    /// that weren't allowed
+    pub fn after_step(&mut self, cpu: &mut Processor) -> Option<Fault> {
+        if self.regions.is_empty() {
+            return None;
+        }
+
+        let mut fault = None;
+
+        for &(index, address, before) in &self.before {
+            let region = &self.regions[index];
+            if (region.access == Access::Privileged && self.privileged) || cpu.memory()[address] == before {
+                continue;
+            }
+            cpu.memory_mut()[address] = before;
+            if fault.is_none() {
+                fault = Some(self.fault(Violation::Write, region, address as u16));
+            }
+        }
+
+        // Handlers that returned popped what the interrupt pushed
+        if let Some(stack) = cpu.register("S") {
+            while self.handlers.last().map_or(false, |&handler| stack > handler) {
+                self.handlers.pop();
+            }
+        }
+        let privileged = self.is_privileged(cpu);
+
+        let pc = cpu.pc();
+        self.next_pc = Some(pc);
+        if fault.is_none() {
+            fault = self.regions
+                .iter()
+                .filter(|region| region.contains(pc as usize))
+                .find(|region| match region.access {
+                    Access::NoExecute => true,
+                    Access::Privileged => !privileged,
+                    Access::ReadOnly => false,
+                })
+                .map(|region| self.fault(Violation::Execute, region, pc));
+        }
+        fault
+    }
+
+    /// Makes the code privileged while it handles the interrupt the CPU
+    /// just took
+    pub fn enter_handler(&mut self, cpu: &Processor) {
+        if let Some(stack) = cpu.register("S") {
+            self.handlers.push(stack);
+        }
+        self.next_pc = Some(cpu.pc());
+    }
+
+    fn is_privileged(&self, cpu: &Processor) -> bool {
+        match (self.handlers.last(), cpu.register("S")) {
+            (Some(&handler), Some(stack)) => stack <= handler,
+            _ => false,
+        }
+    }
+
+    fn fault(&self, violation: Violation, region: &Region, address: u16) -> Fault {
+        Fault {
+            violation: violation,
+            access: region.access,
+            address: address,
+            pc: self.pc,
+            interrupt: region.interrupt,
+        }
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Access::ReadOnly => write!(f, "read-only"),
+            Access::NoExecute => write!(f, "no-execute"),
+            Access::Privileged => write!(f, "privileged"),
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.violation, self.access) {
+            (Violation::Write, Access::Privileged) => write!(f, "Unprivileged write to privileged memory")?,
+            (Violation::Write, access) => write!(f, "Write to {} memory", access)?,
+            (Violation::Execute, Access::Privileged) => write!(f, "Unprivileged execution of privileged memory")?,
+            (Violation::Execute, access) => write!(f, "Execution of {} memory", access)?,
+        }
+        write!(f, " at ${:04X} by the instruction at ${:04X}", self.address, self.pc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use interconnect::Interrupt;
+    use processor::Processor;
+    use processor::tests::StubProcessor;
+
+    use super::*;
+
+    const HANDLER: u16 = 0x8000;
+
+    fn protection(access: Access, interrupt: Option<Interrupt>) -> Protection {
+        Protection::new(vec![Region {
+                                 start: 0x2000,
+                                 end: 0x200F,
+                                 access: access,
+                                 interrupt: interrupt,
+                             }])
+    }
+
+    /// Runs an instruction that writes $42 to the given address
+    fn write(protection: &mut Protection, cpu: &mut StubProcessor, address: u16) -> Option<Fault> {
+        cpu.writes = Some(vec![address]);
+        protection.before_step(cpu);
+        cpu.memory[address as usize] = 0x42;
+        cpu.pc += 3;
+        protection.after_step(cpu)
+    }
+
+    /// Runs an instruction that writes nothing
+    fn step<F>(protection: &mut Protection, cpu: &mut StubProcessor, instruction: F) -> Option<Fault>
+        where F: FnOnce(&mut StubProcessor)
+    {
+        cpu.writes = Some(Vec::new());
+        protection.before_step(cpu);
+        instruction(cpu);
+        protection.after_step(cpu)
+    }
+
+    #[test]
+    fn undoes_writes_to_read_only_memory() {
+        let mut protection = protection(Access::ReadOnly, None);
+        let mut cpu = StubProcessor::new();
+
+        let fault = write(&mut protection, &mut cpu, 0x2005).expect("no fault");
+        assert_eq!(fault.violation, Violation::Write);
+        assert_eq!(fault.address, 0x2005);
+        assert_eq!(fault.pc, 0x0600);
+        assert_eq!(cpu.memory[0x2005], 0x00);
+    }
+
+    #[test]
+    fn keeps_writes_next_to_a_region() {
+        let mut protection = protection(Access::ReadOnly, None);
+        let mut cpu = StubProcessor::new();
+
+        assert!(write(&mut protection, &mut cpu, 0x1FFF).is_none());
+        assert!(write(&mut protection, &mut cpu, 0x2010).is_none());
+        assert_eq!(cpu.memory[0x1FFF], 0x42);
+        assert_eq!(cpu.memory[0x2010], 0x42);
+    }
+
+    #[test]
+    fn interrupt_handlers_are_privileged_until_they_return() {
+        let mut protection = protection(Access::Privileged, None);
+        let mut cpu = StubProcessor::new();
+        cpu.handler = Some(HANDLER);
+
+        assert!(cpu.irq());
+        protection.enter_handler(&cpu);
+        assert!(write(&mut protection, &mut cpu, 0x2000).is_none());
+        assert_eq!(cpu.memory[0x2000], 0x42);
+
+        cpu.memory[0x2000] = 0x00;
+        assert!(step(&mut protection, &mut cpu, |cpu| cpu.rti(0x0600)).is_none());
+        let fault = write(&mut protection, &mut cpu, 0x2000).expect("no fault");
+        assert_eq!(fault.access, Access::Privileged);
+        assert_eq!(cpu.memory[0x2000], 0x00);
+    }
+
+    #[test]
+    fn masking_interrupts_is_not_privileged() {
+        let mut protection = protection(Access::Privileged, Some(Interrupt::Irq));
+        let mut cpu = StubProcessor::new();
+        cpu.handler = Some(HANDLER);
+
+        // SEI
+        assert!(step(&mut protection, &mut cpu, |cpu| {
+                cpu.interrupts_disabled = true;
+                cpu.pc += 1;
+            })
+            .is_none());
+        let fault = write(&mut protection, &mut cpu, 0x2000).expect("no fault");
+        assert_eq!(fault.interrupt, Some(Interrupt::Irq));
+        assert_eq!(cpu.memory[0x2000], 0x00);
+        // The interrupt can't be taken, so the VM reports the fault instead
+        assert!(!cpu.irq());
+        assert_eq!(cpu.pc(), 0x0604);
+    }
+}
@@ -0,0 +1,196 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+/// Something fed into the virtual machine from outside
+#[derive(Clone, Debug, PartialEq)]
+pub enum Input {
+    /// A byte the game loop wrote into memory, such as the key code at $04
+    Memory(usize, u8),
+    /// A command entered into the console
+    Command(String),
+}
+
+/// Writes inputs to a file as they happen, each stamped with the VM cycle it
+/// was fed in before. Replaying them from the start of a level reproduces
+/// the session.
+///
+/// Recordings are stored one input per line, e.g. `120 mem 0004 26` or
+/// `300 cmd break UpArrow`, so they can be read and edited by hand. Every
+/// line is written straight away, so nothing is lost when the game quits.
+pub struct Recorder {
+    file: File,
+}
+
+impl Recorder {
+    pub fn create<P>(path: P) -> io::Result<Recorder>
+        where P: AsRef<Path>
+    {
+        Ok(Recorder { file: File::create(path)? })
+    }
+
+    pub fn record(&mut self, cycle: u64, input: &Input) -> io::Result<()> {
+        match *input {
+            Input::Memory(address, value) => writeln!(self.file, "{} mem {:04X} {:02X}", cycle, address, value),
+            Input::Command(ref command) => writeln!(self.file, "{} cmd {}", cycle, command),
+        }
+    }
+}
+
+/// A recorded session, ready to be replayed
+#[derive(Debug, Default)]
+pub struct Recording {
+    inputs: Vec<(u64, Input)>,
+}
+
+impl Recording {
+    pub fn load<P>(path: P) -> io::Result<Recording>
+        where P: AsRef<Path>
+    {
+        let file = File::open(path)?;
+        let mut inputs = Vec::new();
+
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry = parse_line(&line).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData,
+                               format!("Invalid input on line {}: {}", number + 1, line))
+            })?;
+            inputs.push(entry);
+        }
+
+        // Replays walk the inputs in order
+        inputs.sort_by_key(|&(cycle, _)| cycle);
+        Ok(Recording { inputs: inputs })
+    }
+
+    pub fn len(&self) -> usize {
+        self.inputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+}
+
+fn parse_line(line: &str) -> Option<(u64, Input)> {
+    let mut parts = line.trim().splitn(3, ' ');
+    let cycle = parts.next().and_then(|cycle| cycle.parse().ok())?;
+    let kind = parts.next()?;
+    let rest = parts.next().unwrap_or("");
+
+    let input = match kind {
+        "mem" => {
+            let mut values = rest.split_whitespace();
+            let address = values.next().and_then(|address| usize::from_str_radix(address, 16).ok())?;
+            let value = values.next().and_then(|value| u8::from_str_radix(value, 16).ok())?;
+            if address > 0xFFFF {
+                return None;
+            }
+            Input::Memory(address, value)
+        }
+        "cmd" => Input::Command(rest.to_owned()),
+        _ => return None,
+    };
+
+    Some((cycle, input))
+}
+
+/// Feeds a recording back into the virtual machine
+pub struct Replay {
+    recording: Recording,
+    position: usize,
+}
+
+impl Replay {
+    pub fn new(recording: Recording) -> Replay {
+        Replay {
+            recording: recording,
+            position: 0,
+        }
+    }
+
+    /// Takes the inputs due at or before the given cycle
+    pub fn inputs_until(&mut self, cycle: u64) -> Vec<Input> {
+        let mut inputs = Vec::new();
+        while let Some(&(at, ref input)) = self.recording.inputs.get(self.position) {
+            if at > cycle {
+                break;
+            }
+            inputs.push(input.clone());
+            self.position += 1;
+        }
+        inputs
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.position >= self.recording.inputs.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("hakka-{}-{}.txt", name, ::std::process::id()))
+    }
+
+    #[test]
+    fn replays_what_was_recorded_at_the_same_cycles() {
+        let inputs = vec![(0, Input::Memory(0x04, 38)),
+                          (120, Input::Command("break UpArrow".into())),
+                          (120, Input::Memory(0x04, 0)),
+                          (300, Input::Command("memset Y_0 $8C 00".into()))];
+
+        let path = temp_path("recording");
+        {
+            let mut recorder = Recorder::create(&path).unwrap();
+            for &(cycle, ref input) in &inputs {
+                recorder.record(cycle, input).unwrap();
+            }
+        }
+        let recording = Recording::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(recording.len(), inputs.len());
+
+        let mut replay = Replay::new(recording);
+        assert_eq!(replay.inputs_until(0), vec![inputs[0].1.clone()]);
+        assert_eq!(replay.inputs_until(119), vec![]);
+        assert_eq!(replay.inputs_until(120), vec![inputs[1].1.clone(), inputs[2].1.clone()]);
+        assert!(!replay.is_finished());
+        assert_eq!(replay.inputs_until(1000), vec![inputs[3].1.clone()]);
+        assert!(replay.is_finished());
+    }
+
+    #[test]
+    fn parses_lines_as_they_are_written() {
+        assert_eq!(parse_line("120 mem 0004 26"), Some((120, Input::Memory(0x04, 0x26))));
+        assert_eq!(parse_line("  300 cmd break UpArrow "), Some((300, Input::Command("break UpArrow".into()))));
+        assert_eq!(parse_line("10 cmd"), Some((10, Input::Command(String::new()))));
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert_eq!(parse_line("mem 0004 26"), None);
+        assert_eq!(parse_line("120 mem 10000 26"), None);
+        assert_eq!(parse_line("120 mem 0004 100"), None);
+        assert_eq!(parse_line("120 mem 0004"), None);
+        assert_eq!(parse_line("120 key Up"), None);
+
+        let path = temp_path("malformed");
+        fs::write(&path, "0 mem 0004 26\n\n120 key Up\n").unwrap();
+        let err = Recording::load(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "Invalid input on line 3: 120 key Up");
+    }
+}
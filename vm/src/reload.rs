@@ -0,0 +1,369 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use rs6502::{Assembler, CodeSegment};
+
+use diagnostic::Diagnostic;
+use level::MemoryWrite;
+use mapper::Bank;
+use paths::{file_name, same_file};
+use symbols::SymbolTable;
+use vm::VirtualMachine;
+
+/// How many frames pass between looking for changed sources, so the disk
+/// isn't hit every frame
+const CHECK_INTERVAL: u32 = 30;
+
+const SOURCE_EXTENSION: &'static str = "asm";
+
+const ZERO_PAGE_SIZE: usize = 0x100;
+
+/// Assembles a source file at the given load address
+pub fn assemble<P>(path: P, load_address: u16) -> Result<Vec<CodeSegment>, Diagnostic>
+    where P: AsRef<Path>
+{
+    let mut assembler = Assembler::new();
+    assembler.assemble_file(&path, load_address).map_err(|err| Diagnostic::from_assembler_error(&path, &err))
+}
+
+/// Watches the assembly sources of a level and reloads the code assembled
+/// from them into the VM whenever one of them changes.
+///
+/// Every `.asm` file next to a watched source is watched. A changed source
+/// is reassembled into the CPU or bank it was loaded into, and that CPU is
+/// restarted from its reset vector. Unless the zero page is preserved, the
+/// CPU's zero page is put back to how the level started and the level's
+/// memory writes are made again. A change to any other `.asm` file, such as
+/// one included by the sources, reloads all of them.
+pub struct SourceWatcher {
+    cpus: Vec<WatchedCpu>,
+    banks: Vec<WatchedBank>,
+    modified: BTreeMap<PathBuf, SystemTime>,
+    /// Whether changes are picked up automatically
+    pub watching: bool,
+    /// Keeps the zero page and the rest of memory as they are on reload,
+    /// rather than starting over
+    pub preserve_zero_page: bool,
+    frames: u32,
+}
+
+/// The code of a CPU, and its memory as the level started
+struct WatchedCpu {
+    cpu: usize,
+    source: PathBuf,
+    load_address: u16,
+    memory: Vec<MemoryWrite>,
+    initial_zero_page: Vec<u8>,
+}
+
+/// A ROM bank of the first CPU, assembled from its own source
+struct WatchedBank {
+    mapper: usize,
+    bank: usize,
+    source: PathBuf,
+}
+
+impl SourceWatcher {
+    pub fn new() -> SourceWatcher {
+        SourceWatcher {
+            cpus: Vec::new(),
+            banks: Vec::new(),
+            modified: BTreeMap::new(),
+            watching: true,
+            preserve_zero_page: false,
+            frames: 0,
+        }
+    }
+
+    /// Starts watching the source of the code loaded into a CPU, along with
+    /// the memory the level writes before it starts. The CPU's zero page is
+    /// remembered as it is now, to start over from on reload
+    pub fn watch_cpu<P>(&mut self,
+                        cpu: usize,
+                        source: P,
+                        load_address: u16,
+                        memory: Vec<MemoryWrite>,
+                        vm: &mut VirtualMachine)
+        where P: Into<PathBuf>
+    {
+        let initial_zero_page = vm.with_cpu(cpu, |vm| vm.cpu.memory()[..ZERO_PAGE_SIZE].to_vec());
+        self.cpus.push(WatchedCpu {
+            cpu: cpu,
+            source: source.into(),
+            load_address: load_address,
+            memory: memory,
+            initial_zero_page: initial_zero_page,
+        });
+        self.modified = self.modification_times();
+    }
+
+    /// Starts watching the source of a ROM bank of the first CPU
+    pub fn watch_bank<P>(&mut self, mapper: usize, bank: usize, source: P)
+        where P: Into<PathBuf>
+    {
+        self.banks.push(WatchedBank {
+            mapper: mapper,
+            bank: bank,
+            source: source.into(),
+        });
+        self.modified = self.modification_times();
+    }
+
+    /// Every watched source
+    pub fn sources(&self) -> Vec<&Path> {
+        self.cpus
+            .iter()
+            .map(|cpu| cpu.source.as_path())
+            .chain(self.banks.iter().map(|bank| bank.source.as_path()))
+            .collect()
+    }
+
+    /// Reloads the code of the sources that changed since the last check
+    pub fn poll(&mut self, vm: &mut VirtualMachine) {
+        if !self.watching {
+            return;
+        }
+        self.frames += 1;
+        if self.frames < CHECK_INTERVAL {
+            return;
+        }
+        self.frames = 0;
+
+        let modified = self.modification_times();
+        let sources = sources_to_reload(&self.sources(), &self.modified, &modified);
+        self.modified = modified;
+
+        if !sources.is_empty() {
+            self.reload_sources(&sources, vm);
+        }
+    }
+
+    /// Reassembles every source and loads it where it belongs. Code that
+    /// doesn't assemble stays as it is. Returns false if any of it didn't
+    pub fn reload(&mut self, vm: &mut VirtualMachine) -> bool {
+        let sources = self.sources().into_iter().map(|source| source.to_path_buf()).collect::<Vec<_>>();
+        self.reload_sources(&sources, vm)
+    }
+
+    /// Reloads the banks and CPUs assembled from the given sources, and
+    /// restarts the CPUs they belong to
+    fn reload_sources(&mut self, sources: &[PathBuf], vm: &mut VirtualMachine) -> bool {
+        let changed = |source: &Path| sources.iter().any(|path| same_file(path, source));
+        let mut reloaded = true;
+        let mut restart = Vec::new();
+
+        for bank in self.banks.iter().filter(|bank| changed(&bank.source)) {
+            if vm.with_cpu(0, |vm| bank.reload(vm)) {
+                restart.push(0);
+            } else {
+                reloaded = false;
+            }
+        }
+
+        let preserve = self.preserve_zero_page;
+        for cpu in &self.cpus {
+            if changed(&cpu.source) {
+                if !vm.with_cpu(cpu.cpu, |vm| cpu.reload(preserve, vm)) {
+                    reloaded = false;
+                }
+            } else if restart.contains(&cpu.cpu) {
+                vm.with_cpu(cpu.cpu, |vm| cpu.restart(preserve, vm));
+            }
+        }
+        reloaded
+    }
+
+    /// When each assembly source next to the watched sources was last
+    /// modified
+    fn modification_times(&self) -> BTreeMap<PathBuf, SystemTime> {
+        let mut times = BTreeMap::new();
+        for source in self.sources() {
+            times.extend(modification_times(source));
+        }
+        times
+    }
+}
+
+impl WatchedCpu {
+    /// Reassembles the source into the selected CPU and restarts it
+    fn reload(&self, preserve_zero_page: bool, vm: &mut VirtualMachine) -> bool {
+        let name = file_name(&self.source);
+
+        let segments = match assemble(&self.source, self.load_address) {
+            Ok(segments) => segments,
+            Err(diagnostic) => {
+                writeln!(vm.console, "Unable to reload {}, the code already loaded keeps running:", name).unwrap();
+                writeln!(vm.console, "{}", diagnostic).unwrap();
+                return false;
+            }
+        };
+        let symbols = match SymbolTable::from_file(&self.source, self.load_address) {
+            Ok(symbols) => symbols,
+            Err(err) => {
+                writeln!(vm.console, "Unable to reload {}: {}", name, err).unwrap();
+                return false;
+            }
+        };
+
+        let size = segments.iter().map(|segment| segment.code.len()).sum::<usize>();
+        vm.unload_code_segments();
+        vm.load_code_segments(segments);
+        vm.load_symbols(symbols);
+        self.restart(preserve_zero_page, vm);
+
+        let zero_page = if preserve_zero_page { "kept" } else { "reset" };
+        if vm.cpu_count() > 1 {
+            writeln!(vm.console, "Reloaded {} on cpu {} ({} bytes, zero page {})", name, self.cpu, size, zero_page)
+                .unwrap();
+        } else {
+            writeln!(vm.console, "Reloaded {} ({} bytes, zero page {})", name, size, zero_page).unwrap();
+        }
+        true
+    }
+
+    /// Puts the memory of the selected CPU back to how the level started,
+    /// unless it is preserved, and resets it
+    fn restart(&self, preserve_zero_page: bool, vm: &mut VirtualMachine) {
+        if !preserve_zero_page {
+            vm.cpu.memory_mut()[..ZERO_PAGE_SIZE].copy_from_slice(&self.initial_zero_page);
+            for write in &self.memory {
+                match write.evaluate(vm) {
+                    Ok((address, bytes)) => vm.cpu.memory_mut()[address..address + bytes.len()].copy_from_slice(&bytes),
+                    Err(err) => writeln!(vm.console, "Invalid memory write to '{}': {}", write.address, err).unwrap(),
+                }
+            }
+        }
+        vm.cpu.reset();
+    }
+}
+
+impl WatchedBank {
+    /// Reassembles the source into the bank of the selected CPU
+    fn reload(&self, vm: &mut VirtualMachine) -> bool {
+        let name = file_name(&self.source);
+        let (window, size) = match vm.mappers().get(self.mapper) {
+            Some(mapper) => (mapper.window, mapper.size),
+            None => return false,
+        };
+
+        let segments = match assemble(&self.source, window) {
+            Ok(segments) => segments,
+            Err(diagnostic) => {
+                writeln!(vm.console, "Unable to reload {}, the bank already loaded stays:", name).unwrap();
+                writeln!(vm.console, "{}", diagnostic).unwrap();
+                return false;
+            }
+        };
+        let bank = SymbolTable::from_file(&self.source, window)
+            .map_err(|err| err.to_string())
+            .and_then(|symbols| Bank::rom(segments, symbols, window, size));
+        match bank {
+            Ok(bank) => {
+                vm.replace_bank(self.mapper, self.bank, bank);
+                writeln!(vm.console, "Reloaded {} into bank {}", name, self.bank).unwrap();
+                true
+            }
+            Err(err) => {
+                writeln!(vm.console, "Unable to reload {}: {}", name, err).unwrap();
+                false
+            }
+        }
+    }
+}
+
+/// The sources to reload, given when the files next to them were modified
+/// at the last check and now. These are the sources that changed, or every
+/// source if any other file changed, as it may be included by any of them
+fn sources_to_reload(sources: &[&Path],
+                     before: &BTreeMap<PathBuf, SystemTime>,
+                     after: &BTreeMap<PathBuf, SystemTime>)
+                     -> Vec<PathBuf> {
+    let changed = after.iter()
+        .filter(|&(path, time)| before.get(path) != Some(time))
+        .map(|(path, _)| path.clone())
+        .chain(before.keys().filter(|path| !after.contains_key(*path)).cloned())
+        .collect::<Vec<_>>();
+
+    let is_source = |path: &PathBuf| sources.iter().any(|source| same_file(source, path));
+    if changed.iter().all(is_source) {
+        changed
+    } else {
+        sources.iter().map(|source| source.to_path_buf()).collect()
+    }
+}
+
+/// When each assembly source next to the given one was last modified
+fn modification_times(source: &Path) -> BTreeMap<PathBuf, SystemTime> {
+    let directory = match source.parent() {
+        Some(parent) if parent != Path::new("") => parent,
+        _ => Path::new("."),
+    };
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(_) => return BTreeMap::new(),
+    };
+
+    entries.filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().map_or(false, |extension| extension == SOURCE_EXTENSION))
+        .filter_map(|path| {
+            let modified = fs::metadata(&path).and_then(|metadata| metadata.modified()).ok();
+            modified.map(|modified| (path, modified))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn times(files: &[(&str, u64)]) -> BTreeMap<PathBuf, SystemTime> {
+        files.iter()
+            .map(|&(path, seconds)| (PathBuf::from(path), SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)))
+            .collect()
+    }
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn reloads_nothing_when_nothing_changed() {
+        let sources = [Path::new("level/cpu.asm"), Path::new("level/bank.asm")];
+        let before = times(&[("level/cpu.asm", 1), ("level/bank.asm", 1), ("level/macros.asm", 1)]);
+
+        assert!(sources_to_reload(&sources, &before, &before.clone()).is_empty());
+    }
+
+    #[test]
+    fn reloads_only_the_changed_sources() {
+        let sources = [Path::new("level/cpu.asm"), Path::new("level/bank.asm")];
+        let before = times(&[("level/cpu.asm", 1), ("level/bank.asm", 1), ("level/macros.asm", 1)]);
+        let after = times(&[("level/cpu.asm", 1), ("level/bank.asm", 2), ("level/macros.asm", 1)]);
+
+        assert_eq!(paths(&["level/bank.asm"]), sources_to_reload(&sources, &before, &after));
+    }
+
+    #[test]
+    fn reloads_every_source_when_another_file_changed() {
+        let sources = [Path::new("level/cpu.asm"), Path::new("level/bank.asm")];
+        let before = times(&[("level/cpu.asm", 1), ("level/bank.asm", 1), ("level/macros.asm", 1)]);
+        let after = times(&[("level/cpu.asm", 2), ("level/bank.asm", 1), ("level/macros.asm", 2)]);
+
+        assert_eq!(paths(&["level/cpu.asm", "level/bank.asm"]), sources_to_reload(&sources, &before, &after));
+    }
+
+    #[test]
+    fn reloads_every_source_when_a_file_appears_or_disappears() {
+        let sources = [Path::new("level/cpu.asm")];
+        let before = times(&[("level/cpu.asm", 1), ("level/macros.asm", 1)]);
+        let after = times(&[("level/cpu.asm", 1), ("level/sprites.asm", 1)]);
+
+        assert_eq!(paths(&["level/cpu.asm"]), sources_to_reload(&sources, &before, &after));
+        assert_eq!(paths(&["level/cpu.asm"]), sources_to_reload(&sources, &after, &before));
+    }
+}
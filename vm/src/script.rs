@@ -0,0 +1,444 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, Map, Scope, AST};
+
+use vm::VirtualMachine;
+
+/// Called every frame, before the CPU runs
+const FRAME_CALLBACK: &'static str = "on_frame";
+/// Called with the address of a breakpoint when it is hit
+const BREAKPOINT_CALLBACK: &'static str = "on_breakpoint";
+
+/// How many operations a script may run in one call before it is stopped,
+/// so a script stuck in a loop can't hang the game
+const MAX_OPERATIONS: u64 = 1_000_000;
+/// How deep script functions may call each other
+const MAX_CALL_LEVELS: usize = 64;
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// What a script asked the debugger to do with execution
+#[derive(Clone, Copy)]
+enum Execution {
+    Pause,
+    Resume,
+    Step,
+}
+
+/// The part of the virtual machine scripts can see. Script functions can't
+/// borrow the VM itself, so it is copied in before scripts run and the
+/// changes are copied back out afterwards
+#[derive(Default)]
+struct Machine {
+    memory: Vec<u8>,
+    memory_changed: bool,
+    registers: Vec<(&'static str, u16)>,
+    flags: Vec<(char, bool)>,
+    symbols: HashMap<String, u16>,
+    breakpoints: Vec<(usize, bool)>,
+    execution: Option<Execution>,
+    output: Vec<String>,
+}
+
+impl Machine {
+    fn load(&mut self, vm: &VirtualMachine) {
+        // The code may have been reloaded or another CPU selected since the
+        // last call
+        self.symbols = vm.symbols().all().into_iter().map(|(name, address)| (name.to_owned(), address)).collect();
+        self.memory.clear();
+        self.memory.extend_from_slice(vm.cpu.memory());
+        self.memory_changed = false;
+        self.registers = vm.registers();
+        self.flags = vm.flags().into_iter().map(|(letter, _, set)| (letter, set)).collect();
+    }
+
+    fn store(&mut self, vm: &mut VirtualMachine) {
+        if self.memory_changed {
+            // Scripts are held to the same protection as the console
+            for (address, byte) in self.memory.iter().enumerate() {
+                if vm.cpu.memory()[address] != *byte && vm.write_protected(address, 1).is_none() {
+                    vm.write_memory(address, &[*byte]);
+                }
+            }
+        }
+        for &(name, value) in &self.registers {
+            vm.set_register(name, value);
+        }
+        for &(letter, set) in &self.flags {
+            vm.set_flag(letter, set);
+        }
+        for (address, set) in self.breakpoints.drain(..) {
+            if vm.has_breakpoint(address) != set {
+                vm.toggle_breakpoint(address);
+            }
+        }
+        match self.execution.take() {
+            Some(Execution::Pause) => vm.break_execution(),
+            Some(Execution::Resume) => vm.continue_execution(),
+            Some(Execution::Step) => vm.step_execution(),
+            None => (),
+        }
+        for line in self.output.drain(..) {
+            writeln!(vm.console, "{}", line).unwrap();
+        }
+    }
+
+    fn address(&self, address: i64) -> ScriptResult<usize> {
+        if address < 0 || address as usize >= self.memory.len() {
+            return Err(format!("Address {:X} is out of range", address).into());
+        }
+        Ok(address as usize)
+    }
+}
+
+struct Script {
+    path: PathBuf,
+    ast: AST,
+    scope: Scope<'static>,
+    /// Bound to `this` in callbacks, so scripts can keep state between calls
+    state: Dynamic,
+}
+
+impl Script {
+    fn defines(&self, name: &str, parameters: usize) -> bool {
+        self.ast.iter_functions().any(|function| function.name == name && function.params.len() == parameters)
+    }
+}
+
+/// Runs Rhai scripts against the virtual machine. A script runs once when
+/// it is loaded, and is kept around if it defines an `on_frame()` or
+/// `on_breakpoint(address)` function to be called later.
+pub struct ScriptEngine {
+    engine: Engine,
+    machine: Rc<RefCell<Machine>>,
+    scripts: Vec<Script>,
+}
+
+impl ScriptEngine {
+    pub fn new() -> ScriptEngine {
+        let machine = Rc::new(RefCell::new(Machine::default()));
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.set_max_call_levels(MAX_CALL_LEVELS);
+
+        let m = machine.clone();
+        engine.on_print(move |text| m.borrow_mut().output.push(text.into()));
+        let m = machine.clone();
+        engine.on_debug(move |text, _, _| m.borrow_mut().output.push(text.into()));
+
+        let m = machine.clone();
+        engine.register_fn("peek", move |address: i64| -> ScriptResult<i64> {
+            let machine = m.borrow();
+            let address = machine.address(address)?;
+            Ok(machine.memory[address] as i64)
+        });
+        let m = machine.clone();
+        engine.register_fn("peek_word", move |address: i64| -> ScriptResult<i64> {
+            let machine = m.borrow();
+            let low = machine.address(address)?;
+            let high = machine.address(address + 1)?;
+            Ok(machine.memory[low] as i64 | (machine.memory[high] as i64) << 8)
+        });
+        let m = machine.clone();
+        engine.register_fn("poke", move |address: i64, value: i64| -> ScriptResult<()> {
+            let mut machine = m.borrow_mut();
+            let address = machine.address(address)?;
+            machine.memory[address] = value as u8;
+            machine.memory_changed = true;
+            Ok(())
+        });
+        let m = machine.clone();
+        engine.register_fn("poke_word", move |address: i64, value: i64| -> ScriptResult<()> {
+            let mut machine = m.borrow_mut();
+            let low = machine.address(address)?;
+            let high = machine.address(address + 1)?;
+            machine.memory[low] = value as u8;
+            machine.memory[high] = (value >> 8) as u8;
+            machine.memory_changed = true;
+            Ok(())
+        });
+
+        let m = machine.clone();
+        engine.register_fn("reg", move |name: &str| -> ScriptResult<i64> {
+            m.borrow()
+                .registers
+                .iter()
+                .find(|&&(register, _)| register.eq_ignore_ascii_case(name))
+                .map(|&(_, value)| value as i64)
+                .ok_or(format!("Unknown register '{}'", name).into())
+        });
+        let m = machine.clone();
+        engine.register_fn("set_reg", move |name: &str, value: i64| -> ScriptResult<()> {
+            let mut machine = m.borrow_mut();
+            match machine.registers.iter_mut().find(|&&mut (register, _)| register.eq_ignore_ascii_case(name)) {
+                Some(register) => {
+                    register.1 = value as u16;
+                    Ok(())
+                }
+                None => Err(format!("Unknown register '{}'", name).into()),
+            }
+        });
+        let m = machine.clone();
+        engine.register_fn("flag", move |letter: &str| -> ScriptResult<bool> {
+            m.borrow()
+                .flags
+                .iter()
+                .find(|&&(flag, _)| letter.eq_ignore_ascii_case(&flag.to_string()))
+                .map(|&(_, set)| set)
+                .ok_or(format!("Unknown flag '{}'", letter).into())
+        });
+        let m = machine.clone();
+        engine.register_fn("set_flag", move |letter: &str, set: bool| -> ScriptResult<()> {
+            let mut machine = m.borrow_mut();
+            match machine.flags.iter_mut().find(|&&mut (flag, _)| letter.eq_ignore_ascii_case(&flag.to_string())) {
+                Some(flag) => {
+                    flag.1 = set;
+                    Ok(())
+                }
+                None => Err(format!("Unknown flag '{}'", letter).into()),
+            }
+        });
+        let m = machine.clone();
+        engine.register_fn("sym", move |name: &str| -> ScriptResult<i64> {
+            m.borrow()
+                .symbols
+                .get(name)
+                .map(|&address| address as i64)
+                .ok_or(format!("Unknown symbol '{}'", name).into())
+        });
+
+        let m = machine.clone();
+        engine.register_fn("set_break", move |address: i64| -> ScriptResult<()> {
+            let mut machine = m.borrow_mut();
+            let address = machine.address(address)?;
+            machine.breakpoints.push((address, true));
+            Ok(())
+        });
+        let m = machine.clone();
+        engine.register_fn("clear_break", move |address: i64| -> ScriptResult<()> {
+            let mut machine = m.borrow_mut();
+            let address = machine.address(address)?;
+            machine.breakpoints.push((address, false));
+            Ok(())
+        });
+        let m = machine.clone();
+        engine.register_fn("pause", move || m.borrow_mut().execution = Some(Execution::Pause));
+        let m = machine.clone();
+        engine.register_fn("resume", move || m.borrow_mut().execution = Some(Execution::Resume));
+        let m = machine.clone();
+        engine.register_fn("step", move || m.borrow_mut().execution = Some(Execution::Step));
+
+        ScriptEngine {
+            engine: engine,
+            machine: machine,
+            scripts: Vec::new(),
+        }
+    }
+
+    /// Loads and runs a script file. The script is kept if it defines any
+    /// callbacks; returns whether it was
+    pub fn load<P>(&mut self, path: P, vm: &mut VirtualMachine) -> Result<bool, String>
+        where P: AsRef<Path>
+    {
+        let path = path.as_ref();
+        let mut source = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut source))
+            .map_err(|err| err.to_string())?;
+        let ast = self.engine.compile(&source).map_err(|err| err.to_string())?;
+
+        self.machine.borrow_mut().load(vm);
+        let mut scope = Scope::new();
+        let result = self.engine.run_ast_with_scope(&mut scope, &ast);
+        self.machine.borrow_mut().store(vm);
+        result.map_err(|err| err.to_string())?;
+
+        let script = Script {
+            path: path.to_path_buf(),
+            ast: ast,
+            scope: scope,
+            state: Dynamic::from_map(Map::new()),
+        };
+        if script.defines(FRAME_CALLBACK, 0) || script.defines(BREAKPOINT_CALLBACK, 1) {
+            self.scripts.push(script);
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    /// The files of the scripts waiting for callbacks
+    pub fn scripts(&self) -> Vec<&Path> {
+        self.scripts.iter().map(|script| script.path.as_path()).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scripts.is_empty()
+    }
+
+    /// Removes the script at the given index. Returns false if there isn't one
+    pub fn unload(&mut self, index: usize) -> bool {
+        if index < self.scripts.len() {
+            self.scripts.remove(index);
+            return true;
+        }
+        false
+    }
+
+    pub fn clear(&mut self) {
+        self.scripts.clear();
+    }
+
+    /// Calls `on_frame()` in every script that defines it
+    pub fn run_frame(&mut self, vm: &mut VirtualMachine) {
+        self.call(vm, FRAME_CALLBACK, Vec::new());
+    }
+
+    /// Calls `on_breakpoint(address)` in every script that defines it
+    pub fn run_breakpoint(&mut self, vm: &mut VirtualMachine, address: u16) {
+        self.call(vm, BREAKPOINT_CALLBACK, vec![Dynamic::from(address as i64)]);
+    }
+
+    fn call(&mut self, vm: &mut VirtualMachine, name: &str, args: Vec<Dynamic>) {
+        if !self.scripts.iter().any(|script| script.defines(name, args.len())) {
+            return;
+        }
+
+        self.machine.borrow_mut().load(vm);
+
+        // Scripts that fail are unloaded, rather than failing every frame
+        let mut failed = Vec::new();
+        for (index, script) in self.scripts.iter_mut().enumerate() {
+            if !script.defines(name, args.len()) {
+                continue;
+            }
+
+            // The top level statements already ran when the script loaded
+            let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(&mut script.state);
+            let result = self.engine
+                .call_fn_with_options::<Dynamic>(options, &mut script.scope, &script.ast, name, args.clone());
+            if let Err(err) = result {
+                failed.push((index, format!("Script {} failed in {}: {}", script.path.display(), name, err)));
+            }
+        }
+
+        self.machine.borrow_mut().store(vm);
+        for (index, message) in failed.into_iter().rev() {
+            writeln!(vm.console, "{}", message).unwrap();
+            self.scripts.remove(index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use rs6502::Cpu;
+
+    use protection::{Access, Protection, Region};
+    use symbols::SymbolTable;
+
+    use super::*;
+
+    /// Loads a script into a new engine, returning whether it was kept
+    fn load(name: &str, source: &str, vm: &mut VirtualMachine) -> (ScriptEngine, Result<bool, String>) {
+        let path = env::temp_dir().join(format!("hakka-script-{}-{}.rhai", name, ::std::process::id()));
+        fs::write(&path, source).unwrap();
+        let mut scripts = ScriptEngine::new();
+        let result = scripts.load(&path, vm);
+        fs::remove_file(&path).unwrap();
+        (scripts, result)
+    }
+
+    fn machine() -> VirtualMachine<'static> {
+        VirtualMachine::headless(Cpu::new(), None).unwrap()
+    }
+
+    #[test]
+    fn rejects_addresses_outside_of_memory() {
+        let mut vm = machine();
+
+        let (_, result) = load("peek", "peek(0x10000)", &mut vm);
+        assert!(result.unwrap_err().contains("Address 10000 is out of range"));
+
+        let (_, result) = load("poke", "poke(0xFFFF, 1); poke_word(0xFFFF, 1)", &mut vm);
+        assert!(result.unwrap_err().contains("Address 10000 is out of range"));
+        // The writes before the error still count
+        assert_eq!(0x01, vm.cpu.memory()[0xFFFF]);
+    }
+
+    #[test]
+    fn skips_writes_to_protected_memory() {
+        let mut vm = machine();
+        vm.set_protection(Protection::new(vec![Region {
+                                                   start: 0x2000,
+                                                   end: 0x200F,
+                                                   access: Access::ReadOnly,
+                                                   interrupt: None,
+                                               }]));
+
+        let (_, result) = load("protected", "poke(0x2000, 1); poke(0x2010, 2);", &mut vm);
+
+        assert_eq!(Ok(false), result);
+        assert_eq!(0x00, vm.cpu.memory()[0x2000]);
+        assert_eq!(0x02, vm.cpu.memory()[0x2010]);
+        assert_eq!(1, vm.bytes_written());
+    }
+
+    #[test]
+    fn unloads_scripts_that_fail() {
+        let mut vm = machine();
+        let (mut scripts, result) = load("failing", "fn on_frame() { peek(-1) }", &mut vm);
+        assert_eq!(Ok(true), result);
+
+        scripts.run_frame(&mut vm);
+
+        assert!(scripts.is_empty());
+        assert!(vm.console.lines().iter().any(|line| line.contains("failed in on_frame")));
+    }
+
+    #[test]
+    fn stops_and_unloads_scripts_that_run_forever() {
+        let mut vm = machine();
+        let (mut scripts, _) = load("forever", "fn on_frame() { loop {} }", &mut vm);
+
+        scripts.run_frame(&mut vm);
+
+        assert!(scripts.is_empty());
+    }
+
+    #[test]
+    fn keeps_this_between_calls() {
+        let mut vm = machine();
+        let (mut scripts, _) = load("state",
+                                    "fn on_frame() { this.frames = (this.frames ?? 0) + 1; poke(0x0200, this.frames); }",
+                                    &mut vm);
+
+        scripts.run_frame(&mut vm);
+        scripts.run_frame(&mut vm);
+        scripts.run_frame(&mut vm);
+
+        assert_eq!(0x03, vm.cpu.memory()[0x0200]);
+    }
+
+    #[test]
+    fn sees_symbols_loaded_after_the_script() {
+        let mut vm = machine();
+        let (mut scripts, _) = load("symbols", "fn on_frame() { poke(0x0200, sym(\"TARGET\") & 0xFF); }", &mut vm);
+        let mut symbols = SymbolTable::new();
+        symbols.insert("TARGET", 0xC042);
+        vm.load_symbols(symbols);
+
+        scripts.run_frame(&mut vm);
+
+        assert!(!scripts.is_empty());
+        assert_eq!(0x42, vm.cpu.memory()[0x0200]);
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use hound::{SampleFormat, WavSpec, WavWriter};
+
+/// The number of voices the device mixes together
+pub const VOICES: usize = 3;
+
+/// The frequency word, volume and control of a voice
+const VOICE_SIZE: usize = 4;
+
+/// The size of the registers of all voices
+pub const REGISTERS_SIZE: usize = VOICES * VOICE_SIZE;
+
+/// The sample rate used when nothing asks for another one
+pub const SAMPLE_RATE: u32 = 44100;
+
+const CONTROL_ON: u8 = 0x01;
+const CONTROL_NOISE: u8 = 0x02;
+
+/// The loudest volume. Voices at full volume mix to a full scale sample
+const MAX_VOLUME: i32 = 15;
+const VOLUME_STEP: i32 = ::std::i16::MAX as i32 / (MAX_VOLUME * VOICES as i32);
+
+/// How many frames of samples may wait to be played before the oldest are
+/// dropped, so a speaker that falls behind doesn't lag further and further
+const QUEUED_FRAMES: usize = 4;
+
+/// Samples waiting to be played, shared with whatever plays them
+pub type SampleQueue = Arc<Mutex<VecDeque<i16>>>;
+
+/// Where the waveform of a voice is at
+#[derive(Debug)]
+struct Voice {
+    /// How far into the current period the voice is, in samples times the
+    /// frequency
+    phase: u32,
+    /// The shift register noise is taken from
+    noise: u16,
+}
+
+/// A tone and noise generator in the memory of the first CPU.
+///
+/// Each voice has 4 registers: its frequency in Hz as a little endian word,
+/// its volume from 0 to 15 and its control. Bit 0 of the control turns the
+/// voice on, bit 1 makes it play noise instead of a square wave.
+///
+/// The registers are read once per frame, and a frame's worth of samples
+/// generated from them. Those are played through the speaker and written
+/// to the WAV file being captured to, if either is there, so the sound of a
+/// level can be checked without a sound card.
+pub struct SoundDevice {
+    pub address: u16,
+    sample_rate: u32,
+    frames_per_second: u32,
+    /// What is left over of a sample from the previous frames, in samples
+    /// times the frame rate
+    owed: u32,
+    voices: Vec<Voice>,
+    speaker: Option<SampleQueue>,
+    capture: Option<(PathBuf, WavWriter<BufWriter<File>>)>,
+    /// How many samples were written to the capture
+    captured: u32,
+}
+
+impl SoundDevice {
+    pub fn new(address: u16, sample_rate: u32, frames_per_second: u32) -> SoundDevice {
+        SoundDevice {
+            address: address,
+            sample_rate: sample_rate,
+            frames_per_second: frames_per_second,
+            owed: 0,
+            voices: (0..VOICES).map(|_| Voice { phase: 0, noise: 1 }).collect(),
+            speaker: None,
+            capture: None,
+            captured: 0,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Plays the sound through a speaker taking samples from the queue
+    pub fn play_through(&mut self, speaker: SampleQueue) {
+        self.speaker = Some(speaker);
+    }
+
+    /// The WAV file being captured to, if any
+    pub fn capture(&self) -> Option<&Path> {
+        self.capture.as_ref().map(|&(ref path, _)| path.as_path())
+    }
+
+    /// Starts writing the sound to a WAV file, replacing any capture in
+    /// progress
+    pub fn start_capture<P>(&mut self, path: P) -> Result<(), String>
+        where P: AsRef<Path>
+    {
+        self.stop_capture()?;
+
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: self.sample_rate,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let path = path.as_ref();
+        let writer = WavWriter::create(path, spec).map_err(|err| err.to_string())?;
+        self.capture = Some((path.to_path_buf(), writer));
+        self.captured = 0;
+        Ok(())
+    }
+
+    /// Finishes the WAV file being captured to. Returns its path and how
+    /// many seconds it holds, if there was one
+    pub fn stop_capture(&mut self) -> Result<Option<(PathBuf, f32)>, String> {
+        match self.capture.take() {
+            Some((path, writer)) => {
+                writer.finalize().map_err(|err| err.to_string())?;
+                Ok(Some((path, self.captured as f32 / self.sample_rate as f32)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Generates a frame of samples from the registers in memory. A capture
+    /// that can't be written to is stopped, and the error returned
+    pub fn frame(&mut self, memory: &[u8]) -> Result<(), String> {
+        self.owed += self.sample_rate;
+        let count = self.owed / self.frames_per_second;
+        self.owed %= self.frames_per_second;
+
+        let start = self.address as usize;
+        let registers = &memory[start..start + REGISTERS_SIZE];
+        let sample_rate = self.sample_rate;
+        let samples = (0..count)
+            .map(|_| {
+                self.voices
+                    .iter_mut()
+                    .zip(registers.chunks(VOICE_SIZE))
+                    .map(|(voice, registers)| voice.sample(registers, sample_rate))
+                    .sum::<i32>() as i16
+            })
+            .collect::<Vec<_>>();
+
+        if let Some(ref speaker) = self.speaker {
+            let mut queue = speaker.lock().unwrap();
+            queue.extend(samples.iter().cloned());
+            let limit = count as usize * QUEUED_FRAMES;
+            while queue.len() > limit {
+                queue.pop_front();
+            }
+        }
+
+        let result = match self.capture {
+            Some((_, ref mut writer)) => samples.iter().map(|&sample| writer.write_sample(sample)).collect(),
+            None => return Ok(()),
+        };
+        match result {
+            Ok(()) => {
+                self.captured += count;
+                Ok(())
+            }
+            Err(err) => {
+                self.capture = None;
+                Err(err.to_string())
+            }
+        }
+    }
+}
+
+impl Voice {
+    /// The next sample of the voice, given its registers
+    fn sample(&mut self, registers: &[u8], sample_rate: u32) -> i32 {
+        let frequency = registers[0] as u32 | (registers[1] as u32) << 8;
+        let volume = (registers[2] & 0x0F) as i32;
+        let control = registers[3];
+        if control & CONTROL_ON == 0 || frequency == 0 {
+            return 0;
+        }
+
+        self.phase += frequency;
+        while self.phase >= sample_rate {
+            self.phase -= sample_rate;
+            // A 15 bit shift register, fed back from its lowest two bits
+            let bit = (self.noise ^ (self.noise >> 1)) & 1;
+            self.noise = (self.noise >> 1) | (bit << 14);
+        }
+
+        let high = if control & CONTROL_NOISE != 0 {
+            self.noise & 1 != 0
+        } else {
+            self.phase * 2 < sample_rate
+        };
+        if high {
+            volume * VOLUME_STEP
+        } else {
+            -volume * VOLUME_STEP
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use hound::WavReader;
+
+    use super::*;
+
+    #[test]
+    fn captures_a_square_wave() {
+        let address = 0xD400;
+        let mut memory = [0u8; 0x10000];
+        // 441 Hz at full volume, a period of 100 samples
+        memory[address..address + VOICE_SIZE].copy_from_slice(&[0xB9, 0x01, 0x0F, CONTROL_ON]);
+
+        let path = env::temp_dir().join(format!("hakka-sound-{}.wav", ::std::process::id()));
+        let mut sound = SoundDevice::new(address as u16, SAMPLE_RATE, 60);
+        sound.start_capture(&path).unwrap();
+        for _ in 0..60 {
+            sound.frame(&memory).unwrap();
+        }
+        let (_, seconds) = sound.stop_capture().unwrap().unwrap();
+        assert_eq!(seconds, 1.0);
+
+        let samples = WavReader::open(&path)
+            .unwrap()
+            .samples::<i16>()
+            .map(|sample| sample.unwrap())
+            .collect::<Vec<_>>();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(samples.len(), SAMPLE_RATE as usize);
+
+        let high = (15 * VOLUME_STEP) as i16;
+        assert!(samples.iter().all(|&sample| sample == high || sample == -high));
+        let rises = samples.windows(2)
+            .enumerate()
+            .filter(|&(_, pair)| pair[0] < 0 && pair[1] > 0)
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+        assert_eq!(rises.len(), 441);
+        assert!(rises.windows(2).all(|pair| pair[1] - pair[0] == 100));
+    }
+}
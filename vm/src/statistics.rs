@@ -0,0 +1,92 @@
+use std::fmt;
+
+/// How a level was solved. Every hack costs points, so the lower the score
+/// the better, like par in golf
+#[derive(RustcDecodable, RustcEncodable, Clone, Debug, Default)]
+pub struct Statistics {
+    /// Console commands executed
+    pub commands: u32,
+    /// Bytes of memory changed by commands, scripts and debuggers
+    pub bytes_written: u32,
+    /// Breakpoints set
+    pub breakpoints: u32,
+    /// Hints revealed
+    pub hints: u32,
+    /// Clock cycles the CPUs ran. Time spent broken in the debugger
+    /// doesn't count
+    pub cycles: u64,
+    /// Real time spent in the level
+    pub milliseconds: u64,
+}
+
+const COMMAND_POINTS: u64 = 10;
+const BYTE_WRITTEN_POINTS: u64 = 5;
+const BREAKPOINT_POINTS: u64 = 10;
+const HINT_POINTS: u64 = 25;
+/// One point per this many clock cycles, a second of running at 150 cycles
+/// an update and 60 updates a second
+const CYCLES_PER_POINT: u64 = 9000;
+
+impl Statistics {
+    /// Weighs the hacks it took to solve the level. Wall time doesn't count,
+    /// as it depends on how long the player stared at the screen
+    pub fn score(&self) -> u64 {
+        self.commands as u64 * COMMAND_POINTS + self.bytes_written as u64 * BYTE_WRITTEN_POINTS +
+        self.breakpoints as u64 * BREAKPOINT_POINTS + self.hints as u64 * HINT_POINTS +
+        self.cycles / CYCLES_PER_POINT
+    }
+}
+
+impl fmt::Display for Statistics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let seconds = self.milliseconds / 1000;
+        write!(f,
+               "{} commands, {} bytes written, {} breakpoints, {} hints, {} cycles, {}:{:02}.{}",
+               self.commands,
+               self.bytes_written,
+               self.breakpoints,
+               self.hints,
+               self.cycles,
+               seconds / 60,
+               seconds % 60,
+               self.milliseconds % 1000 / 100)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statistics() -> Statistics {
+        Statistics {
+            commands: 1,
+            bytes_written: 2,
+            breakpoints: 3,
+            hints: 4,
+            cycles: CYCLES_PER_POINT * 5 + CYCLES_PER_POINT - 1,
+            milliseconds: 83_456,
+        }
+    }
+
+    #[test]
+    fn weighs_every_hack() {
+        assert_eq!(0, Statistics::default().score());
+        assert_eq!(10 + 2 * 5 + 3 * 10 + 4 * 25 + 5, statistics().score());
+    }
+
+    #[test]
+    fn scores_a_point_per_second_of_cycles() {
+        let statistics = Statistics { cycles: CYCLES_PER_POINT - 1, ..Statistics::default() };
+        assert_eq!(0, statistics.score());
+
+        let statistics = Statistics { cycles: CYCLES_PER_POINT * 2, ..Statistics::default() };
+        assert_eq!(2, statistics.score());
+    }
+
+    #[test]
+    fn shows_the_time_in_minutes_and_seconds() {
+        assert_eq!("1 commands, 2 bytes written, 3 breakpoints, 4 hints, 53999 cycles, 1:23.4",
+                   statistics().to_string());
+        assert!(Statistics::default().to_string().ends_with(" 0:00.0"));
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use rs6502::{Assembler, OpCode};
+
+/// Prefix of the labels inserted in front of every source line to find out
+/// where the assembler placed it
+const LINE_LABEL_PREFIX: &'static str = "HAKKA_LINE_";
+
+/// The labels and variables declared in an assembly source, mapped to the
+/// addresses they resolve to. Also maps the source lines to the address of
+/// the code they were assembled into.
+#[derive(Debug, Default)]
+pub struct SymbolTable {
+    symbols: HashMap<String, u16>,
+    labels: BTreeMap<u16, String>,
+    lines: BTreeMap<usize, u16>,
+    source: Option<PathBuf>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable {
+            symbols: HashMap::new(),
+            labels: BTreeMap::new(),
+            lines: BTreeMap::new(),
+            source: None,
+        }
+    }
+
+    /// Builds a symbol table from an assembly file, assembled at the given
+    /// offset
+    pub fn from_file<P>(path: P, offset: u16) -> io::Result<SymbolTable>
+        where P: AsRef<Path>
+    {
+        let mut file = File::open(&path)?;
+        let mut source = String::new();
+        file.read_to_string(&mut source)?;
+
+        let mut table = SymbolTable::from_source(source, offset);
+        table.source = Some(path.as_ref().to_path_buf());
+        Ok(table)
+    }
+
+    /// Builds a symbol table from assembly source, assembled at the given
+    /// offset
+    pub fn from_source<S>(source: S, offset: u16) -> SymbolTable
+        where S: Into<String>
+    {
+        let source = source.into();
+        let mut table = SymbolTable::new();
+        let mut variables = Vec::new();
+        let mut labels = Vec::new();
+        let mut lines = Vec::new();
+        let mut probe = String::new();
+
+        for (number, line) in source.lines().enumerate() {
+            let original = line;
+            let line = line.split(';').next().unwrap_or("");
+            let trimmed = line.trim();
+
+            // Label every line that produces code or names a location
+            if !trimmed.is_empty() && !trimmed.starts_with('.') && !trimmed.contains('=') {
+                let label = format!("{}{}", LINE_LABEL_PREFIX, number + 1);
+                probe.push_str(&label);
+                probe.push('\n');
+                lines.push((number + 1, label));
+            }
+            probe.push_str(original);
+            probe.push('\n');
+
+            if let Some(index) = line.find('=') {
+                let name = line[..index].trim();
+                let value = line[index + 1..].trim();
+                if is_identifier(name) {
+                    variables.push((name.to_owned(), value.to_owned()));
+                }
+                continue;
+            }
+
+            let first = match line.split_whitespace().next() {
+                Some(first) => first,
+                None => continue,
+            };
+            let name = first.trim_end_matches(':');
+            if is_identifier(name) && OpCode::from_mnemonic(name).is_none() {
+                labels.push(name.to_owned());
+            }
+        }
+
+        // rs6502 doesn't expose the addresses it assigns to labels, so we
+        // have it assemble a jump to each label after the original code and
+        // read the targets back out of the generated operands
+        let count = labels.len() + lines.len();
+        if count > 0 {
+            for label in labels.iter().chain(lines.iter().map(|&(_, ref label)| label)) {
+                probe.push_str(&format!("JMP {}\n", label));
+            }
+
+            let mut assembler = Assembler::new();
+            if let Ok(segments) = assembler.assemble_string(probe, offset) {
+                if let Some(segment) = segments.last() {
+                    if segment.code.len() >= count * 3 {
+                        let jumps = &segment.code[segment.code.len() - count * 3..];
+                        let mut addresses = jumps.chunks(3)
+                            .map(|jump| jump[1] as u16 | (jump[2] as u16) << 8);
+
+                        for label in labels {
+                            let address = addresses.next().unwrap();
+                            table.labels.insert(address, label.clone());
+                            table.insert(label, address);
+                        }
+                        for (number, _) in lines {
+                            table.lines.insert(number, addresses.next().unwrap());
+                        }
+                    }
+                }
+            }
+        }
+
+        // Variables may refer to labels or to other variables declared
+        // before or after them, so keep resolving until nothing changes
+        let mut pending = variables;
+        loop {
+            let count = pending.len();
+            pending.retain(|&(ref name, ref value)| {
+                match parse_value(value).or_else(|| table.get(value)) {
+                    Some(resolved) => {
+                        table.insert(name.clone(), resolved);
+                        false
+                    }
+                    None => true,
+                }
+            });
+            if pending.is_empty() || pending.len() == count {
+                break;
+            }
+        }
+
+        table
+    }
+
+    pub fn insert<S>(&mut self, name: S, address: u16)
+        where S: Into<String>
+    {
+        self.symbols.insert(name.into(), address);
+    }
+
+    pub fn get(&self, name: &str) -> Option<u16> {
+        self.symbols.get(name).cloned()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// Every symbol name and the address it resolves to
+    pub fn all(&self) -> Vec<(&str, u16)> {
+        self.symbols.iter().map(|(name, address)| (&name[..], *address)).collect()
+    }
+
+    /// The file the symbols were read from, if any
+    pub fn source(&self) -> Option<&Path> {
+        self.source.as_ref().map(|source| source.as_path())
+    }
+
+    /// The first source line at or after `line` that code was assembled
+    /// from, together with the address of that code
+    pub fn address_of_line(&self, line: usize) -> Option<(usize, u16)> {
+        self.lines.range(line..).next().map(|(line, address)| (*line, *address))
+    }
+
+    /// The source line the code at the given address was assembled from
+    pub fn line_of_address(&self, address: u16) -> Option<usize> {
+        // Labels share their address with the instruction that follows
+        // them, so prefer the last line mapped to an address
+        self.lines
+            .iter()
+            .filter(|&(_, line_address)| *line_address == address)
+            .map(|(line, _)| *line)
+            .last()
+    }
+
+    /// The code label closest to, but not after, the given address
+    pub fn label_before(&self, address: u16) -> Option<&str> {
+        self.labels
+            .iter()
+            .take_while(|&(label_address, _)| *label_address <= address)
+            .last()
+            .map(|(_, name)| &name[..])
+    }
+}
+
+/// Parses a number the way the assembler writes them: `$` for hexadecimal,
+/// `%` for binary and decimal otherwise. A leading `#` marks an immediate
+/// value rather than a base, so it is skipped before the base prefix
+fn parse_value(value: &str) -> Option<u16> {
+    let value = if value.starts_with('#') { &value[1..] } else { value };
+    if value.starts_with('$') {
+        u16::from_str_radix(&value[1..], 16).ok()
+    } else if value.starts_with('%') {
+        u16::from_str_radix(&value[1..], 2).ok()
+    } else {
+        value.parse().ok()
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    !name.is_empty() &&
+    name.chars().all(|c| c.is_alphanumeric() || c == '_') &&
+    !name.chars().next().unwrap().is_digit(10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_labels_at_the_assembly_offset() {
+        let table = SymbolTable::from_source("Start:\n  LDA #$01\nLoop:\n  JMP Loop\n", 0xC000);
+
+        assert_eq!(Some(0xC000), table.get("Start"));
+        assert_eq!(Some(0xC002), table.get("Loop"));
+        assert_eq!(Some("Loop"), table.label_before(0xC004));
+        assert_eq!(Some((2, 0xC000)), table.address_of_line(2));
+        assert_eq!(Some(4), table.line_of_address(0xC002));
+    }
+
+    #[test]
+    fn resolves_hexadecimal_decimal_and_binary_variables() {
+        let table = SymbolTable::from_source("SCREEN = $0200\nLIVES = 10\nMASK = %1010\n  LDA SCREEN\n",
+                                             0x0600);
+
+        assert_eq!(Some(0x0200), table.get("SCREEN"));
+        assert_eq!(Some(10), table.get("LIVES"));
+        assert_eq!(Some(0b1010), table.get("MASK"));
+    }
+
+    #[test]
+    fn skips_the_immediate_marker_before_the_base() {
+        let table = SymbolTable::from_source("HEX = #$10\nDECIMAL = #10\nBINARY = #%11\n", 0x0600);
+
+        assert_eq!(Some(0x10), table.get("HEX"));
+        assert_eq!(Some(10), table.get("DECIMAL"));
+        assert_eq!(Some(0b11), table.get("BINARY"));
+    }
+
+    #[test]
+    fn resolves_forward_references() {
+        let table = SymbolTable::from_source("ALIAS = SCREEN\nENTRY = Start\nSCREEN = $0200\nStart:\n  RTS\n",
+                                             0x0600);
+
+        assert_eq!(Some(0x0200), table.get("ALIAS"));
+        assert_eq!(Some(0x0600), table.get("ENTRY"));
+    }
+
+    #[test]
+    fn leaves_out_variables_that_cannot_be_resolved() {
+        let table = SymbolTable::from_source("FIRST = SECOND\nSECOND = FIRST\nKNOWN = $10\n", 0x0600);
+
+        assert_eq!(None, table.get("FIRST"));
+        assert_eq!(None, table.get("SECOND"));
+        assert_eq!(Some(0x10), table.get("KNOWN"));
+    }
+}
@@ -51,6 +51,10 @@ impl Text {
         }
     }
 
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
     pub fn render(&self, renderer: &mut Renderer) {
         renderer.copy(&self.texture,
                   None,
@@ -1,61 +1,174 @@
 
-use rs6502::{CodeSegment, Cpu, Disassembler};
+use rs6502::{CodeSegment, Cpu};
 use sdl2::render::Renderer;
 use sdl2::ttf::Sdl2TtfContext;
 use console::Console;
+use hud::DebugHud;
+use monitor::MemoryMonitor;
+use symbols::SymbolTable;
+use gdb::GdbServer;
+use dap::DapServer;
+use script::ScriptEngine;
+use reload::SourceWatcher;
+use interconnect::Interconnect;
+use mapper::{Bank, Mapper};
+use protection::{Fault, Protection, Region};
+use interconnect::Interrupt;
+use processor::{Processor, ADDRESS_SPACE};
+use sound::SoundDevice;
 use std::io::Write;
-use std::ops::Range;
+use std::mem;
 
-#[derive(Debug)]
-pub struct MemoryMonitor {
-    pub enabled: bool,
-    start_addr: usize,
-    end_addr: usize,
+/// The code, symbols, banks, protected memory and breakpoints of a CPU,
+/// parked here while another CPU is selected
+struct Core {
+    cpu: Box<Processor>,
+    segments: Vec<CodeSegment>,
+    symbols: SymbolTable,
+    mappers: Vec<Mapper>,
+    protection: Protection,
+    breakpoints: Box<[u8; ADDRESS_SPACE]>,
+    bank_breakpoints: Vec<(usize, u16)>,
 }
 
+/// One or more CPUs, each with their own memory, plus everything needed to
+/// debug them.
+///
+/// `cpu` is the selected CPU, which the console and debuggers work on. The
+/// other CPUs are parked until they are selected; `cycle` runs all of them.
+/// CPUs are any `Processor`, a 6502 unless another core is plugged in.
 pub struct VirtualMachine<'a> {
-    pub cpu: Cpu,
-    pub monitor: MemoryMonitor,
+    pub cpu: Box<Processor>,
+    pub monitor: MemoryMonitor<'a>,
     pub console: Console<'a>,
+    pub gdb_server: Option<GdbServer>,
+    pub dap_server: Option<DapServer>,
+    pub scripts: Option<ScriptEngine>,
+    pub watcher: Option<SourceWatcher>,
+    /// Shared memory and mailboxes between the CPUs
+    pub interconnect: Interconnect,
+    /// Plays what the level code writes to the sound registers
+    pub sound: Option<SoundDevice>,
+    hud: Option<DebugHud<'a>>,
     segments: Vec<CodeSegment>,
+    symbols: SymbolTable,
+    /// Bank switched windows of the selected CPU's memory
+    mappers: Vec<Mapper>,
+    /// Memory the code running on the selected CPU may not use freely
+    protection: Protection,
     clock_rate: Option<u32>,
-    breakpoints: [u8; 64 * 1024],
+    breakpoints: Box<[u8; ADDRESS_SPACE]>,
+    /// Breakpoints that only hit while the given bank is mapped, by bank
+    /// and address
+    bank_breakpoints: Vec<(usize, u16)>,
+    /// Every CPU by index, with `None` in place of the selected one
+    cores: Vec<Option<Core>>,
+    selected: usize,
     broken: bool,
     step: bool,
+    breakpoint_hit: Option<u16>,
+    cycles: u64,
+    /// Clock cycles run by all of the CPUs
+    cpu_cycles: u64,
+    breakpoints_set: u32,
+    bytes_written: u32,
+    hints: Vec<String>,
+    hints_used: usize,
+    /// Named values expressions can use like labels, such as the size of
+    /// the screen
+    constants: Vec<(String, u16)>,
 }
 
 impl<'a> VirtualMachine<'a> {
-    pub fn new<CR>(cpu: Cpu,
+    /// Creates a machine drawn in the window of the given renderer. Fails
+    /// if the CPU doesn't address 64K of memory
+    pub fn new<P, CR>(cpu: P,
                    clock_rate: CR,
                    ttf_context: &'a Sdl2TtfContext,
                    mut renderer: &mut Renderer,
                    font_file: &'a str)
-                   -> VirtualMachine<'a>
-        where CR: Into<Option<u32>>
+                   -> Result<VirtualMachine<'a>, String>
+        where P: Processor + 'static,
+              CR: Into<Option<u32>>
     {
+        check_address_space(&cpu)?;
+
         let mut console = Console::new(ttf_context, renderer, font_file);
+        let monitor = MemoryMonitor::new(ttf_context, renderer, font_file);
 
         writeln!(console, "Welcome to hakka. Type 'help' for instructions").unwrap();
         writeln!(console, "").unwrap();
 
+        let hud = DebugHud::new(ttf_context, font_file);
+        Ok(VirtualMachine::with_views(Box::new(cpu), clock_rate.into(), console, monitor, Some(hud)))
+    }
+
+    /// Creates a machine that isn't drawn. It runs, debugs and takes
+    /// commands like any other, so it can be used without a window, such
+    /// as in tests
+    pub fn headless<P, CR>(cpu: P, clock_rate: CR) -> Result<VirtualMachine<'a>, String>
+        where P: Processor + 'static,
+              CR: Into<Option<u32>>
+    {
+        check_address_space(&cpu)?;
+
+        Ok(VirtualMachine::with_views(Box::new(cpu),
+                                      clock_rate.into(),
+                                      Console::headless(),
+                                      MemoryMonitor::headless(),
+                                      None))
+    }
+
+    fn with_views(cpu: Box<Processor>,
+                  clock_rate: Option<u32>,
+                  console: Console<'a>,
+                  monitor: MemoryMonitor<'a>,
+                  hud: Option<DebugHud<'a>>)
+                  -> VirtualMachine<'a> {
         VirtualMachine {
             cpu: cpu,
             console: console,
+            gdb_server: None,
+            dap_server: None,
+            scripts: None,
+            watcher: None,
+            interconnect: Interconnect::default(),
+            sound: None,
+            hud: hud,
             segments: Vec::new(),
-            clock_rate: clock_rate.into(),
-            monitor: MemoryMonitor {
-                enabled: false,
-                start_addr: 0,
-                end_addr: 0,
-            },
-            breakpoints: [0; 64 * 1024],
+            symbols: SymbolTable::new(),
+            mappers: Vec::new(),
+            protection: Protection::default(),
+            clock_rate: clock_rate,
+            monitor: monitor,
+            breakpoints: Box::new([0; ADDRESS_SPACE]),
+            bank_breakpoints: Vec::new(),
+            cores: vec![None],
+            selected: 0,
             broken: false,
             step: false,
+            breakpoint_hit: None,
+            cycles: 0,
+            cpu_cycles: 0,
+            breakpoints_set: 0,
+            bytes_written: 0,
+            hints: Vec::new(),
+            hints_used: 0,
+            constants: Vec::new(),
         }
     }
 
     pub fn render(&mut self, mut renderer: &mut Renderer) {
         self.console.render(renderer);
+        self.monitor.render(renderer);
+
+        if self.is_debugging() {
+            let lines = self.hud_lines();
+            if let Some(ref mut hud) = self.hud {
+                hud.update(renderer, lines);
+                hud.render(renderer);
+            }
+        }
     }
 
     pub fn load_code_segments(&mut self, segments: Vec<CodeSegment>) {
@@ -67,19 +180,229 @@ impl<'a> VirtualMachine<'a> {
             self.cpu.load(&segment.code, segment.address).unwrap();
         }
 
-        self.cpu.registers.PC = self.segments[0].address;
+        let address = self.segments[0].address;
+        self.cpu.set_pc(address);
+    }
+
+    /// Zeroes the memory the loaded code was loaded into and forgets the
+    /// code, so none of it is left behind past the end of shorter code
+    /// loaded in its place
+    pub fn unload_code_segments(&mut self) {
+        for segment in self.segments.drain(..) {
+            let start = segment.address as usize;
+            for byte in &mut self.cpu.memory_mut()[start..start + segment.code.len()] {
+                *byte = 0;
+            }
+        }
+    }
+
+    /// Adds a 6502 with empty memory, returning its index. Select it to load
+    /// code into it
+    pub fn add_cpu(&mut self) -> usize {
+        self.add_core(Box::new(Cpu::new()))
+    }
+
+    /// Adds a CPU of any kind, returning its index. Fails if its memory
+    /// isn't the 64K every CPU addresses
+    pub fn add_processor(&mut self, cpu: Box<Processor>) -> Result<usize, String> {
+        check_address_space(&*cpu)?;
+        Ok(self.add_core(cpu))
+    }
+
+    fn add_core(&mut self, cpu: Box<Processor>) -> usize {
+        self.cores.push(Some(Core {
+            cpu: cpu,
+            segments: Vec::new(),
+            symbols: SymbolTable::new(),
+            mappers: Vec::new(),
+            protection: Protection::default(),
+            breakpoints: Box::new([0; ADDRESS_SPACE]),
+            bank_breakpoints: Vec::new(),
+        }));
+        self.cores.len() - 1
+    }
+
+    pub fn cpu_count(&self) -> usize {
+        self.cores.len()
+    }
+
+    pub fn selected_cpu(&self) -> usize {
+        self.selected
+    }
+
+    /// Makes the CPU with the given index the one the console and debuggers
+    /// work on. Returns false if there is no such CPU
+    pub fn select_cpu(&mut self, index: usize) -> bool {
+        if index >= self.cores.len() {
+            return false;
+        }
+        if index == self.selected {
+            return true;
+        }
+
+        let parked = self.cores[index].take().unwrap();
+        let current = Core {
+            cpu: mem::replace(&mut self.cpu, parked.cpu),
+            segments: mem::replace(&mut self.segments, parked.segments),
+            symbols: mem::replace(&mut self.symbols, parked.symbols),
+            mappers: mem::replace(&mut self.mappers, parked.mappers),
+            protection: mem::replace(&mut self.protection, parked.protection),
+            breakpoints: mem::replace(&mut self.breakpoints, parked.breakpoints),
+            bank_breakpoints: mem::replace(&mut self.bank_breakpoints, parked.bank_breakpoints),
+        };
+        self.cores[self.selected] = Some(current);
+        self.selected = index;
+        true
+    }
+
+    /// Runs `f` with the CPU of the given index selected, then selects the
+    /// CPU that was selected before
+    pub fn with_cpu<F, R>(&mut self, index: usize, f: F) -> R
+        where F: FnOnce(&mut VirtualMachine<'a>) -> R
+    {
+        let selected = self.selected;
+        self.select_cpu(index);
+        let result = f(self);
+        self.select_cpu(selected);
+        result
+    }
+
+    pub fn load_symbols(&mut self, symbols: SymbolTable) {
+        self.symbols = symbols;
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /// Looks up a symbol of the loaded code, or of the code in any bank,
+    /// or else a constant
+    pub fn symbol(&self, name: &str) -> Option<u16> {
+        self.symbols
+            .get(name)
+            .or_else(|| {
+                self.mappers
+                    .iter()
+                    .flat_map(|mapper| mapper.banks().iter())
+                    .filter_map(|bank| bank.symbols.get(name))
+                    .next()
+            })
+            .or_else(|| self.constants.iter().find(|&&(ref constant, _)| constant == name).map(|&(_, value)| value))
+    }
+
+    /// Names a value for expressions, which unlike a label doesn't show up
+    /// in the disassembly. Labels of the same name take precedence
+    pub fn define_constant<S>(&mut self, name: S, value: u16)
+        where S: Into<String>
+    {
+        self.constants.push((name.into(), value));
+    }
+
+    /// Adds a bank switched window to the memory of the selected CPU, with
+    /// its first bank mapped
+    pub fn add_mapper(&mut self, mut mapper: Mapper) {
+        mapper.install(&mut *self.cpu);
+        self.mappers.push(mapper);
+    }
+
+    /// Replaces a bank of a mapper of the selected CPU, as when its source
+    /// was reassembled. Returns false if there is no such bank
+    pub fn replace_bank(&mut self, mapper: usize, index: usize, bank: Bank) -> bool {
+        match self.mappers.get_mut(mapper) {
+            Some(mapper) => mapper.replace(index, bank, &mut *self.cpu),
+            None => false,
+        }
+    }
+
+    pub fn mappers(&self) -> &[Mapper] {
+        &self.mappers
+    }
+
+    /// The bank mapped at the given address, if it is inside a bank
+    /// switched window
+    pub fn mapped_bank(&self, address: usize) -> Option<usize> {
+        self.mapper_at(address).map(|mapper| mapper.selected())
+    }
+
+    /// Protects regions of the selected CPU's memory from its code
+    pub fn set_protection(&mut self, protection: Protection) {
+        self.protection = protection;
+    }
+
+    pub fn protection(&self) -> &Protection {
+        &self.protection
+    }
+
+    /// The first region of the selected CPU's memory within the given range
+    /// that the console and debuggers can't write to
+    pub fn write_protected(&self, start: usize, length: usize) -> Option<&Region> {
+        self.protection.write_protected(start, length)
+    }
+
+    fn mapper_at(&self, address: usize) -> Option<&Mapper> {
+        self.mappers.iter().find(|mapper| mapper.contains(address))
     }
 
-    /// Cycles the Virtual Machine CPU according to the clock rate
+    /// Cycles every CPU according to the clock rate. Execution stops on all
+    /// of them when one hits a breakpoint, and that CPU is selected
     pub fn cycle(&mut self) {
+        let selected = self.selected;
+        let step = self.step;
+        let mut hit = None;
+
+        // Pass on anything written to the devices between cycles, e.g. by
+        // the console
+        for index in 0..self.cores.len() {
+            self.sync_devices(index);
+        }
+
+        for index in 0..self.cores.len() {
+            self.select_cpu(index);
+            // Only the selected CPU is single stepped
+            self.step = step && index == selected;
+            self.cycle_selected();
+            if hit.is_none() && self.breakpoint_hit.is_some() {
+                hit = Some(index);
+            }
+        }
+        self.step = false;
+        self.select_cpu(hit.unwrap_or(selected));
+
+        self.monitor.update(self.cpu.memory());
+        self.cycles += 1;
+
+        // The sound stands still with the code while execution is broken
+        if self.broken {
+            return;
+        }
+        if let Some(mut sound) = self.sound.take() {
+            if let Err(err) = self.with_cpu(0, |vm| sound.frame(vm.cpu.memory())) {
+                writeln!(self.console, "Audio capture stopped: {}", err).unwrap();
+            }
+            self.sound = Some(sound);
+        }
+    }
+
+    fn cycle_selected(&mut self) {
+        // Pick up banks switched between cycles, e.g. by the console
+        self.sync_mappers();
+
         if let Some(clock_rate) = self.clock_rate {
             let mut n = 0;
             while (n < clock_rate && !self.broken) || self.step {
-                n += self.cpu.step().expect("SEGFAULT") as u32;
-                if self.breakpoints[self.cpu.registers.PC as usize] > 0 {
+                self.protection.before_step(&*self.cpu);
+                let cycles = self.cpu.step().expect("SEGFAULT");
+                n += cycles as u32;
+                self.cpu_cycles += cycles as u64;
+                self.check_protection();
+                let selected = self.selected;
+                self.sync_mappers();
+                self.sync_devices(selected);
+                if self.breakpoint_at(self.cpu.pc()) {
                     self.broken = true;
+                    self.breakpoint_hit = Some(self.cpu.pc());
                     writeln!(self.console, "").unwrap();
-                    writeln!(self.console, "BREAKPOINT hit at {:04x}", self.cpu.registers.PC).unwrap();
+                    self.report_breakpoint();
                     // We are supposed to pass the current timestamp to prevent the keys which are
                     // used to toggle the console from inputing text into the console. As no key
                     // is pressed to open the console in this instance, passing the time is not
@@ -93,36 +416,186 @@ impl<'a> VirtualMachine<'a> {
                 self.step = false;
             }
         } else {
-            self.cpu.step().expect("SEGFAULT");
+            self.protection.before_step(&*self.cpu);
+            self.cpu_cycles += self.cpu.step().expect("SEGFAULT") as u64;
+            self.check_protection();
+            let selected = self.selected;
+            self.sync_mappers();
+            self.sync_devices(selected);
             if self.step {
                 self.dump_local_disassembly();
             }
             self.step = false;
-            if self.breakpoints[self.cpu.registers.PC as usize] > 0 {
+            if self.breakpoint_at(self.cpu.pc()) {
                 self.broken = true;
+                self.breakpoint_hit = Some(self.cpu.pc());
                 writeln!(self.console, "").unwrap();
-                writeln!(self.console, "BREAKPOINT hit at {:04x}", self.cpu.registers.PC).unwrap();
+                self.report_breakpoint();
                 self.console.toggle(0);
             }
         }
     }
 
-    pub fn enable_memory_monitor(&mut self, range: Range<usize>)  { 
-        self.monitor.start_addr = range.start;
-        self.monitor.end_addr = range.end;
-        self.monitor.enabled = true;
+    /// Looks for writes and execution the last step wasn't allowed, and
+    /// either raises the interrupt of the region at fault or stops execution
+    fn check_protection(&mut self) {
+        let fault = match self.protection.after_step(&mut *self.cpu) {
+            Some(fault) => fault,
+            None => return,
+        };
+        // A fault the code can't be interrupted for, because interrupts are
+        // masked or have no handler, stops execution instead
+        let taken = match fault.interrupt {
+            Some(Interrupt::Irq) => self.cpu.irq(),
+            Some(Interrupt::Nmi) => self.cpu.nmi(),
+            None => false,
+        };
+        if taken {
+            self.protection.enter_handler(&*self.cpu);
+        } else {
+            self.report_fault(fault);
+        }
+    }
+
+    fn report_fault(&mut self, fault: Fault) {
+        self.broken = true;
+        writeln!(self.console, "").unwrap();
+        if self.cores.len() > 1 {
+            writeln!(self.console, "FAULT on cpu {}: {}", self.selected, fault).unwrap();
+        } else {
+            writeln!(self.console, "FAULT: {}", fault).unwrap();
+        }
+        self.console.toggle(0);
+    }
+
+    fn sync_mappers(&mut self) {
+        for mapper in &mut self.mappers {
+            mapper.sync(&mut *self.cpu);
+        }
+    }
+
+    /// Whether execution should stop at the given address, given the banks
+    /// mapped right now
+    fn breakpoint_at(&self, pc: u16) -> bool {
+        if self.breakpoints[pc as usize] > 0 {
+            return true;
+        }
+        let bank = self.mapped_bank(pc as usize);
+        self.bank_breakpoints.iter().any(|&(breakpoint_bank, address)| address == pc && bank == Some(breakpoint_bank))
+    }
+
+    /// Lets the interconnect pick up what the CPU with the given index wrote
+    /// to the devices
+    fn sync_devices(&mut self, index: usize) {
+        if self.interconnect.is_empty() {
+            return;
+        }
+
+        let interrupted = {
+            let mut selected = Some(&mut *self.cpu);
+            let mut cpus: Vec<&mut Processor> = Vec::new();
+            for core in &mut self.cores {
+                match *core {
+                    Some(ref mut core) => cpus.push(&mut *core.cpu),
+                    None => cpus.push(selected.take().unwrap()),
+                }
+            }
+            self.interconnect.sync(index, &mut cpus)
+        };
+
+        // The CPUs that took an interrupt are privileged while handling it
+        for index in interrupted {
+            match self.cores[index] {
+                Some(ref mut core) => core.protection.enter_handler(&*core.cpu),
+                None => self.protection.enter_handler(&*self.cpu),
+            }
+        }
+    }
+
+    fn report_breakpoint(&mut self) {
+        let pc = self.cpu.pc();
+        let location = match self.mapped_bank(pc as usize) {
+            Some(bank) => format!("{}:{:04x}", bank, pc),
+            None => format!("{:04x}", pc),
+        };
+        if self.cores.len() > 1 {
+            writeln!(self.console, "BREAKPOINT hit at {} on cpu {}", location, self.selected).unwrap();
+        } else {
+            writeln!(self.console, "BREAKPOINT hit at {}", location).unwrap();
+        }
+    }
+
+    /// How many times the machine has been cycled, whether or not the CPUs
+    /// ran
+    pub fn cycle_count(&self) -> u64 {
+        self.cycles
+    }
+
+    /// How many clock cycles the CPUs have run. Unlike `cycle_count`, this
+    /// stands still while execution is broken
+    pub fn cpu_cycles(&self) -> u64 {
+        self.cpu_cycles
+    }
+
+    /// How many times a breakpoint has been set, by any means
+    pub fn breakpoints_set(&self) -> u32 {
+        self.breakpoints_set
+    }
+
+    /// How many bytes `write_memory` has changed
+    pub fn bytes_written(&self) -> u32 {
+        self.bytes_written
+    }
+
+    /// Writes to the memory of the selected CPU on behalf of the player,
+    /// counting the bytes that changed. The caller checks the range and
+    /// protected memory first
+    pub fn write_memory(&mut self, address: usize, bytes: &[u8]) {
+        let memory = self.cpu.memory_mut();
+        for (index, byte) in bytes.iter().enumerate() {
+            if memory[address + index] != *byte {
+                memory[address + index] = *byte;
+                self.bytes_written += 1;
+            }
+        }
+    }
+
+    pub fn load_hints(&mut self, hints: Vec<String>) {
+        self.hints = hints;
+        self.hints_used = 0;
     }
-    pub fn is_memory_monitor_enabled(&self) -> bool {
-        self.monitor.enabled
+
+    /// The hints revealed so far
+    pub fn revealed_hints(&self) -> &[String] {
+        &self.hints[..self.hints_used]
     }
-    pub fn disable_memory_monitor(&mut self) {
-        self.monitor.enabled = false;
+
+    pub fn hint_count(&self) -> usize {
+        self.hints.len()
+    }
+
+    /// Reveals the next hint, if there are any left
+    pub fn reveal_hint(&mut self) -> Option<&str> {
+        if self.hints_used == self.hints.len() {
+            return None;
+        }
+        self.hints_used += 1;
+        Some(&self.hints[self.hints_used - 1])
     }
 
     pub fn is_debugging(&self) -> bool {
         self.broken
     }
 
+    /// The address of the breakpoint hit since this was last called, if any
+    pub fn take_breakpoint_hit(&mut self) -> Option<u16> {
+        self.breakpoint_hit.take()
+    }
+
+    pub fn is_stepping(&self) -> bool {
+        self.step
+    }
+
     pub fn break_execution(&mut self) {
         self.broken = true;
     }
@@ -133,32 +606,92 @@ impl<'a> VirtualMachine<'a> {
         self.broken = true;
         self.step = true;
     }
+    pub fn has_breakpoint(&self, address: usize) -> bool {
+        self.breakpoints[address] > 0
+    }
     pub fn toggle_breakpoint(&mut self, address: usize) -> bool {
         if self.breakpoints[address] > 0 {
             self.breakpoints[address] = 0;
             return false;
         } else {
             self.breakpoints[address] = 1;
+            self.breakpoints_set += 1;
             return true;
         }
     }
 
-    pub fn dump_disassembly(&mut self) {
-        writeln!(self.console, " ").unwrap();
+    pub fn has_bank_breakpoint(&self, bank: usize, address: usize) -> bool {
+        self.bank_breakpoints.contains(&(bank, address as u16))
+    }
 
+    /// Toggles a breakpoint that only hits while the given bank is mapped.
+    /// Returns whether the breakpoint is now set, or `None` if no window
+    /// holds that bank at the address
+    pub fn toggle_bank_breakpoint(&mut self, bank: usize, address: usize) -> Option<bool> {
+        match self.mapper_at(address) {
+            Some(mapper) if bank < mapper.banks().len() => (),
+            _ => return None,
+        }
+
+        let breakpoint = (bank, address as u16);
+        if let Some(index) = self.bank_breakpoints.iter().position(|existing| *existing == breakpoint) {
+            self.bank_breakpoints.remove(index);
+            Some(false)
+        } else {
+            self.bank_breakpoints.push(breakpoint);
+            self.breakpoints_set += 1;
+            Some(true)
+        }
+    }
+
+    /// Lists the loaded code, followed by the code in every bank
+    pub fn dump_disassembly(&mut self) {
+        let mut lines = Vec::new();
         for segment in &self.segments {
-            writeln!(self.console, ".ORG ${:04X}", segment.address).unwrap();
-            let disassembler = Disassembler::with_offset(segment.address);
-            let pairs = disassembler.disassemble_with_addresses(&segment.code);
-            let lines = self.highlight_lines(self.cpu.registers.PC as usize,
-                                             pairs,
-                                             segment.address,
-                                             false);
-            for line in lines {
-                write!(self.console, "{}", line).unwrap();
+            lines.push(format!(".ORG ${:04X}\n", segment.address));
+            lines.extend(self.segment_lines(segment, None, false));
+        }
+        for mapper in &self.mappers {
+            for bank in 0..mapper.banks().len() {
+                lines.extend(self.bank_lines(mapper, bank));
             }
         }
+        self.write_listing(lines);
+    }
+
+    /// Lists the code in a bank. Returns false if there is no such bank
+    pub fn dump_bank_disassembly(&mut self, bank: usize) -> bool {
+        let mut lines = Vec::new();
+        let mut found = false;
+        for mapper in self.mappers.iter().filter(|mapper| bank < mapper.banks().len()) {
+            found = true;
+            lines.extend(self.bank_lines(mapper, bank));
+        }
+        if found {
+            self.write_listing(lines);
+        }
+        found
+    }
+
+    fn bank_lines(&self, mapper: &Mapper, bank: usize) -> Vec<String> {
+        let mut lines = Vec::new();
+        for segment in &mapper.banks()[bank].segments {
+            lines.push(format!(".ORG ${:04X} (bank {})\n", segment.address, bank));
+            lines.extend(self.segment_lines(segment, Some(bank), false));
+        }
+        lines
+    }
+
+    fn segment_lines(&self, segment: &CodeSegment, bank: Option<usize>, limit_results: bool) -> Vec<String> {
+        let pairs = self.cpu.disassemble(&segment.code, segment.address);
+        self.highlight_lines(self.cpu.pc() as usize, pairs, segment.address, limit_results, bank)
+    }
 
+    fn write_listing(&mut self, lines: Vec<String>) {
+        writeln!(self.console, " ").unwrap();
+        for line in lines {
+            write!(self.console, "{}", line).unwrap();
+        }
         writeln!(self.console, " ").unwrap();
     }
 
@@ -166,11 +699,8 @@ impl<'a> VirtualMachine<'a> {
         writeln!(self.console, " ").unwrap();
 
         let result = {
-            let pc = self.cpu.registers.PC as usize;
-            let local_segment = self.get_local_segment(pc);
-            let disassembler = Disassembler::with_offset(local_segment.address);
-            let pairs = disassembler.disassemble_with_addresses(&local_segment.code);
-            self.highlight_lines(pc, pairs, local_segment.address, true)
+            let (local_segment, bank) = self.get_local_segment(self.cpu.pc() as usize);
+            self.segment_lines(local_segment, bank, true)
         };
         for line in result {
             write!(self.console, "{}", line).unwrap();
@@ -180,7 +710,7 @@ impl<'a> VirtualMachine<'a> {
 
     pub fn dump_memory_page(&mut self, page: usize) {
         let mut addr = page * 0x100;
-        for chunk in self.cpu.memory[page * 0x100..(page * 0x100) + 0x100].chunks(8) {
+        for chunk in self.cpu.memory()[page * 0x100..(page * 0x100) + 0x100].chunks(8) {
             write!(self.console, "{:04X}: ", addr).unwrap();
             for b in chunk {
                 write!(self.console, "{:02X} ", *b).unwrap();
@@ -191,67 +721,162 @@ impl<'a> VirtualMachine<'a> {
         writeln!(self.console, "").unwrap();
     }
 
-    pub fn dump_memory(&mut self) {
-        for chunk in self.cpu.memory[self.monitor.start_addr..self.monitor.end_addr + 0x01]
-            .chunks(8) {
+    pub fn dump_memory_range(&mut self, start: usize, end: usize) {
+        for chunk in self.cpu.memory()[start..end + 0x01].chunks(8) {
             for b in chunk {
                 write!(self.console, "{:02X} ", *b).unwrap();
             }
             writeln!(self.console, "").unwrap();
         }
+        writeln!(self.console, "").unwrap();
     }
 
-    pub fn dump_memory_range(&mut self, start: usize, end: usize) {
-        for chunk in self.cpu.memory[start..end + 0x01].chunks(8) {
+    /// Dumps a range of a bank, whether the bank is mapped or not. Returns
+    /// false if the range isn't inside a window holding that bank
+    pub fn dump_bank_range(&mut self, bank: usize, start: usize, end: usize) -> bool {
+        let bytes = match self.mapper_at(start) {
+            Some(mapper) if mapper.contains(end) && bank < mapper.banks().len() => {
+                (start..end + 0x01).map(|address| mapper.read(bank, address, self.cpu.memory())).collect::<Vec<_>>()
+            }
+            _ => return false,
+        };
+
+        for chunk in bytes.chunks(8) {
             for b in chunk {
                 write!(self.console, "{:02X} ", *b).unwrap();
             }
             writeln!(self.console, "").unwrap();
         }
         writeln!(self.console, "").unwrap();
+        true
     }
 
     pub fn dump_registers(&mut self) {
         writeln!(self.console, " ").unwrap();
-        writeln!(self.console,"A: {} ({:04X})", self.cpu.registers.A, self.cpu.registers.A).unwrap();
-        writeln!(self.console, "X: {} ({:04X})", self.cpu.registers.X, self.cpu.registers.X).unwrap();
-        writeln!(self.console, "Y: {} ({:04X})", self.cpu.registers.Y, self.cpu.registers.Y).unwrap();
-        writeln!(self.console, "PC: {} ({:04X})", self.cpu.registers.PC, self.cpu.registers.PC).unwrap();
-        writeln!(self.console, "S: {} ({:04X})", self.cpu.stack.pointer, self.cpu.stack.pointer).unwrap();
+        for (name, value) in self.registers() {
+            writeln!(self.console, "{}: {} ({:04X})", name, value, value).unwrap();
+        }
         writeln!(self.console, " ").unwrap();
     }
 
     pub fn dump_flags(&mut self) {
         writeln!(self.console, " ").unwrap();
-        writeln!(self.console, "Carry: {}", self.cpu.flags.carry).unwrap();
-        writeln!(self.console, "Zero: {}", self.cpu.flags.zero).unwrap();
-        writeln!(self.console, "Interrupts disabled: {}", self.cpu.flags.interrupt_disabled).unwrap();
-        writeln!(self.console, "Decimal mode: {}", self.cpu.flags.decimal).unwrap();
-        writeln!(self.console, "Break: {}", self.cpu.flags.breakpoint).unwrap();
-        writeln!(self.console, "Overflow: {}", self.cpu.flags.overflow).unwrap();
-        writeln!(self.console, "Sign: {}", self.cpu.flags.sign).unwrap();
-        writeln!(self.console, "Unused: {}", self.cpu.flags.unused).unwrap();
+        for (_, name, set) in self.flags() {
+            writeln!(self.console, "{}: {}", name, set).unwrap();
+        }
         writeln!(self.console, " ").unwrap();
     }
 
-    fn get_local_segment(&self, pc: usize) -> &CodeSegment {
+    /// The names and values of the CPU registers
+    pub fn registers(&self) -> Vec<(&'static str, u16)> {
+        self.cpu.registers()
+    }
+
+    /// The letter, name and state of each status flag
+    pub fn flags(&self) -> Vec<(char, &'static str, bool)> {
+        self.cpu.flags()
+    }
+
+    /// Sets a register by the name `registers` gives it. Returns false if
+    /// there is no such register
+    pub fn set_register(&mut self, name: &str, value: u16) -> bool {
+        self.cpu.set_register(name, value)
+    }
+
+    /// Sets a status flag by its letter. Returns false if there is no such
+    /// flag
+    pub fn set_flag(&mut self, letter: char, set: bool) -> bool {
+        self.cpu.set_flag(letter, set)
+    }
+
+    /// Every instruction in the loaded code, with its address
+    pub fn instructions(&self) -> Vec<(u16, String)> {
+        let mut result = Vec::new();
+        for segment in &self.segments {
+            for (line, offset) in self.cpu.disassemble(&segment.code, segment.address) {
+                result.push((segment.address.wrapping_add(offset), line));
+            }
+        }
+        result
+    }
+
+    /// What the debug HUD shows about the selected CPU
+    fn hud_lines(&self) -> Vec<String> {
+        let registers = self.registers()
+            .into_iter()
+            .map(|(name, value)| if name == "PC" {
+                format!("{}:{:04X}", name, value)
+            } else {
+                format!("{}:{:02X}", name, value)
+            })
+            .collect::<Vec<_>>();
+        let flags = self.flags();
+        let names = flags.iter().map(|&(letter, _, _)| letter).collect::<String>();
+        let states = flags.iter().map(|&(_, _, set)| if set { '1' } else { '0' }).collect::<String>();
+
+        let mut lines = Vec::new();
+        if self.cores.len() > 1 {
+            lines.push(format!("CPU {}", self.selected));
+        }
+        if let Some(bank) = self.mapped_bank(self.cpu.pc() as usize) {
+            lines.push(format!("BANK {}", bank));
+        }
+        lines.extend(vec![registers.join(" "),
+             names,
+             states,
+             format!("> {}", self.current_instruction())]);
+        lines
+    }
+
+    /// The disassembled instruction at the program counter
+    pub fn current_instruction(&self) -> String {
+        let pc = self.cpu.pc();
+        if !self.segments.is_empty() {
+            let (segment, _) = self.get_local_segment(pc as usize);
+            for (line, offset) in self.cpu.disassemble(&segment.code, segment.address) {
+                if segment.address.wrapping_add(offset) == pc {
+                    return line;
+                }
+            }
+        }
+
+        format!("{:04X} ???", pc)
+    }
+
+    /// The segment holding the code at the program counter, and the bank it
+    /// is in if it is in a bank
+    fn get_local_segment(&self, pc: usize) -> (&CodeSegment, Option<usize>) {
+        if let Some(mapper) = self.mapper_at(pc) {
+            let bank = mapper.selected();
+            for segment in &mapper.banks()[bank].segments {
+                let addr = segment.address as usize;
+                if pc >= addr && pc <= addr + segment.code.len() {
+                    return (segment, Some(bank));
+                }
+            }
+        }
+
         for segment in &self.segments {
             let addr = segment.address as usize;
             if pc >= addr && pc <= addr + segment.code.len() {
-                return segment;
+                return (segment, None);
             }
         }
 
-        &self.segments[0]
+        (&self.segments[0], None)
     }
 
     fn highlight_lines(&self,
                        pc: usize,
                        pairs: Vec<(String, u16)>,
                        segment_start: u16,
-                       limit_results: bool)
+                       limit_results: bool,
+                       bank: Option<usize>)
                        -> Vec<String> {
         let mut result = Vec::new();
+        // Code in a bank that isn't mapped can't be where the program
+        // counter is
+        let mapped = bank.map_or(true, |bank| self.mapped_bank(segment_start as usize) == Some(bank));
 
         let base = pc as isize - segment_start as isize;
 
@@ -262,8 +887,10 @@ impl<'a> VirtualMachine<'a> {
                     continue;
                 }
             }
-            let current_line = pc as u16 == segment_start + pair.1;
-            let breakpoint = self.breakpoints[segment_start as usize + pair.1 as usize] > 0x00;
+            let address = segment_start as usize + pair.1 as usize;
+            let current_line = mapped && pc as u16 == segment_start + pair.1;
+            let breakpoint = (mapped && self.breakpoints[address] > 0x00) ||
+                             bank.map_or(false, |bank| self.has_bank_breakpoint(bank, address));
 
             if breakpoint && current_line {
                 result.push(format!("> * {}", pair.0));
@@ -279,3 +906,138 @@ impl<'a> VirtualMachine<'a> {
         result
     }
 }
+
+/// Checks that a CPU addresses the 64K the breakpoints, levels and banks
+/// assume
+fn check_address_space(cpu: &Processor) -> Result<(), String> {
+    if cpu.memory().len() != ADDRESS_SPACE {
+        return Err(format!("A CPU must address 64K of memory, not {} bytes", cpu.memory().len()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use processor::tests::StubProcessor;
+
+    /// Two stub CPUs that run one instruction per cycle, with code loaded
+    /// at $0600 in both and the first one selected
+    fn machine() -> VirtualMachine<'static> {
+        let mut vm = VirtualMachine::headless(StubProcessor::new(), 2).unwrap();
+        vm.add_processor(Box::new(StubProcessor::new())).unwrap();
+        for cpu in 0..2 {
+            vm.with_cpu(cpu, |vm| {
+                vm.load_code_segments(vec![CodeSegment {
+                                               address: 0x0600,
+                                               code: vec![0xEA; 0x10],
+                                           }])
+            });
+        }
+        vm
+    }
+
+    fn pc(vm: &mut VirtualMachine, cpu: usize) -> u16 {
+        vm.with_cpu(cpu, |vm| vm.cpu.pc())
+    }
+
+    #[test]
+    fn a_breakpoint_on_another_cpu_selects_it_and_stops_every_cpu() {
+        let mut vm = machine();
+        vm.with_cpu(1, |vm| vm.toggle_breakpoint(0x0601));
+
+        vm.cycle();
+
+        assert_eq!(1, vm.selected_cpu());
+        assert!(vm.is_debugging());
+        assert_eq!(Some(0x0601), vm.take_breakpoint_hit());
+        assert_eq!(0x0601, pc(&mut vm, 0));
+
+        vm.cycle();
+
+        assert_eq!(0x0601, pc(&mut vm, 0));
+        assert_eq!(0x0601, pc(&mut vm, 1));
+    }
+
+    #[test]
+    fn only_the_selected_cpu_is_single_stepped() {
+        let mut vm = machine();
+        vm.select_cpu(1);
+        vm.step_execution();
+
+        vm.cycle();
+
+        assert_eq!(0x0600, pc(&mut vm, 0));
+        assert_eq!(0x0601, pc(&mut vm, 1));
+        assert_eq!(1, vm.selected_cpu());
+        assert!(!vm.is_stepping());
+    }
+
+    #[test]
+    fn with_cpu_selects_the_previous_cpu_afterwards() {
+        let mut vm = machine();
+        vm.select_cpu(1);
+
+        let selected = vm.with_cpu(0, |vm| {
+            vm.cpu.memory_mut()[0x0200] = 0x42;
+            vm.selected_cpu()
+        });
+
+        assert_eq!(0, selected);
+        assert_eq!(1, vm.selected_cpu());
+        assert_eq!(0x00, vm.cpu.memory()[0x0200]);
+        assert_eq!(0x42, vm.with_cpu(0, |vm| vm.cpu.memory()[0x0200]));
+    }
+
+    #[test]
+    fn selecting_a_cpu_that_does_not_exist_keeps_the_selection() {
+        let mut vm = machine();
+
+        assert!(!vm.select_cpu(2));
+        assert_eq!(0, vm.selected_cpu());
+    }
+
+    #[test]
+    fn rejects_cpus_that_do_not_address_64k() {
+        let mut small = StubProcessor::new();
+        small.memory.truncate(0x1000);
+        let mut vm = machine();
+
+        assert_eq!(Err("A CPU must address 64K of memory, not 4096 bytes".to_owned()),
+                   vm.add_processor(Box::new(small)));
+        assert_eq!(2, vm.cpu_count());
+
+        let mut small = StubProcessor::new();
+        small.memory.truncate(0x1000);
+        assert!(VirtualMachine::headless(small, 2).is_err());
+    }
+
+    #[test]
+    fn shows_the_selected_cpu_on_the_hud_when_there_are_several() {
+        let mut vm = machine();
+        vm.select_cpu(1);
+        vm.set_register("S", 0x42);
+        vm.set_flag('I', true);
+
+        assert_eq!(vec!["CPU 1", "S:42 PC:0600", "I", "1", "> 0600 ???"], vm.hud_lines());
+    }
+
+    #[test]
+    fn shows_the_bank_mapped_at_the_program_counter_on_the_hud() {
+        let mut vm = VirtualMachine::headless(StubProcessor::new(), 2).unwrap();
+        vm.add_mapper(Mapper::new(0x0600, 0x100, 0x00FF, vec![Bank::ram(0x100), Bank::ram(0x100)]));
+        vm.cpu.memory_mut()[0x00FF] = 1;
+
+        vm.cycle();
+
+        assert_eq!(vec!["BANK 1", "S:FF PC:0601", "I", "0", "> 0601 ???"], vm.hud_lines());
+    }
+
+    #[test]
+    fn leaves_the_bank_off_the_hud_outside_of_its_window() {
+        let mut vm = VirtualMachine::headless(StubProcessor::new(), 2).unwrap();
+        vm.add_mapper(Mapper::new(0x8000, 0x100, 0x00FF, vec![Bank::ram(0x100)]));
+
+        assert_eq!(vec!["S:FF PC:0600", "I", "0", "> 0600 ???"], vm.hud_lines());
+    }
+}